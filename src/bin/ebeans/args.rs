@@ -15,6 +15,9 @@ pub struct Args {
     /// Enables write-ahead logging and set the directory to store WAL files in.
     #[arg(short = 'b', long)]
     pub wal_dir: Option<PathBuf>,
+    /// Loads per-tube settings from a YAML configuration file.
+    #[arg(short, long)]
+    pub config: Option<PathBuf>,
     /// Sets the maximum allowed job size.
     #[arg(short = 'z', long, default_value_t = 65535)]
     pub max_job_size: u32,
@@ -0,0 +1,187 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+
+use beanstalk_rs::wire::protocol::ClientInfo;
+
+/// The state of a single connection, as held by its client loop.
+#[derive(Debug)]
+pub struct ClientState {
+    pub peer: SocketAddr,
+    /// name set with `client-name`
+    pub name: Option<Vec<u8>>,
+    /// tube `use`d by the client
+    pub tube: Vec<u8>,
+    /// tube names and patterns watched by the client, in the order watched
+    pub watching: Vec<Vec<u8>>,
+    /// IDs of the jobs reserved by the client
+    pub reserved: BTreeSet<u64>,
+    /// time of the client's last command
+    pub last_active: Instant,
+    /// cancelled to disconnect the client
+    kill: CancellationToken,
+}
+
+/// Tracks the state of every connected client, so that admin commands on one
+/// connection can list or kill the others.
+#[derive(Debug, Default)]
+pub struct Clients {
+    inner: Mutex<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    next_id: u64,
+    clients: BTreeMap<u64, ClientState>,
+}
+
+impl Clients {
+    /// Registers a newly connected client, which uses and watches the default
+    /// tube. Returns the client's registration, which deregisters it when
+    /// dropped, and a token that's cancelled when the client is killed or the
+    /// server shuts down.
+    pub fn register(
+        self: &Arc<Self>,
+        peer: SocketAddr,
+        cancel: &CancellationToken,
+    ) -> (Registration, CancellationToken) {
+        let kill = cancel.child_token();
+        let mut inner = self.lock();
+
+        inner.next_id = inner.next_id.saturating_add(1);
+        let id = inner.next_id;
+        inner.clients.insert(
+            id,
+            ClientState {
+                peer,
+                name: None,
+                tube: b"default".to_vec(),
+                watching: vec![b"default".to_vec()],
+                reserved: BTreeSet::new(),
+                last_active: Instant::now(),
+                kill: kill.clone(),
+            },
+        );
+
+        let registration = Registration {
+            clients: self.clone(),
+            id,
+        };
+        (registration, kill)
+    }
+
+    /// Updates the state of a connected client.
+    pub fn update(&self, id: u64, f: impl FnOnce(&mut ClientState)) {
+        if let Some(client) = self.lock().clients.get_mut(&id) {
+            f(client);
+        }
+    }
+
+    /// Describes every connected client, in the order they connected.
+    pub fn list(&self) -> Vec<ClientInfo> {
+        let now = Instant::now();
+
+        self.lock()
+            .clients
+            .iter()
+            .map(|(&id, c)| ClientInfo {
+                id,
+                peer: c.peer.to_string(),
+                name: c.name.clone(),
+                tube: c.tube.clone(),
+                watching: c.watching.clone(),
+                reserved: c.reserved.iter().copied().collect(),
+                idle: u32::try_from(
+                    now.saturating_duration_since(c.last_active).as_secs(),
+                )
+                .unwrap_or(u32::MAX),
+            })
+            .collect()
+    }
+
+    /// Disconnects a client, returning a boolean indicating if it existed.
+    /// Its client loop releases its jobs as it exits.
+    pub fn kill(&self, id: u64) -> bool {
+        let inner = self.lock();
+        let Some(client) = inner.clients.get(&id) else {
+            return false;
+        };
+
+        client.kill.cancel();
+        true
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        // The state is always left consistent, so is safe to use if a holder
+        // of the lock panicked.
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// A connected client's entry in [`Clients`], removed when dropped.
+#[derive(Debug)]
+pub struct Registration {
+    clients: Arc<Clients>,
+    pub id: u64,
+}
+
+impl Registration {
+    /// Updates the state of this client.
+    pub fn update(&self, f: impl FnOnce(&mut ClientState)) {
+        self.clients.update(self.id, f);
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        // The client's session releases its reserved jobs and forgets its
+        // watch set when it's dropped along with the client loop.
+        self.clients.lock().clients.remove(&self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_clients() {
+        let clients = Arc::new(Clients::default());
+        let cancel = CancellationToken::new();
+        let peer = "127.0.0.1:1234".parse().unwrap();
+
+        let (first, first_kill) = clients.register(peer, &cancel);
+        let (second, second_kill) = clients.register(peer, &cancel);
+        assert_ne!(first.id, second.id);
+
+        first.update(|c| {
+            c.name = Some(b"worker-1".to_vec());
+            c.reserved.insert(7);
+        });
+        tokio::time::advance(std::time::Duration::from_secs(5)).await;
+        second.update(|c| c.last_active = Instant::now());
+
+        let list = clients.list();
+        assert_eq!(list.len(), 2);
+        assert_eq!(list[0].name.as_deref(), Some(&b"worker-1"[..]));
+        assert_eq!(list[0].reserved, vec![7]);
+        assert_eq!(list[0].idle, 5);
+        assert_eq!(list[1].idle, 0);
+        assert_eq!(list[1].peer, "127.0.0.1:1234");
+
+        assert!(clients.kill(first.id));
+        assert!(first_kill.is_cancelled());
+        assert!(!second_kill.is_cancelled());
+        assert!(!clients.kill(3));
+
+        drop(first);
+        assert_eq!(clients.list().len(), 1);
+
+        // Shutting down the server disconnects every client.
+        cancel.cancel();
+        assert!(second_kill.is_cancelled());
+    }
+}
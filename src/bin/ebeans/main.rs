@@ -1,21 +1,28 @@
 mod args;
+mod clients;
 
+use std::path::Path;
 use std::process::ExitCode;
+use std::sync::Arc;
 
-use anyhow::{anyhow, Context, Result};
+use anyhow::{Context, Result, anyhow};
 use clap::Parser;
 use futures::sink::SinkExt;
 use tokio::io::AsyncWriteExt;
 use tokio::net::{TcpListener, TcpStream};
 use tokio::sync::mpsc;
+use tokio::time::Instant;
 use tokio::{select, signal};
 use tokio_stream::StreamExt;
 use tokio_util::sync::CancellationToken;
-use tracing::{debug, error, info, instrument, warn, Level};
+use tracing::{Level, debug, error, info, instrument, warn};
 
 use crate::args::Args;
+use crate::clients::Clients;
+use beanstalk_rs::types::config::Config;
+use beanstalk_rs::types::tube::session::SharedServer;
 use beanstalk_rs::wire::events::BeanstalkClientEvent;
-use beanstalk_rs::wire::{self, decoder};
+use beanstalk_rs::wire::{self, binary, decoder};
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> ExitCode {
@@ -35,6 +42,17 @@ async fn main() -> ExitCode {
         return ExitCode::from(2);
     }
 
+    let config = match args.config.as_deref().map(load_config).transpose() {
+        Ok(config) => config.unwrap_or_default(),
+        Err(error) => {
+            error!(
+                error = format!("{error:#}"),
+                "failed to load configuration"
+            );
+            return ExitCode::from(2);
+        },
+    };
+
     // Cancellation and termination channel.
     // TODO: this termination channel is a mpsc - so could be repurposed when
     // implementing durability as a stream of events.
@@ -57,28 +75,50 @@ async fn main() -> ExitCode {
         },
     };
 
+    let server = SharedServer::new(&config, args.max_job_size);
+    {
+        let server = server.clone();
+        let cancel = cancel.clone();
+        tokio::spawn(async move { server.run_timers(cancel).await });
+    }
+
     let (shutdown_hold, mut shutdown_wait) = mpsc::channel::<()>(1);
 
-    let exit_code =
-        match accept_loop(cancel, shutdown_hold, listener, args.max_job_size)
-            .await
-        {
-            Ok(()) => ExitCode::SUCCESS,
-            Err(error) => {
-                error!(%error, "encountered runtime error");
-                ExitCode::FAILURE
-            },
-        };
+    let exit_code = match accept_loop(
+        cancel,
+        shutdown_hold,
+        listener,
+        server,
+        Arc::default(),
+        args.max_job_size,
+    )
+    .await
+    {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            error!(%error, "encountered runtime error");
+            ExitCode::FAILURE
+        },
+    };
 
     shutdown_wait.recv().await;
 
     exit_code
 }
 
+fn load_config(path: &Path) -> Result<Config> {
+    let input = std::fs::read_to_string(path)
+        .with_context(|| format!("reading {}", path.display()))?;
+    Config::from_yaml(&input)
+        .with_context(|| format!("parsing {}", path.display()))
+}
+
 async fn accept_loop(
     cancel: CancellationToken,
     shutdown_hold: mpsc::Sender<()>,
     listener: TcpListener,
+    server: Arc<SharedServer>,
+    clients: Arc<Clients>,
     max_job_size: u32,
 ) -> Result<()> {
     info!(addr = %listener.local_addr()?, "listening");
@@ -95,6 +135,8 @@ async fn accept_loop(
                     cancel.clone(),
                     shutdown_hold.clone(),
                     conn,
+                    server.clone(),
+                    clients.clone(),
                     max_job_size,
                 ));
             },
@@ -111,6 +153,8 @@ async fn do_client_loop(
     cancel: CancellationToken,
     _shutdown_hold: mpsc::Sender<()>,
     conn: TcpStream,
+    server: Arc<SharedServer>,
+    clients: Arc<Clients>,
     max_job_size: u32,
 ) -> Result<()> {
     use wire::protocol::*;
@@ -119,7 +163,26 @@ async fn do_client_loop(
 
     conn.set_nodelay(true).context("setting NODELAY")?;
 
-    let mut framed = wire::framed(conn);
+    // The client's token is cancelled on shutdown or by a `kill-client`.
+    let (client, cancel) = clients.register(conn.peer_addr()?, &cancel);
+    let mut session = server.session();
+
+    // Binary protocol clients are told apart by the first byte they send.
+    let mut first = [0u8];
+    let binary = select! {
+        x = conn.peek(&mut first) => x? == 1 && first[0] == binary::MAGIC[0],
+        _ = cancel.cancelled() => false,
+    };
+
+    let mut framed = if binary {
+        debug!("using binary protocol");
+        wire::framed_binary(conn)
+    } else {
+        wire::framed(conn)
+    };
+
+    // A command in the put family, and the job data received for it so far.
+    let mut pending: Option<(Command, Vec<u8>)> = None;
 
     let conn_result = loop {
         let evt = select! {
@@ -150,17 +213,87 @@ async fn do_client_loop(
             },
         };
 
-        let BeanstalkClientEvent::Command(cmd) = evt else {
-            framed.send(Response::BadFormat).await?;
-            continue;
+        let (cmd, data) = match evt {
+            BeanstalkClientEvent::Command(cmd) if cmd.put_bytes().is_some() => {
+                // Refuses data too large for the job as soon as the command
+                // is read, as beanstalkd does, and discards it as it arrives.
+                if cmd.put_bytes() > Some(session.max_job_size(&cmd)) {
+                    select! {
+                        x = framed.send(Response::JobTooBig) => x?,
+                        _ = cancel.cancelled() => break Ok(()),
+                    }
+                    continue;
+                }
+
+                pending = Some((cmd, Vec::new()));
+                continue;
+            },
+            BeanstalkClientEvent::Command(cmd) => (cmd, Vec::new()),
+            BeanstalkClientEvent::PutChunk(chunk) => {
+                if let Some((_, data)) = &mut pending {
+                    data.extend_from_slice(&chunk);
+                }
+                continue;
+            },
+            BeanstalkClientEvent::PutEnd => match pending.take() {
+                Some(put) => put,
+                None => continue,
+            },
+            BeanstalkClientEvent::Discarded => continue,
         };
 
-        let resp = match cmd {
-            _ => Response::InternalError,
+        client.update(|c| c.last_active = Instant::now());
+
+        let resps = match cmd {
+            Command::Quit => break Ok(()),
+            Command::ClientName { name } => {
+                client.update(|c| c.name = Some(name));
+                vec![Response::Named]
+            },
+            Command::Capabilities => vec![Response::OkCapabilities {
+                data: Capabilities {
+                    version: env!("CARGO_PKG_VERSION"),
+                    extensions: EXTENSIONS,
+                    limits: Limits {
+                        max_job_size,
+                        max_tube_name_length: MAX_NAME_LEN,
+                    },
+                    // TODO: report these once implemented.
+                    durability: false,
+                    replication: false,
+                },
+            }],
+            Command::ListClients => vec![Response::OkListClients {
+                clients: clients.list(),
+            }],
+            Command::KillClient { id } => {
+                if clients.kill(id) {
+                    vec![Response::Killed]
+                } else {
+                    vec![Response::NotFound]
+                }
+            },
+            cmd => select! {
+                resps = session.handle(cmd, data) => resps,
+                _ = cancel.cancelled() => break Ok(()),
+            },
         };
 
+        let reserved = session.reserved();
+        client.update(|c| {
+            c.tube = session.using().to_vec();
+            c.watching = session.watching();
+            c.reserved = reserved;
+        });
+
+        for resp in resps {
+            select! {
+                x = framed.feed(resp) => x?,
+                _ = cancel.cancelled() => break,
+            }
+        }
         select! {
-            x = framed.send(resp) => x?,
+            x = framed.flush() => x?,
             _ = cancel.cancelled() => break Ok(()),
         }
     };
@@ -0,0 +1,279 @@
+//! config provides per-tube configuration, and the configuration file format.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use super::job::Job;
+
+/// The contents of a configuration file, in YAML format:
+///
+/// ```
+/// use beanstalk_rs::types::config::Config;
+///
+/// let config = Config::from_yaml(
+///     "
+/// tubes:
+///   emails:
+///     default-ttr: 300
+///     max-jobs: 10000
+///     dead-letter-tube: emails-failed
+///     dead-letter-timeouts: 3
+/// ",
+/// )
+/// .unwrap();
+///
+/// assert_eq!(config.tubes["emails"].max_jobs, Some(10000));
+/// ```
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Config {
+    /// where jobs that time out re-enter the ready queue, unless overridden by
+    /// their tube's settings
+    #[serde(default)]
+    pub timeout_requeue: Option<RequeuePos>,
+    /// the size in bytes above which job data is compressed while stored, or
+    /// none to store all job data uncompressed
+    #[serde(default)]
+    pub compress_threshold: Option<u32>,
+    /// number of seconds a `put-unique` key is remembered for, overriding the
+    /// server's default of five minutes
+    #[serde(default)]
+    pub dedup_window: Option<u32>,
+    /// settings for individual tubes, by tube name
+    #[serde(default)]
+    pub tubes: BTreeMap<String, TubeConfig>,
+}
+
+impl Config {
+    /// Parses a configuration file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the input isn't valid YAML or contains unknown or
+    /// invalid settings.
+    pub fn from_yaml(input: &str) -> serde_yaml::Result<Self> {
+        serde_yaml::from_str(input)
+    }
+}
+
+/// Settings that change how a single tube behaves. Unset settings leave the
+/// tube behaving as in beanstalkd.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct TubeConfig {
+    /// tube that jobs crossing a dead-letter threshold are buried in, or the
+    /// job's own tube if unset
+    #[serde(
+        default,
+        deserialize_with = "deserialize_name",
+        serialize_with = "serialize_opt_name",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub dead_letter_tube: Option<Vec<u8>>,
+    /// number of releases after which a job is dead-lettered
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dead_letter_releases: Option<u64>,
+    /// number of timeouts after which a job is dead-lettered
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dead_letter_timeouts: Option<u64>,
+    /// number of buries after which a job is dead-lettered
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dead_letter_buries: Option<u64>,
+    /// TTR given to jobs put with a TTR of zero
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_ttr: Option<u32>,
+    /// maximum size of a job's data, overriding the server-wide maximum
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_job_size: Option<u32>,
+    /// maximum number of jobs in the tube, in any state
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_jobs: Option<u64>,
+    /// maximum total size of the data of all jobs in the tube
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_bytes: Option<u64>,
+    /// where jobs that time out re-enter the ready queue, overriding the
+    /// server-wide setting
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_requeue: Option<RequeuePos>,
+    /// amount by which a ready job's effective priority improves for each
+    /// second it waits
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pri_aging: Option<u32>,
+    /// maximum number of jobs reserved from the tube per rate interval
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reserve_rate: Option<u32>,
+    /// number of seconds over which `reserve_rate` applies, or one if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reserve_rate_interval: Option<u32>,
+    /// maximum number of jobs from the tube reserved at once
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_reserved: Option<u64>,
+    /// tube that jobs expiring before being reserved are moved to, or deleted
+    /// if unset
+    #[serde(
+        default,
+        deserialize_with = "deserialize_name",
+        serialize_with = "serialize_opt_name",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub expired_tube: Option<Vec<u8>>,
+    /// number of seconds the results of completed jobs without a reply tube are
+    /// kept, overriding the server-wide default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result_retention: Option<u32>,
+    /// tubes that each job put to the tube is copied into, making it a
+    /// broadcast tube if there are any
+    #[serde(
+        default,
+        deserialize_with = "deserialize_names",
+        serialize_with = "serialize_names",
+        skip_serializing_if = "BTreeSet::is_empty"
+    )]
+    pub subscribers: BTreeSet<Vec<u8>>,
+    /// share of reservations given to the tube relative to other watched
+    /// tubes with weights, regardless of priority
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub weight: Option<u32>,
+}
+
+/// Where a job re-enters the ready queue among jobs of the same priority.
+#[derive(
+    Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum RequeuePos {
+    /// ahead of all jobs of the same priority, to be reserved next
+    Front,
+    /// behind all jobs of the same priority, as if newly put
+    #[default]
+    Back,
+}
+
+/// A change to a single [`TubeConfig`] setting, where `None` unsets it.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum TubeSetting {
+    /// On the wire: `dead-letter-tube <tube>`, or `-` to unset.
+    DeadLetterTube(Option<Vec<u8>>),
+    /// On the wire: `dead-letter-releases <count>`, or `0` to unset.
+    DeadLetterReleases(Option<u64>),
+    /// On the wire: `dead-letter-timeouts <count>`, or `0` to unset.
+    DeadLetterTimeouts(Option<u64>),
+    /// On the wire: `dead-letter-buries <count>`, or `0` to unset.
+    DeadLetterBuries(Option<u64>),
+    /// On the wire: `default-ttr <seconds>`, or `0` to unset.
+    DefaultTtr(Option<u32>),
+    /// On the wire: `max-job-size <bytes>`, or `0` to unset.
+    MaxJobSize(Option<u32>),
+    /// On the wire: `max-jobs <count>`, or `0` to unset.
+    MaxJobs(Option<u64>),
+    /// On the wire: `max-bytes <bytes>`, or `0` to unset.
+    MaxBytes(Option<u64>),
+    /// On the wire: `timeout-requeue <front|back>`, or `-` to unset.
+    TimeoutRequeue(Option<RequeuePos>),
+    /// On the wire: `pri-aging <rate>`, or `0` to unset.
+    PriAging(Option<u32>),
+    /// On the wire: `reserve-rate <jobs per interval>`, or `0` to unset.
+    ReserveRate(Option<u32>),
+    /// On the wire: `reserve-rate-interval <seconds>`, or `0` to unset.
+    ReserveRateInterval(Option<u32>),
+    /// On the wire: `max-reserved <count>`, or `0` to unset.
+    MaxReserved(Option<u64>),
+    /// On the wire: `expired-tube <tube>`, or `-` to unset.
+    ExpiredTube(Option<Vec<u8>>),
+    /// On the wire: `result-retention <seconds>`, or `0` to unset.
+    ResultRetention(Option<u32>),
+    /// On the wire: `weight <weight>`, or `0` to unset.
+    Weight(Option<u32>),
+}
+
+impl TubeConfig {
+    /// Applies a change to a single setting.
+    pub fn apply(&mut self, setting: TubeSetting) {
+        use TubeSetting as S;
+
+        match setting {
+            S::DeadLetterTube(v) => self.dead_letter_tube = v,
+            S::DeadLetterReleases(v) => self.dead_letter_releases = v,
+            S::DeadLetterTimeouts(v) => self.dead_letter_timeouts = v,
+            S::DeadLetterBuries(v) => self.dead_letter_buries = v,
+            S::DefaultTtr(v) => self.default_ttr = v,
+            S::MaxJobSize(v) => self.max_job_size = v,
+            S::MaxJobs(v) => self.max_jobs = v,
+            S::MaxBytes(v) => self.max_bytes = v,
+            S::TimeoutRequeue(v) => self.timeout_requeue = v,
+            S::PriAging(v) => self.pri_aging = v,
+            S::ReserveRate(v) => self.reserve_rate = v,
+            S::ReserveRateInterval(v) => self.reserve_rate_interval = v,
+            S::MaxReserved(v) => self.max_reserved = v,
+            S::ExpiredTube(v) => self.expired_tube = v,
+            S::ResultRetention(v) => self.result_retention = v,
+            S::Weight(v) => self.weight = v,
+        }
+    }
+
+    /// Returns true if the job has reached any of the dead-letter thresholds.
+    #[must_use]
+    pub fn dead_letters(&self, job: &Job) -> bool {
+        [
+            (self.dead_letter_releases, job.releases),
+            (self.dead_letter_timeouts, job.timeouts),
+            (self.dead_letter_buries, job.buries),
+        ]
+        .into_iter()
+        .any(|(limit, count)| limit.is_some_and(|limit| count >= limit))
+    }
+}
+
+/// Deserialises a tube name from a string.
+fn deserialize_name<'de, D>(d: D) -> Result<Option<Vec<u8>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Ok(Option::<String>::deserialize(d)?.map(String::into_bytes))
+}
+
+/// Deserialises a set of tube names from a list of strings.
+fn deserialize_names<'de, D>(d: D) -> Result<BTreeSet<Vec<u8>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Ok(BTreeSet::<String>::deserialize(d)?
+        .into_iter()
+        .map(String::into_bytes)
+        .collect())
+}
+
+/// Serialises a tube name as a string.
+pub(crate) fn serialize_name<S>(name: &[u8], s: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    s.serialize_str(&String::from_utf8_lossy(name))
+}
+
+/// Serialises an optional tube name as a string.
+#[allow(clippy::ref_option)] // serde passes fields by reference
+pub(crate) fn serialize_opt_name<S>(
+    name: &Option<Vec<u8>>,
+    s: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    match name {
+        Some(name) => s.serialize_some(&String::from_utf8_lossy(name)),
+        None => s.serialize_none(),
+    }
+}
+
+/// Serialises tube names as a list of strings.
+pub(crate) fn serialize_names<'a, S>(
+    names: impl IntoIterator<Item = &'a Vec<u8>>,
+    s: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    s.collect_seq(names.into_iter().map(|name| String::from_utf8_lossy(name)))
+}
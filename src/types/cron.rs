@@ -0,0 +1,236 @@
+//! cron implements parsing and evaluation of cron schedule expressions.
+
+use std::fmt;
+
+/// A parsed five-field cron expression: `<minute> <hour> <day-of-month>
+/// <month> <day-of-week>`, evaluated in UTC.
+///
+/// Each field is `*` or a comma-separated list of values or `a-b` ranges, any
+/// of which may be followed by a `/n` step. Days of the week run from 0
+/// (Sunday) to 7 (also Sunday). As in Vixie cron, if both the day of the
+/// month and the day of the week are restricted, a day matching either one
+/// matches, where a field is restricted unless it starts with `*`, so `*/2`
+/// isn't. Otherwise, a day must match both.
+///
+/// ```
+/// use beanstalk_rs::types::cron::Cron;
+///
+/// let every_5_minutes = Cron::parse(b"*/5 * * * *").unwrap();
+/// assert_eq!(every_5_minutes.next_after(0), Some(300));
+/// assert_eq!(every_5_minutes.next_after(300), Some(600));
+/// ```
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Cron {
+    expr: String,
+    minutes: u64,  // bit n set if minute n matches
+    hours: u64,    // bit n set if hour n matches
+    days: u64,     // bit n set if day n of the month matches
+    months: u64,   // bit n set if month n matches
+    weekdays: u64, // bit n set if day n of the week matches, 0 is Sunday
+    days_restricted: bool,
+    weekdays_restricted: bool,
+}
+
+/// The furthest ahead [`Cron::next_after`] will search, in days. This covers
+/// the gap between leap days.
+const MAX_SEARCH_DAYS: u64 = 8 * 366;
+
+const SECS_PER_MINUTE: u64 = 60;
+const SECS_PER_DAY: u64 = 24 * 60 * SECS_PER_MINUTE;
+
+impl Cron {
+    /// Parses a cron expression, returning `None` if it's invalid.
+    #[must_use]
+    pub fn parse(expr: &[u8]) -> Option<Self> {
+        let expr = std::str::from_utf8(expr).ok()?;
+        let fields: Vec<&str> = expr.split(' ').collect();
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            return None;
+        };
+
+        let mut weekdays_mask = parse_field(weekdays, 0, 7)?;
+        // Both 0 and 7 represent Sunday.
+        if weekdays_mask & (1 << 7) != 0 {
+            weekdays_mask |= 1;
+        }
+
+        Some(Self {
+            expr: expr.to_owned(),
+            minutes: parse_field(minutes, 0, 59)?,
+            hours: parse_field(hours, 0, 23)?,
+            days: parse_field(days, 1, 31)?,
+            months: parse_field(months, 1, 12)?,
+            weekdays: weekdays_mask,
+            days_restricted: !days.starts_with('*'),
+            weekdays_restricted: !weekdays.starts_with('*'),
+        })
+    }
+
+    /// Returns the first time this expression matches strictly after `after`,
+    /// both in seconds since the Unix epoch. Returns `None` if it doesn't
+    /// match in the next few years, as for `0 0 30 2 *`.
+    #[must_use]
+    pub fn next_after(&self, after: u64) -> Option<u64> {
+        // Start from the beginning of the next minute.
+        let start = (after / SECS_PER_MINUTE).checked_add(1)?;
+        let start_day = start / (SECS_PER_DAY / SECS_PER_MINUTE);
+        let start_minute = start % (SECS_PER_DAY / SECS_PER_MINUTE);
+
+        for day in start_day..start_day.checked_add(MAX_SEARCH_DAYS)? {
+            if !self.matches_day(day) {
+                continue;
+            }
+
+            // Only the first day searched starts part-way through.
+            let from = if day == start_day { start_minute } else { 0 };
+            let Some(minute) = (from..24 * 60)
+                .find(|m| bit(self.hours, m / 60) && bit(self.minutes, m % 60))
+            else {
+                continue;
+            };
+
+            return day
+                .checked_mul(SECS_PER_DAY)?
+                .checked_add(minute.checked_mul(SECS_PER_MINUTE)?);
+        }
+
+        None
+    }
+
+    /// Returns true if the day with the given number of days since the Unix
+    /// epoch matches the day and month fields.
+    fn matches_day(&self, day: u64) -> bool {
+        let (month, day_of_month) = month_and_day(day);
+        // 1970-01-01 was a Thursday.
+        let weekday = day.wrapping_add(4) % 7;
+
+        if !bit(self.months, month) {
+            return false;
+        }
+
+        let day_matches = bit(self.days, day_of_month);
+        let weekday_matches = bit(self.weekdays, weekday);
+        if self.days_restricted && self.weekdays_restricted {
+            day_matches || weekday_matches
+        } else {
+            day_matches && weekday_matches
+        }
+    }
+}
+
+impl fmt::Display for Cron {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.expr)
+    }
+}
+
+/// Returns true if bit `n` of `mask` is set.
+fn bit(mask: u64, n: u64) -> bool {
+    n < 64 && mask & (1 << n) != 0
+}
+
+/// Parses a single cron field with values in `min..=max` into a bitmask.
+fn parse_field(field: &str, min: u64, max: u64) -> Option<u64> {
+    let mut mask = 0u64;
+
+    for item in field.split(',') {
+        let (range, step) = match item.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u64>().ok()?),
+            None => (item, 1),
+        };
+        if step == 0 {
+            return None;
+        }
+
+        let (lo, hi) = if range == "*" {
+            (min, max)
+        } else if let Some((lo, hi)) = range.split_once('-') {
+            (lo.parse().ok()?, hi.parse().ok()?)
+        } else {
+            let v = range.parse().ok()?;
+            // As in traditional cron, `a/n` means `a-max/n`.
+            (v, if item.contains('/') { max } else { v })
+        };
+        if lo < min || hi > max || lo > hi {
+            return None;
+        }
+
+        let steps = usize::try_from(step).ok()?;
+        for v in (lo..=hi).step_by(steps) {
+            mask |= 1 << v;
+        }
+    }
+
+    Some(mask)
+}
+
+/// Converts a number of days since the Unix epoch to a month (1-12) and day of
+/// the month (1-31) in the proleptic Gregorian calendar.
+// See: https://howardhinnant.github.io/date_algorithms.html#civil_from_days
+// Panic safety: the inputs are bounded well below overflow by next_after.
+#[allow(clippy::arithmetic_side_effects)]
+fn month_and_day(days: u64) -> (u64, u64) {
+    let z = days + 719_468;
+    let doe = z % 146_097; // day of the 400-year era
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100); // day of March-based year
+    let mp = (5 * doy + 2) / 153; // month, starting from March = 0
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+
+    (month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2024-02-28T12:34:56Z, a Wednesday.
+    const T: u64 = 1_709_123_696;
+
+    #[test]
+    fn test_parse() {
+        assert!(Cron::parse(b"* * * * *").is_some());
+        assert!(Cron::parse(b"0,15,30-45/5 */2 1-7 1,6 1-5").is_some());
+        assert!(Cron::parse(b"0 0 * * 7").is_some());
+
+        assert!(Cron::parse(b"* * * *").is_none());
+        assert!(Cron::parse(b"* * * * * *").is_none());
+        assert!(Cron::parse(b"*  * * * *").is_none());
+        assert!(Cron::parse(b"60 * * * *").is_none());
+        assert!(Cron::parse(b"* 24 * * *").is_none());
+        assert!(Cron::parse(b"* * 0 * *").is_none());
+        assert!(Cron::parse(b"* * * 13 *").is_none());
+        assert!(Cron::parse(b"* * * * 8").is_none());
+        assert!(Cron::parse(b"*/0 * * * *").is_none());
+        assert!(Cron::parse(b"5-1 * * * *").is_none());
+        assert!(Cron::parse(b"a * * * *").is_none());
+    }
+
+    #[test]
+    fn test_next_after() {
+        #[track_caller]
+        fn next(expr: &str, after: u64) -> Option<u64> {
+            Cron::parse(expr.as_bytes()).unwrap().next_after(after)
+        }
+
+        assert_eq!(next("* * * * *", T), Some(T - 56 + 60));
+        assert_eq!(next("*/5 * * * *", T), Some(T - 56 - 4 * 60 + 5 * 60));
+        // 2024-02-29 (a leap day) at 00:00.
+        assert_eq!(next("0 0 * * *", T), Some(1_709_164_800));
+        assert_eq!(next("0 0 29 2 *", T), Some(1_709_164_800));
+        // Friday 2024-03-01.
+        assert_eq!(next("0 0 * * 5", T), Some(1_709_251_200));
+        // The 5th or a Sunday, so Sunday 2024-03-03.
+        assert_eq!(next("0 0 5 * 0", T), Some(1_709_424_000));
+        // Sunday 2024-03-03, via either 0 or 7.
+        assert_eq!(next("0 0 * * 7", T), next("0 0 * * 0", T));
+        // A stepped `*` doesn't restrict the day, so both fields must match:
+        // Friday 2024-03-01, and Sunday 2024-03-03, an odd day.
+        assert_eq!(next("0 0 */1 * 5", T), Some(1_709_251_200));
+        assert_eq!(next("0 0 */2 * 0", T), Some(1_709_424_000));
+        // 2025-01-01 at 00:00.
+        assert_eq!(next("0 0 1 1 *", T), Some(1_735_689_600));
+        assert_eq!(next("0 0 30 2 *", T), None);
+    }
+}
@@ -1,3 +1,9 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use miniz_oxide::{deflate, inflate};
+
 use tokio::io::{AsyncRead, AsyncSeek};
 use tokio::time::Instant;
 
@@ -7,15 +13,132 @@ use super::tube::Pri;
 #[derive(Debug)]
 pub struct Job {
     pub pri: Pri,
-    pub data: Vec<u8>,
+    pub data: Body,
     pub state: JobState, // also contains state-specific data
     pub created: Instant,
-    pub ttr: u32,
+    /// time the job last joined the back of a ready queue, from which its
+    /// priority ages
+    pub ready_since: Instant,
+    /// delay set by the last put or release
+    pub delay: Duration,
+    pub ttr: Duration,
     pub reserves: u64,
     pub timeouts: u64,
     pub releases: u64,
     pub buries: u64,
     pub kicks: u64,
+    /// tube the job was in before being dead-lettered
+    pub original_tube: Option<Vec<u8>>,
+    /// metadata attached to the job when it was put
+    pub headers: Headers,
+    /// time after which the job is expired if it hasn't yet been reserved
+    pub expires: Option<Instant>,
+    /// tube the job's result is put to when it's completed, or none to keep
+    /// the result for retrieval by the job's ID
+    pub reply_tube: Option<Vec<u8>>,
+}
+
+/// Key/value metadata attached to a job separately from its data.
+pub type Headers = BTreeMap<Vec<u8>, Vec<u8>>;
+
+/// A job's data as it's stored, which is compressed if it's larger than a
+/// threshold and compressing it saves space. Compression is transparent to
+/// clients, which always see the original data.
+///
+/// ```
+/// use beanstalk_rs::types::job::Body;
+///
+/// let data = b"{\"hello\": \"world\"}".repeat(100);
+/// let body = Body::new(data.clone(), Some(1024));
+///
+/// assert_eq!(body.len(), 1800);
+/// assert!(body.stored_len() < 100);
+/// assert_eq!(body.get(), &data[..]);
+///
+/// // Data at or below the threshold is stored as is.
+/// assert_eq!(Body::new(data, None).stored_len(), 1800);
+/// assert_eq!(Body::new(b"hi".to_vec(), Some(1)).stored_len(), 2);
+/// ```
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Body(Repr);
+
+/// How a [`Body`] is stored, which is private so that compressed data can only
+/// have been produced by [`Body::new`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Repr {
+    Plain(Vec<u8>),
+    /// deflate-compressed data, and the length of the original data
+    Compressed {
+        data: Vec<u8>,
+        len: usize,
+    },
+}
+
+impl Body {
+    /// The deflate compression level, trading speed for size.
+    const LEVEL: u8 = 6;
+
+    /// Stores a job's data, compressed if it's longer than `threshold` bytes
+    /// and that makes it smaller, or as is if there's no threshold.
+    #[must_use]
+    pub fn new(data: Vec<u8>, threshold: Option<u32>) -> Self {
+        let Some(threshold) = threshold else {
+            return Self(Repr::Plain(data));
+        };
+        if u64::try_from(data.len()).unwrap_or(u64::MAX) <= threshold.into() {
+            return Self(Repr::Plain(data));
+        }
+
+        let compressed = deflate::compress_to_vec(&data, Self::LEVEL);
+        if compressed.len() < data.len() {
+            Self(Repr::Compressed {
+                data: compressed,
+                len: data.len(),
+            })
+        } else {
+            Self(Repr::Plain(data))
+        }
+    }
+
+    /// Returns the original data, decompressing it if required.
+    ///
+    /// # Panics
+    ///
+    /// Panics if compressed data was corrupted after being stored.
+    #[must_use]
+    pub fn get(&self) -> Cow<'_, [u8]> {
+        match &self.0 {
+            Repr::Plain(data) => Cow::Borrowed(data),
+            // Panic safety: the data was compressed by Body::new, so is valid
+            // and decompresses to exactly len bytes.
+            Repr::Compressed { data, len } => Cow::Owned(
+                inflate::decompress_to_vec_with_limit(data, *len).unwrap(),
+            ),
+        }
+    }
+
+    /// Returns the length of the original data in bytes.
+    #[must_use]
+    pub fn len(&self) -> u64 {
+        let len = match &self.0 {
+            Repr::Plain(data) => data.len(),
+            Repr::Compressed { len, .. } => *len,
+        };
+        u64::try_from(len).unwrap_or(u64::MAX)
+    }
+
+    /// Returns true if the original data is empty.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the number of bytes used to store the data.
+    #[must_use]
+    pub fn stored_len(&self) -> u64 {
+        let (Repr::Plain(data) | Repr::Compressed { data, .. }) = &self.0;
+        u64::try_from(data.len()).unwrap_or(u64::MAX)
+    }
 }
 
 /// AsyncReadSeek is a supertrait, implemented automatically for all types that
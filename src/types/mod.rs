@@ -1,4 +1,6 @@
 //! types provides common types across the project.
+pub mod config;
+pub mod cron;
 pub mod job;
 pub mod states;
 pub mod tube;
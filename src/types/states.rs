@@ -1,15 +1,20 @@
+use std::time::SystemTime;
+
 use tokio::time::Instant;
 
 use serde::Serialize;
 
 use super::tube::{BuriedPos, ReadyPos};
 
+// For delayed jobs, `until` is when the job becomes ready, and `run_at` is the
+// same time on the wall clock, as reported by stats-job.
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum JobState {
     Ready { pos: ReadyPos },
-    Delayed { until: Instant },
+    Delayed { until: Instant, run_at: SystemTime },
     Reserved { deadline: Instant },
     Buried { pos: BuriedPos },
+    Waiting { prerequisites: usize },
 }
 
 // This impl is used to allow JobStats to be serialised to YAML.
@@ -25,6 +30,7 @@ impl Serialize for JobState {
             Delayed { .. } => "delayed",
             Reserved { .. } => "reserved",
             Buried { .. } => "buried",
+            Waiting { .. } => "waiting",
         })
     }
 }
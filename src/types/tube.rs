@@ -1,12 +1,22 @@
-use std::collections::{BTreeMap, BTreeSet, HashSet};
+pub mod session;
+
+use std::collections::{BTreeMap, BTreeSet};
 use std::hash::BuildHasher;
 use std::num::NonZeroU64;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use serde::Serialize;
+use tokio::sync::Notify;
 use tokio::time::Instant;
 
-use super::job::Job;
+use super::config::{Config, RequeuePos, TubeConfig, TubeSetting};
+use super::cron::Cron;
+use super::job::{Body, Headers, Job};
 use super::states::JobState;
+use crate::wire::protocol::{
+    JobStats, ScheduleInfo, ServerStats, TubeStatsResp,
+};
 
 // Required tube functionality:
 // * State transitions:
@@ -23,7 +33,7 @@ use super::states::JobState;
 // NB: bury and touch can be executed regardless of the current watch set,
 // provided the client reserved that particular job.
 
-#[derive(Debug, PartialEq, Serialize)]
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
 pub struct TubeStats {
     /// number of jobs in ready state with priority < 1024
     #[serde(rename = "current-jobs-urgent")]
@@ -40,6 +50,9 @@ pub struct TubeStats {
     /// number of jobs in buried state
     #[serde(rename = "current-jobs-buried")]
     pub current_jobs_buried: u64,
+    /// number of jobs waiting on other jobs to be deleted
+    #[serde(rename = "current-jobs-waiting")]
+    pub current_jobs_waiting: u64,
     /// total jobs created in this tube
     #[serde(rename = "total-jobs")]
     pub total_jobs: u64,
@@ -55,12 +68,29 @@ pub struct TubeStats {
     pub current_watching: u64,
     /// number of seconds this queue has been paused for in total
     pub pause: u32,
+    /// total size in bytes of the data of jobs in this tube
+    #[serde(rename = "current-bytes")]
+    pub current_bytes: u64,
+    /// total size in bytes of the data of jobs in this tube as stored, after
+    /// compressing the data of large jobs
+    #[serde(rename = "current-stored-bytes")]
+    pub current_stored_bytes: u64,
     /// number of `delete` commands issued for this tube
     #[serde(rename = "cmd-delete")]
     pub cmd_delete: u64,
     /// number of `pause-tube` commands issued for this tube
     #[serde(rename = "cmd-pause-tube")]
     pub cmd_pause_tube: u64,
+    /// number of `reserve` commands that found no job while this queue had
+    /// ready jobs held back by its rate limit
+    #[serde(rename = "reserves-throttled")]
+    pub reserves_throttled: u64,
+    /// number of jobs in this tube that expired before being reserved
+    #[serde(rename = "total-jobs-expired")]
+    pub total_jobs_expired: u64,
+    /// number of times a job in this tube has been reserved
+    #[serde(rename = "total-reserves")]
+    pub total_reserves: u64,
 }
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq, PartialOrd, Ord)]
@@ -69,47 +99,543 @@ struct JobId(NonZeroU64);
 #[derive(Clone, Copy, Debug, Eq, PartialEq, PartialOrd, Ord)]
 pub struct BuriedPos(u64);
 
+/// A job's position in a ready queue, which is ordered by rank, then by
+/// sequence number.
 #[derive(Clone, Copy, Debug, Eq, PartialEq, PartialOrd, Ord)]
-pub struct ReadyPos(u64);
+pub struct ReadyPos {
+    rank: u64,
+    sn: u64,
+}
+
+impl ReadyPos {
+    /// Sequence numbers are allocated upwards from here for jobs joining the
+    /// back of the ready queue, and downwards from just below for jobs joining
+    /// the front, so either end can be extended without renumbering.
+    const MIDPOINT: u64 = 1 << 63;
+}
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq, PartialOrd, Ord)]
 pub struct Pri(u32);
 
-#[derive(Debug, Eq, PartialEq, PartialOrd, Ord)]
+impl Pri {
+    /// Ready jobs with a priority below this are counted as urgent.
+    const URGENT_BELOW: u32 = 1024;
+
+    /// Returns true if a ready job with this priority is counted as urgent.
+    fn is_urgent(self) -> bool {
+        self.0 < Self::URGENT_BELOW
+    }
+}
+
+impl From<u32> for Pri {
+    fn from(value: u32) -> Self {
+        Self(value)
+    }
+}
+
+impl From<Pri> for u32 {
+    fn from(value: Pri) -> Self {
+        value.0
+    }
+}
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq, PartialOrd, Ord)]
 struct QueueName(Vec<u8>);
 
-#[derive(Debug)]
-struct QueueSet(HashSet<QueueName>);
+/// A queue name, or a pattern in which each `*` matches any run of characters,
+/// so that it matches both existing and future queues.
+#[derive(Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
+struct QueuePattern(Vec<u8>);
+
+impl QueuePattern {
+    /// Returns the part of the pattern before the first `*`, which all
+    /// matching queue names start with.
+    fn prefix(&self) -> &[u8] {
+        self.0.split(|&c| c == b'*').next().unwrap_or_default()
+    }
+
+    /// Returns true if the pattern matches the queue name.
+    fn matches(&self, qn: &QueueName) -> bool {
+        let mut parts = self.0.split(|&c| c == b'*');
+        let Some(mut rest) = qn.0.strip_prefix(self.prefix()) else {
+            return false;
+        };
+        parts.next();
+        let Some(last) = parts.next_back() else {
+            // Without a `*`, the pattern is a plain name.
+            return rest.is_empty();
+        };
+
+        // Taking the earliest match of each part between `*`s leaves as much
+        // of the name as possible for the parts after it.
+        for part in parts.filter(|part| !part.is_empty()) {
+            let Some(i) = rest.windows(part.len()).position(|w| w == part)
+            else {
+                return false;
+            };
+            rest = rest.get(i.saturating_add(part.len())..).unwrap_or_default();
+        }
+
+        rest.ends_with(last)
+    }
+}
+
+/// How a client's `reserve` chooses between the queues it watches.
+#[derive(
+    Clone, Copy, Debug, Default, Eq, PartialEq, PartialOrd, Ord, Serialize,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum ReserveMode {
+    /// the best priority job across all watched queues, as in beanstalkd
+    #[default]
+    Priority,
+    /// a job from the first watched queue, in the order they were watched,
+    /// that a job can be reserved from
+    Ordered,
+}
+
+/// The unit a client gives and receives times in.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum TimeUnit {
+    /// whole seconds, as in beanstalkd
+    #[default]
+    Seconds,
+    /// whole milliseconds
+    Milliseconds,
+}
+
+impl TimeUnit {
+    /// Converts a number of this unit to a duration.
+    #[must_use]
+    pub fn duration(self, n: u32) -> Duration {
+        match self {
+            Self::Seconds => Duration::from_secs(n.into()),
+            Self::Milliseconds => Duration::from_millis(n.into()),
+        }
+    }
+
+    /// Converts a duration to a whole number of this unit, rounding down and
+    /// saturating at `u32::MAX`.
+    #[must_use]
+    pub fn count(self, d: Duration) -> u32 {
+        match self {
+            Self::Seconds => secs_u32(d),
+            Self::Milliseconds => {
+                u32::try_from(d.as_millis()).unwrap_or(u32::MAX)
+            },
+        }
+    }
+}
+
+/// The queues watched by a client, by name or pattern, in the order they were
+/// watched.
+#[derive(Clone, Debug, Default, Eq, PartialEq, PartialOrd, Ord)]
+struct QueueSet {
+    patterns: Vec<QueuePattern>,
+    mode: ReserveMode,
+}
+
+impl QueueSet {
+    /// Returns true if any of the set's patterns match the queue name.
+    fn matches(&self, qn: &QueueName) -> bool {
+        self.patterns.iter().any(|p| p.matches(qn))
+    }
+
+    /// Returns the set's names and patterns, in the order given to `watch`.
+    fn list(&self) -> Vec<Vec<u8>> {
+        self.patterns.iter().map(|p| p.0.clone()).collect()
+    }
+
+    /// Returns the names of the existing queues matching the set.
+    fn resolve(
+        &self,
+        queues: &BTreeMap<QueueName, TubeState>,
+    ) -> BTreeSet<QueueName> {
+        self.patterns
+            .iter()
+            .flat_map(|p| Self::resolve_pattern(p, queues))
+            .cloned()
+            .collect()
+    }
+
+    /// Returns the names of the existing queues matching each of the set's
+    /// patterns in turn, excluding those matched by earlier patterns.
+    fn resolve_each(
+        &self,
+        queues: &BTreeMap<QueueName, TubeState>,
+    ) -> Vec<BTreeSet<QueueName>> {
+        let mut seen = BTreeSet::new();
+
+        self.patterns
+            .iter()
+            .map(|p| {
+                Self::resolve_pattern(p, queues)
+                    .filter(|qn| seen.insert(*qn))
+                    .cloned()
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Returns the names of the existing queues matching a pattern.
+    fn resolve_pattern<'a>(
+        p: &'a QueuePattern,
+        queues: &'a BTreeMap<QueueName, TubeState>,
+    ) -> impl Iterator<Item = &'a QueueName> {
+        let prefix = p.prefix();
+        queues
+            .range(QueueName(prefix.to_vec())..)
+            .map(|(qn, _)| qn)
+            .take_while(move |qn| qn.0.starts_with(prefix))
+            .filter(move |qn| p.matches(qn))
+    }
+}
 
 pub struct TubeState {
     buried: BTreeMap<BuriedPos, JobId>, // position -> job ID
     buried_sn: BuriedPos,
     ready: BTreeMap<ReadyPos, JobId>, // position -> job ID
-    ready_sn: ReadyPos,
+    ready_sn: u64,
+    ready_front_sn: u64,
+    created: Instant,
     // NB: Instants are only non-decreasing, so must tolerate duplication.
     delayed: BTreeSet<(Instant, JobId)>, // (ready time, job ID)
+    expiring: BTreeSet<(Instant, JobId)>, // (expiry time, job ID)
+    reserved: BTreeSet<(Instant, JobId)>, // (deadline, job ID)
     pause_until: Option<Instant>,
+    // The time of the next reservation if made at exactly the rate limit. See:
+    // https://en.wikipedia.org/wiki/Generic_cell_rate_algorithm
+    reserve_tat: Instant,
+    data_bytes: u64, // total size of the data of jobs in this queue
+    stored_bytes: u64, // as data_bytes, but after any compression
+    config: TubeConfig,
     stats: TubeStats,
 }
 
-// TODO: make it configurable if jobs that time out and re-enter the queue go to
-// the back or front. Defaulting to the back may be sensible.
-// TODO: allow the user to configure a limit to ensure a job that times out N
-// times gets buried.
 impl TubeState {
-    /// Inserts a job into the ready queue. Panics if the job ID is already
-    /// present.
-    fn put_ready(&mut self, job_id: JobId) -> ReadyPos {
-        let rp = self.ready_sn;
-        self.ready_sn = ReadyPos(self.ready_sn.0 + 1);
+    fn new() -> Self {
+        Self {
+            buried: BTreeMap::new(),
+            buried_sn: BuriedPos(0),
+            ready: BTreeMap::new(),
+            ready_sn: ReadyPos::MIDPOINT,
+            ready_front_sn: ReadyPos::MIDPOINT - 1,
+            created: Instant::now(),
+            delayed: BTreeSet::new(),
+            expiring: BTreeSet::new(),
+            reserved: BTreeSet::new(),
+            pause_until: None,
+            reserve_tat: Instant::now(),
+            data_bytes: 0,
+            stored_bytes: 0,
+            config: TubeConfig::default(),
+            stats: TubeStats::default(),
+        }
+    }
+
+    /// Returns the number of jobs in this queue, in any state.
+    fn current_jobs(&self) -> u64 {
+        [
+            self.stats.current_jobs_ready,
+            self.stats.current_jobs_reserved,
+            self.stats.current_jobs_delayed,
+            self.stats.current_jobs_buried,
+            self.stats.current_jobs_waiting,
+        ]
+        .into_iter()
+        .fold(0, u64::saturating_add)
+    }
+
+    /// Counts a job's data towards the size of this queue's jobs.
+    fn add_data(&mut self, data: &Body) {
+        self.data_bytes = self.data_bytes.saturating_add(data.len());
+        self.stored_bytes = self.stored_bytes.saturating_add(data.stored_len());
+    }
+
+    /// Stops counting a job's data towards the size of this queue's jobs.
+    fn take_data(&mut self, data: &Body) {
+        self.data_bytes = self.data_bytes.saturating_sub(data.len());
+        self.stored_bytes = self.stored_bytes.saturating_sub(data.stored_len());
+    }
+
+    /// Checks that a job of the given size may be put to this queue, given its
+    /// limits and the server-wide maximum job size.
+    fn check_put(&self, size: u64, max_job_size: u32) -> Result<(), PutError> {
+        self.check_size(size, max_job_size)?;
+
+        let full =
+            self.config
+                .max_jobs
+                .is_some_and(|max| self.current_jobs() >= max)
+                || self.config.max_bytes.is_some_and(|max| {
+                    self.data_bytes.saturating_add(size) > max
+                });
+        if full {
+            return Err(PutError::QuotaExceeded);
+        }
+
+        Ok(())
+    }
+
+    /// Checks that a job of the given size isn't larger than this queue's or
+    /// the server-wide maximum job size.
+    fn check_size(&self, size: u64, max_job_size: u32) -> Result<(), PutError> {
+        let max_job_size = self.config.max_job_size.unwrap_or(max_job_size);
+        if size > u64::from(max_job_size) {
+            return Err(PutError::JobTooBig);
+        }
+
+        Ok(())
+    }
+
+    /// Returns the ID of the job at the head of the ready queue, if any.
+    fn peek_ready(&self) -> Option<JobId> {
+        self.ready.values().next().copied()
+    }
+
+    /// Returns the ID of the delayed job that becomes ready soonest, if any.
+    fn peek_delayed(&self) -> Option<JobId> {
+        self.delayed.first().map(|&(_, id)| id)
+    }
+
+    /// Returns the ID of the job buried longest ago, if any.
+    fn peek_buried(&self) -> Option<JobId> {
+        self.buried.values().next().copied()
+    }
+
+    /// Returns the effective priority and ID of the job at the head of the
+    /// ready queue, if any, comparable with those of other queues.
+    fn peek_ready_pri(&self) -> Option<(u64, JobId)> {
+        let (pos, &id) = self.ready.first_key_value()?;
+
+        // Undo the offset applied by rank() to get the aged priority now.
+        let rate = u64::from(self.config.pri_aging.unwrap_or(0));
+        let elapsed = self.created.elapsed().as_secs();
+        let pri = pos.rank.saturating_sub(rate.saturating_mul(elapsed));
+
+        Some((pri, id))
+    }
+
+    /// Returns true if the queue has as many reserved jobs as it's allowed.
+    fn is_at_max_reserved(&self) -> bool {
+        self.config
+            .max_reserved
+            .is_some_and(|max| self.stats.current_jobs_reserved >= max)
+    }
+
+    /// Returns true if the queue is paused at `now`.
+    fn is_paused(&self, now: Instant) -> bool {
+        self.pause_until.is_some_and(|until| until > now)
+    }
+
+    /// Returns the period over which this queue's rate limit applies.
+    fn rate_interval(&self) -> Duration {
+        from_secs(self.config.reserve_rate_interval.unwrap_or(1))
+    }
+
+    /// Returns the time between reservations at this queue's rate limit, if
+    /// it has one.
+    fn reserve_interval(&self) -> Option<Duration> {
+        self.rate_interval().checked_div(self.config.reserve_rate?)
+    }
+
+    /// Returns the time from which a job can next be reserved from this queue
+    /// under its rate limit, if later than `now`. Up to an interval's worth of
+    /// reservations can be made at once.
+    fn throttled_until(&self, now: Instant) -> Option<Instant> {
+        let interval = self.reserve_interval()?;
+        let next = self
+            .reserve_tat
+            .checked_add(interval)?
+            .checked_sub(self.rate_interval())?;
+
+        (next > now).then_some(next)
+    }
+
+    /// Counts a reservation from this queue against its rate limit.
+    fn take_reserve_token(&mut self, now: Instant) {
+        if let Some(interval) = self.reserve_interval() {
+            let tat = self.reserve_tat.max(now);
+            self.reserve_tat = tat.checked_add(interval).unwrap_or(tat);
+        }
+    }
+
+    /// Returns the rank of a job with the given priority that has been waiting
+    /// in the ready queue since `since`.
+    ///
+    /// Without aging, a job's rank is its priority. With aging, a job's
+    /// effective priority falls by the aging rate for each second it waits.
+    /// Rather than updating every waiting job as time passes, which preserves
+    /// their order anyway, the rank of each job is instead raised by the aging
+    /// rate for each second between the queue being created and the job
+    /// starting to wait. Ranks are only comparable for the same aging rate, so
+    /// changing the rate requires the queue to be re-ranked.
+    fn rank(&self, pri: Pri, since: Instant) -> u64 {
+        let rate = u64::from(self.config.pri_aging.unwrap_or(0));
+        let elapsed = since.saturating_duration_since(self.created).as_secs();
+
+        u64::from(pri.0).saturating_add(rate.saturating_mul(elapsed))
+    }
+
+    /// Inserts a job that has been waiting since `since` at the back of the
+    /// ready queue, behind other jobs of the same rank. Panics if the job ID is
+    /// already present.
+    fn put_ready(
+        &mut self,
+        job_id: JobId,
+        pri: Pri,
+        since: Instant,
+    ) -> ReadyPos {
+        let rp = ReadyPos {
+            rank: self.rank(pri, since),
+            sn: self.ready_sn,
+        };
+        self.ready_sn = self.ready_sn.saturating_add(1);
+
+        self.insert_ready(job_id, pri, rp)
+    }
+
+    /// Inserts a job that has been waiting since `since` at the front of the
+    /// ready queue, ahead of other jobs of the same rank. Panics if the job ID
+    /// is already present.
+    fn put_ready_front(
+        &mut self,
+        job_id: JobId,
+        pri: Pri,
+        since: Instant,
+    ) -> ReadyPos {
+        let rp = ReadyPos {
+            rank: self.rank(pri, since),
+            sn: self.ready_front_sn,
+        };
+        self.ready_front_sn = self.ready_front_sn.saturating_sub(1);
 
+        self.insert_ready(job_id, pri, rp)
+    }
+
+    /// Inserts a job into the ready queue at the given position.
+    fn insert_ready(
+        &mut self,
+        job_id: JobId,
+        pri: Pri,
+        rp: ReadyPos,
+    ) -> ReadyPos {
         assert!(self.ready.insert(rp, job_id).is_none());
 
-        self.stats.current_jobs_ready += 1;
+        self.stats.current_jobs_ready =
+            self.stats.current_jobs_ready.saturating_add(1);
+        if pri.is_urgent() {
+            self.stats.current_jobs_urgent =
+                self.stats.current_jobs_urgent.saturating_add(1);
+        }
 
         rp
     }
 
+    /// Re-ranks every ready job for the queue's current aging rate, from when
+    /// each started waiting, updating the jobs' states to match. Jobs of the
+    /// same rank keep their order.
+    fn rerank_ready(&mut self, jobs: &mut BTreeMap<JobId, (QueueName, Job)>) {
+        for (pos, id) in std::mem::take(&mut self.ready) {
+            // Panic safety: jobs in a ready queue must exist.
+            let (_, job) = jobs.get_mut(&id).unwrap();
+            let pos = ReadyPos {
+                rank: self.rank(job.pri, job.ready_since),
+                sn: pos.sn,
+            };
+            self.ready.insert(pos, id);
+            job.state = JobState::Ready { pos };
+        }
+    }
+
+    /// Moves a ready job to its place in the queue for a new priority,
+    /// returning its new position. The job keeps any aging it has accrued, and
+    /// its position relative to other jobs of the same rank. Panics if that job
+    /// doesn't exist in the ready queue.
+    fn reprioritise_ready(
+        &mut self,
+        pos: ReadyPos,
+        from: Pri,
+        to: Pri,
+    ) -> ReadyPos {
+        let job_id = self.ready.remove(&pos).unwrap();
+        let new_pos = ReadyPos {
+            rank: pos
+                .rank
+                .saturating_sub(from.0.into())
+                .saturating_add(to.0.into()),
+            sn: pos.sn,
+        };
+        assert!(self.ready.insert(new_pos, job_id).is_none());
+
+        match (from.is_urgent(), to.is_urgent()) {
+            (false, true) => {
+                self.stats.current_jobs_urgent =
+                    self.stats.current_jobs_urgent.saturating_add(1);
+            },
+            (true, false) => {
+                self.stats.current_jobs_urgent =
+                    self.stats.current_jobs_urgent.saturating_sub(1);
+            },
+            _ => {},
+        }
+
+        new_pos
+    }
+
+    /// Inserts a job into the ready queue, or the delayed queue if it has a
+    /// delay, returning the job's new state. A ready job waits from `now`, and
+    /// a delayed job's wall-clock time is found from `wall_now`, the same time
+    /// on the wall clock.
+    fn put_ready_or_delayed(
+        &mut self,
+        job_id: JobId,
+        pri: Pri,
+        delay: Duration,
+        now: Instant,
+        wall_now: SystemTime,
+    ) -> JobState {
+        match (now.checked_add(delay), wall_now.checked_add(delay)) {
+            (Some(until), Some(run_at)) if !delay.is_zero() => {
+                self.put_delayed(job_id, until);
+                JobState::Delayed { until, run_at }
+            },
+            _ => JobState::Ready {
+                pos: self.put_ready(job_id, pri, now),
+            },
+        }
+    }
+
+    /// Inserts a job into the reserved set, returning the deadline by which it
+    /// must be deleted, released, buried, or touched.
+    fn put_reserved(
+        &mut self,
+        job_id: JobId,
+        ttr: Duration,
+        now: Instant,
+    ) -> Instant {
+        // As in beanstalkd, a TTR of 0 is treated as 1 second.
+        let ttr = if ttr.is_zero() {
+            Duration::from_secs(1)
+        } else {
+            ttr
+        };
+        let deadline = now.checked_add(ttr).unwrap_or(now);
+
+        assert!(self.reserved.insert((deadline, job_id)));
+
+        self.stats.current_jobs_reserved =
+            self.stats.current_jobs_reserved.saturating_add(1);
+
+        deadline
+    }
+
+    /// Counts a job as waiting on other jobs.
+    fn put_waiting(&mut self) {
+        self.stats.current_jobs_waiting =
+            self.stats.current_jobs_waiting.saturating_add(1);
+    }
+
     /// Inserts a job into the buried queue.
     fn put_buried(&mut self, job_id: JobId) -> BuriedPos {
         let bp = self.buried_sn;
@@ -129,6 +655,11 @@ impl TubeState {
         self.stats.current_jobs_delayed += 1;
     }
 
+    /// Records when a job that hasn't yet been reserved expires.
+    fn put_expiring(&mut self, job_id: JobId, expires: Instant) {
+        assert!(self.expiring.insert((expires, job_id)));
+    }
+
     /// Removes a job from the buried list.
     fn take_buried(&mut self, pos: &BuriedPos) {
         self.buried.remove(pos).unwrap();
@@ -136,80 +667,3163 @@ impl TubeState {
         self.stats.current_jobs_buried -= 1;
     }
 
+    /// Removes a job from the delayed queue. Panics if that job doesn't exist
+    /// in the delayed queue.
+    fn take_delayed(&mut self, job_id: JobId, until: Instant) {
+        assert!(self.delayed.remove(&(until, job_id)));
+
+        self.stats.current_jobs_delayed =
+            self.stats.current_jobs_delayed.saturating_sub(1);
+    }
+
+    /// Forgets when a job expires. Panics if it isn't recorded.
+    fn take_expiring(&mut self, job_id: JobId, expires: Instant) {
+        assert!(self.expiring.remove(&(expires, job_id)));
+    }
+
+    /// Removes a job from the queue or count corresponding to its state.
+    /// Panics if a ready, delayed, or buried job doesn't exist in its queue.
+    fn take(&mut self, job_id: JobId, pri: Pri, state: JobState) {
+        match state {
+            JobState::Ready { pos } => self.take_ready(pri, pos),
+            JobState::Delayed { until, .. } => {
+                self.take_delayed(job_id, until);
+            },
+            JobState::Reserved { deadline } => {
+                self.take_reserved(job_id, deadline);
+            },
+            JobState::Buried { pos } => self.take_buried(&pos),
+            JobState::Waiting { .. } => {
+                self.stats.current_jobs_waiting =
+                    self.stats.current_jobs_waiting.saturating_sub(1);
+            },
+        }
+    }
+
+    /// Removes a job from the reserved set. Panics if that job doesn't exist in
+    /// the reserved set.
+    fn take_reserved(&mut self, job_id: JobId, deadline: Instant) {
+        assert!(self.reserved.remove(&(deadline, job_id)));
+
+        self.stats.current_jobs_reserved =
+            self.stats.current_jobs_reserved.saturating_sub(1);
+    }
+
     /// Mark a job at a given position as reserved, removing it from the ready
     /// queue. Panics if that job doesn't exist in the ready queue.
-    fn take_ready(&mut self, pos: ReadyPos) {
+    fn take_ready(&mut self, pri: Pri, pos: ReadyPos) {
         self.ready.remove(&pos).unwrap();
 
-        self.stats.current_jobs_ready -= 1;
+        self.stats.current_jobs_ready =
+            self.stats.current_jobs_ready.saturating_sub(1);
+        if pri.is_urgent() {
+            self.stats.current_jobs_urgent =
+                self.stats.current_jobs_urgent.saturating_sub(1);
+        }
+    }
+}
+
+/// A template for jobs the server puts on a schedule.
+struct RecurringJob {
+    qn: QueueName,
+    pri: Pri,
+    ttr: Duration,
+    data: Vec<u8>,
+    cron: Cron,
+    next_run: Option<u64>, // seconds since the Unix epoch
+}
+
+impl RecurringJob {
+    /// Creates a template from which a job is put to the named queue each time
+    /// the cron expression matches after `now`.
+    fn new(
+        qn: QueueName,
+        pri: Pri,
+        ttr: Duration,
+        data: Vec<u8>,
+        cron: Cron,
+        now: SystemTime,
+    ) -> Self {
+        let next_run = cron.next_after(unix_secs(now));
+        Self {
+            qn,
+            pri,
+            ttr,
+            data,
+            cron,
+            next_run,
+        }
     }
 }
 
+/// Identifies a `put-unique` job by the queue it was put to and its key.
+type DedupKey = (QueueName, Vec<u8>);
+
+/// Reasons a job couldn't be put.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum PutError {
+    /// the job's data is larger than its queue's or the server's maximum
+    JobTooBig,
+    /// the queue is at its maximum number of jobs or bytes of job data
+    QuotaExceeded,
+    /// a job the new job was to wait on doesn't exist, or the job being
+    /// completed isn't reserved
+    NotFound,
+}
+
 pub struct Server {
     id: &'static str,
     jobs: BTreeMap<JobId, (QueueName, Job)>,
     queues: BTreeMap<QueueName, TubeState>,
+    next_job_id: NonZeroU64,
+    // Deduplication keys are remembered for dedup_window after the first put,
+    // even if the job they refer to has since been deleted.
+    dedup_keys: BTreeMap<DedupKey, JobId>,
+    dedup_expiry: BTreeSet<(Instant, DedupKey)>, // (expiry time, key)
+    dedup_window: Duration,
+    // Maps each job to any jobs waiting on it to be deleted.
+    dependents: BTreeMap<JobId, Vec<JobId>>,
+    recurring: BTreeMap<Vec<u8>, RecurringJob>, // name -> template
+    // Results of completed jobs without a reply queue, kept until they expire.
+    results: BTreeMap<JobId, Vec<u8>>,
+    result_expiry: BTreeSet<(Instant, JobId)>, // (expiry time, job ID)
+    // Notified whenever a result is stored.
+    result_stored: Arc<Notify>,
+    // Counts the weighted choices between queues made, to vary each choice.
+    weighted_draws: u64,
+    // Counts the clients watching each distinct set of queues, from which the
+    // number of clients watching each queue is found.
+    watch_sets: BTreeMap<QueueSet, u64>,
+    max_job_size: u32, // unless overridden by a queue's configuration
+    timeout_requeue: RequeuePos, // unless overridden by a queue's configuration
+    compress_threshold: Option<u32>, // larger job data is compressed, if set
     is_draining: bool,
+    started: Instant,
+    // Cumulative counts of commands, connections, and timeouts. Counts of jobs
+    // and queues are found when the statistics are requested.
+    stats: ServerStats,
 }
 
 impl Server {
-    /// Reserves a job by ID, returning its contents.
-    fn reserve_by_id(&mut self, id: JobId) -> Option<&Job> {
-        let (qn, job) = self.jobs.get(&id)?;
+    /// How long a `put-unique` key is remembered for by default.
+    const DEFAULT_DEDUP_WINDOW: Duration = Duration::from_mins(5);
+    /// The largest job accepted by default, in bytes.
+    const DEFAULT_MAX_JOB_SIZE: u32 = 65535;
+    /// The queue that clients use and watch when they connect.
+    const DEFAULT_QUEUE: &'static [u8] = b"default";
+    /// How long the result of a completed job is kept for by default.
+    const DEFAULT_RESULT_RETENTION: Duration = Duration::from_mins(5);
 
-        let JobState::Ready { pos } = job.state else {
-            return None;
+    fn new(id: &'static str) -> Self {
+        Self {
+            id,
+            jobs: BTreeMap::new(),
+            queues: BTreeMap::new(),
+            next_job_id: NonZeroU64::MIN,
+            dedup_keys: BTreeMap::new(),
+            dedup_expiry: BTreeSet::new(),
+            dedup_window: Self::DEFAULT_DEDUP_WINDOW,
+            dependents: BTreeMap::new(),
+            recurring: BTreeMap::new(),
+            results: BTreeMap::new(),
+            result_expiry: BTreeSet::new(),
+            result_stored: Arc::new(Notify::new()),
+            watch_sets: BTreeMap::new(),
+            weighted_draws: 0,
+            max_job_size: Self::DEFAULT_MAX_JOB_SIZE,
+            timeout_requeue: RequeuePos::default(),
+            compress_threshold: None,
+            is_draining: false,
+            started: Instant::now(),
+            stats: ServerStats::default(),
+        }
+    }
+
+    /// Applies the settings from a configuration file, creating any queues it
+    /// names. Queues it doesn't name keep their current settings.
+    fn apply_config(&mut self, config: &Config) {
+        if let Some(timeout_requeue) = config.timeout_requeue {
+            self.timeout_requeue = timeout_requeue;
+        }
+        if let Some(compress_threshold) = config.compress_threshold {
+            self.compress_threshold = Some(compress_threshold);
+        }
+        if let Some(dedup_window) = config.dedup_window {
+            self.dedup_window = Duration::from_secs(dedup_window.into());
+        }
+
+        for (name, tc) in &config.tubes {
+            let qn = QueueName(name.clone().into_bytes());
+            let queue = self.queues.entry(qn).or_insert_with(TubeState::new);
+            let pri_aging = queue.config.pri_aging;
+            queue.config = tc.clone();
+            if tc.pri_aging != pri_aging {
+                queue.rerank_ready(&mut self.jobs);
+            }
+        }
+    }
+
+    /// Creates a job on the named queue, creating the queue if required, and
+    /// returns the new job's ID. A TTR of zero is replaced by the queue's
+    /// default TTR, if configured. If the queue is a broadcast queue, a copy
+    /// of the job is created in each of its subscriber queues instead, and the
+    /// ID of the first copy is returned.
+    fn put(
+        &mut self,
+        qn: QueueName,
+        pri: Pri,
+        delay: u32,
+        ttr: u32,
+        data: Vec<u8>,
+    ) -> Result<JobId, PutError> {
+        self.put_timed(qn, pri, from_secs(delay), from_secs(ttr), data)
+    }
+
+    /// As [`Server::put`], but with the delay and TTR as durations, for
+    /// clients giving times in milliseconds.
+    fn put_timed(
+        &mut self,
+        qn: QueueName,
+        pri: Pri,
+        delay: Duration,
+        ttr: Duration,
+        data: Vec<u8>,
+    ) -> Result<JobId, PutError> {
+        let ids = self.put_copies(qn, pri, delay, ttr, data)?;
+        Ok(first_id(&ids))
+    }
+
+    /// As [`Server::put`], but returns the IDs of all the jobs created.
+    fn put_copies(
+        &mut self,
+        qn: QueueName,
+        pri: Pri,
+        delay: Duration,
+        ttr: Duration,
+        data: Vec<u8>,
+    ) -> Result<Vec<JobId>, PutError> {
+        let after = BTreeSet::new();
+        self.create_jobs(qn, &after, pri, delay, ttr, data, SystemTime::now())
+    }
+
+    /// As [`Server::put`], but the job waits until all the given jobs have
+    /// been deleted before becoming ready (or delayed, if it has a delay).
+    /// Fails without creating a job if any of those jobs don't exist.
+    ///
+    /// As the new job's ID isn't known until it's created, no existing job can
+    /// be waiting on it, so checking that all prerequisites exist is enough to
+    /// rule out cycles.
+    fn put_after(
+        &mut self,
+        qn: QueueName,
+        after: &[JobId],
+        pri: Pri,
+        delay: Duration,
+        ttr: Duration,
+        data: Vec<u8>,
+    ) -> Result<JobId, PutError> {
+        let after: BTreeSet<JobId> = after.iter().copied().collect();
+        if !after.iter().all(|id| self.jobs.contains_key(id)) {
+            return Err(PutError::NotFound);
+        }
+
+        let now = SystemTime::now();
+        let ids = self.create_jobs(qn, &after, pri, delay, ttr, data, now)?;
+        Ok(first_id(&ids))
+    }
+
+    /// Creates a job as for [`Server::create_job`], or if the queue is a
+    /// broadcast queue, a copy of the job in each of its subscriber queues,
+    /// returning the IDs of all the jobs created in subscriber name order.
+    /// Fails without creating any jobs if the job is larger than the broadcast
+    /// queue's maximum job size, or any copy can't be created. The data is
+    /// compressed once and shared by every copy.
+    #[allow(clippy::too_many_arguments)]
+    fn create_jobs(
+        &mut self,
+        qn: QueueName,
+        after: &BTreeSet<JobId>,
+        pri: Pri,
+        delay: Duration,
+        ttr: Duration,
+        data: Vec<u8>,
+        now: SystemTime,
+    ) -> Result<Vec<JobId>, PutError> {
+        let subscribers: Vec<QueueName> = self
+            .queues
+            .get(&qn)
+            .map(|q| q.config.subscribers.iter().cloned().map(QueueName))
+            .into_iter()
+            .flatten()
+            .collect();
+        let size = data_len(&data);
+        let data = Body::new(data, self.compress_threshold);
+        if subscribers.is_empty() {
+            let id = self.create_job(qn, after, pri, delay, ttr, data, now)?;
+            return Ok(vec![id]);
+        }
+
+        // Panic safety: the queue has subscribers, so exists.
+        self.queues[&qn].check_size(size, self.max_job_size)?;
+        for sub in &subscribers {
+            let queue = self
+                .queues
+                .entry(sub.clone())
+                .or_insert_with(TubeState::new);
+            queue.check_put(size, self.max_job_size)?;
+        }
+
+        subscribers
+            .into_iter()
+            .map(|sub| {
+                let data = data.clone();
+                self.create_job(sub, after, pri, delay, ttr, data, now)
+            })
+            .collect()
+    }
+
+    /// Creates a job waiting on the given (existing) jobs, or a ready or
+    /// delayed job if there are none, and returns the new job's ID. Fails if
+    /// the job is too big or its queue is full. A delayed job becomes ready
+    /// `delay` after `now`, by the wall clock.
+    #[allow(clippy::too_many_arguments)]
+    fn create_job(
+        &mut self,
+        qn: QueueName,
+        after: &BTreeSet<JobId>,
+        pri: Pri,
+        delay: Duration,
+        ttr: Duration,
+        data: Body,
+        wall_now: SystemTime,
+    ) -> Result<JobId, PutError> {
+        let queue =
+            self.queues.entry(qn.clone()).or_insert_with(TubeState::new);
+        queue.check_put(data.len(), self.max_job_size)?;
+
+        let id = JobId(self.next_job_id);
+        self.next_job_id = self.next_job_id.saturating_add(1);
+
+        let now = Instant::now();
+        let ttr = match queue.config.default_ttr {
+            Some(default_ttr) if ttr.is_zero() => {
+                Duration::from_secs(default_ttr.into())
+            },
+            _ => ttr,
         };
+        queue.add_data(&data);
+        queue.stats.total_jobs = queue.stats.total_jobs.saturating_add(1);
 
-        // Panic safety: a queue must exist if any jobs reference it, so this
-        // should be safe if correctly implemented.
-        let queue = self.queues.get_mut(qn).unwrap();
+        let state = if after.is_empty() {
+            queue.put_ready_or_delayed(id, pri, delay, now, wall_now)
+        } else {
+            for prerequisite in after {
+                self.dependents.entry(*prerequisite).or_default().push(id);
+            }
+            queue.put_waiting();
+            JobState::Waiting {
+                prerequisites: after.len(),
+            }
+        };
 
-        queue.take_ready(pos);
+        let job = Job {
+            pri,
+            data,
+            state,
+            created: now,
+            ready_since: now,
+            delay,
+            ttr,
+            reserves: 0,
+            timeouts: 0,
+            releases: 0,
+            buries: 0,
+            kicks: 0,
+            original_tube: None,
+            headers: Headers::new(),
+            expires: None,
+            reply_tube: None,
+        };
+        self.jobs.insert(id, (qn, job));
 
-        Some(job)
+        Ok(id)
     }
 
-    /// Releases a job by ID, returning a boolean indicating success.
-    fn release(&mut self, id: JobId) -> bool {
-        let Some((qn, job)) = self.jobs.get(&id) else {
+    /// As [`Server::put_timed`], but attaches the given headers to the job.
+    fn put_with_headers(
+        &mut self,
+        qn: QueueName,
+        headers: &Headers,
+        pri: Pri,
+        delay: Duration,
+        ttr: Duration,
+        data: Vec<u8>,
+    ) -> Result<JobId, PutError> {
+        let ids = self.put_copies(qn, pri, delay, ttr, data)?;
+        for id in &ids {
+            // Panic safety: the job was created just above.
+            self.jobs.get_mut(id).unwrap().1.headers = headers.clone();
+        }
+
+        Ok(first_id(&ids))
+    }
+
+    /// As [`Server::put_timed`], but the job expires `ttl` after being put if
+    /// it hasn't been reserved by then. See [`Server::handle_expired_jobs`].
+    fn put_with_ttl(
+        &mut self,
+        qn: QueueName,
+        pri: Pri,
+        delay: Duration,
+        ttr: Duration,
+        ttl: Duration,
+        data: Vec<u8>,
+    ) -> Result<JobId, PutError> {
+        let ids = self.put_copies(qn, pri, delay, ttr, data)?;
+
+        for &id in &ids {
+            // Panic safety: the job was created just above, so its queue
+            // exists.
+            let (qn, job) = self.jobs.get_mut(&id).unwrap();
+            if let Some(expires) = job.created.checked_add(ttl) {
+                self.queues.get_mut(qn).unwrap().put_expiring(id, expires);
+                job.expires = Some(expires);
+            }
+        }
+
+        Ok(first_id(&ids))
+    }
+
+    /// As [`Server::put_timed`], but when the job is completed, its result is
+    /// put to the given reply queue.
+    fn put_with_reply(
+        &mut self,
+        qn: QueueName,
+        reply_tube: &QueueName,
+        pri: Pri,
+        delay: Duration,
+        ttr: Duration,
+        data: Vec<u8>,
+    ) -> Result<JobId, PutError> {
+        let ids = self.put_copies(qn, pri, delay, ttr, data)?;
+        for id in &ids {
+            // Panic safety: the job was created just above.
+            self.jobs.get_mut(id).unwrap().1.reply_tube =
+                Some(reply_tube.0.clone());
+        }
+
+        Ok(first_id(&ids))
+    }
+
+    /// As [`Server::put_timed`], but if a job was put to the same queue with
+    /// the same key within the deduplication window, returns that job's ID
+    /// instead of creating a new job.
+    fn put_unique(
+        &mut self,
+        qn: QueueName,
+        key: Vec<u8>,
+        pri: Pri,
+        delay: Duration,
+        ttr: Duration,
+        data: Vec<u8>,
+    ) -> Result<JobId, PutError> {
+        let now = Instant::now();
+        self.expire_dedup_keys(now);
+
+        let dk = (qn, key);
+        if let Some(&id) = self.dedup_keys.get(&dk) {
+            return Ok(id);
+        }
+
+        let id = self.put_timed(dk.0.clone(), pri, delay, ttr, data)?;
+
+        if let Some(expires) = now.checked_add(self.dedup_window) {
+            self.dedup_expiry.insert((expires, dk.clone()));
+            self.dedup_keys.insert(dk, id);
+        }
+
+        Ok(id)
+    }
+
+    /// As [`Server::put`], but the job is delayed until the given wall-clock
+    /// time, or is ready immediately if that time has passed.
+    fn put_at(
+        &mut self,
+        qn: QueueName,
+        pri: Pri,
+        run_at: SystemTime,
+        ttr: Duration,
+        data: Vec<u8>,
+        now: SystemTime,
+    ) -> Result<JobId, PutError> {
+        // Delayed from now by the wall clock, this becomes ready at run_at.
+        let delay = run_at.duration_since(now).unwrap_or_default();
+        let after = BTreeSet::new();
+        let ids = self.create_jobs(qn, &after, pri, delay, ttr, data, now)?;
+        Ok(first_id(&ids))
+    }
+
+    /// Changes when a delayed job becomes ready to the given wall-clock time,
+    /// returning a boolean indicating success. If that time has passed, the
+    /// job becomes ready immediately. Fails if the job isn't delayed.
+    fn reschedule(
+        &mut self,
+        id: JobId,
+        run_at: SystemTime,
+        now: SystemTime,
+    ) -> bool {
+        let Some((qn, job)) = self.jobs.get_mut(&id) else {
+            return false;
+        };
+        let JobState::Delayed { until, .. } = job.state else {
             return false;
         };
 
-        todo!()
+        // Panic safety: a queue must exist if any jobs reference it.
+        let queue = self.queues.get_mut(qn).unwrap();
+        queue.take_delayed(id, until);
+
+        let delay = run_at.duration_since(now).unwrap_or_default();
+        job.delay = delay;
+        job.ready_since = Instant::now();
+        job.state = queue.put_ready_or_delayed(
+            id,
+            job.pri,
+            delay,
+            job.ready_since,
+            now,
+        );
+
+        true
     }
 
-    /// Reserves the highest-priority ready job from the provided QueueSet,
-    /// ignoring paused queues.
-    ///
-    /// Stochastic fairness is supported: when scanning for the highest-priority
-    /// job in the queueset, the provided [BuildHasher] is used to randomise
-    /// which queue wins.
-    fn reserve_by_queue(
+    /// Forgets any deduplication keys whose window has passed.
+    fn expire_dedup_keys(&mut self, now: Instant) {
+        while let Some((expires, _)) = self.dedup_expiry.first() {
+            if *expires > now {
+                break;
+            }
+
+            // Panic safety: the set is non-empty, as first() returned Some.
+            let (_, dk) = self.dedup_expiry.pop_first().unwrap();
+            self.dedup_keys.remove(&dk);
+        }
+    }
+
+    /// Registers a recurring job template under the given name, replacing any
+    /// existing template with that name. Fails if the template's data is larger
+    /// than its queue allows, as no jobs could be put from it.
+    fn schedule(
         &mut self,
-        qs: QueueSet,
-        h: impl BuildHasher,
-    ) -> Option<&Job> {
-        todo!()
+        name: Vec<u8>,
+        template: RecurringJob,
+    ) -> Result<(), PutError> {
+        let max_job_size = self.queue_max_job_size(&template.qn);
+        if data_len(&template.data) > u64::from(max_job_size) {
+            return Err(PutError::JobTooBig);
+        }
+
+        self.recurring.insert(name, template);
+        Ok(())
     }
 
-    /// Buries a job by ID, returning a boolean indicating if this occurred.
-    fn bury(&mut self, id: JobId) -> bool {
-        todo!()
+    /// Removes a recurring job template by name, returning a boolean
+    /// indicating success. Jobs already put from it are unaffected.
+    fn unschedule(&mut self, name: &[u8]) -> bool {
+        self.recurring.remove(name).is_some()
     }
 
-    /// Kicks a job by ID, returning a boolean indicating if this occurred.
-    fn kick(&mut self, id: JobId) -> bool {
-        todo!()
+    /// Describes all recurring job templates, ordered by name.
+    fn list_schedules(&self, unit: TimeUnit) -> Vec<ScheduleInfo> {
+        self.recurring
+            .iter()
+            .map(|(name, r)| ScheduleInfo {
+                name: name.clone(),
+                tube: r.qn.0.clone(),
+                cron: r.cron.to_string(),
+                pri: r.pri.into(),
+                ttr: unit.count(r.ttr),
+                next_run: r.next_run,
+            })
+            .collect()
     }
 
-    /// Refreshes a job's TTR, returning a boolean indicating success.
-    fn touch(&mut self, id: JobId) -> bool {
-        todo!()
+    /// Puts a job from each recurring job template that's due. A template
+    /// that missed several runs, for example while the server was paused,
+    /// puts only one job, and next runs at its first match after `now`.
+    fn handle_recurring_jobs(&mut self, now: SystemTime) {
+        let now = unix_secs(now);
+
+        let due: Vec<Vec<u8>> = self
+            .recurring
+            .iter()
+            .filter(|(_, r)| r.next_run.is_some_and(|t| t <= now))
+            .map(|(name, _)| name.clone())
+            .collect();
+
+        for name in due {
+            // Panic safety: names were collected from the map just above.
+            let r = self.recurring.get_mut(&name).unwrap();
+            r.next_run = r.cron.next_after(now);
+
+            let (qn, pri, ttr, data) =
+                (r.qn.clone(), r.pri, r.ttr, r.data.clone());
+            // A job rejected by the queue's limits is skipped for this run.
+            let _ = self.put_timed(qn, pri, Duration::ZERO, ttr, data);
+        }
     }
 
-    /// Pushes any delayed jobs that have become ready into the ready queue.
-    fn handle_delayed_jobs(&mut self) {
-        todo!()
+    /// Changes a single setting for the named queue, creating it if required.
+    fn configure_tube(&mut self, qn: QueueName, setting: TubeSetting) {
+        let queue = self.queues.entry(qn).or_insert_with(TubeState::new);
+        let pri_aging = queue.config.pri_aging;
+        queue.config.apply(setting);
+        if queue.config.pri_aging != pri_aging {
+            queue.rerank_ready(&mut self.jobs);
+        }
+    }
+
+    /// Adds a subscriber to a queue, creating the queue if required and making
+    /// it a broadcast queue. Returns false if it was already subscribed.
+    fn subscribe(&mut self, qn: QueueName, subscriber: QueueName) -> bool {
+        let queue = self.queues.entry(qn).or_insert_with(TubeState::new);
+        queue.config.subscribers.insert(subscriber.0)
+    }
+
+    /// Removes a subscriber from a queue, which stops being a broadcast queue
+    /// once it has no subscribers. Returns false if it wasn't subscribed.
+    fn unsubscribe(&mut self, qn: &QueueName, subscriber: &QueueName) -> bool {
+        self.queues
+            .get_mut(qn)
+            .is_some_and(|q| q.config.subscribers.remove(&subscriber.0))
+    }
+
+    /// Deletes a job by ID, returning a boolean indicating success. Any jobs
+    /// that were waiting only on this job become ready.
+    fn delete(&mut self, id: JobId) -> bool {
+        let Some((qn, job)) = self.jobs.remove(&id) else {
+            return false;
+        };
+
+        // Panic safety: a queue must exist if any jobs reference it.
+        let queue = self.queues.get_mut(&qn).unwrap();
+        queue.take(id, job.pri, job.state);
+        if let Some(expires) = job.expires {
+            queue.take_expiring(id, expires);
+        }
+        queue.take_data(&job.data);
+        queue.stats.cmd_delete = queue.stats.cmd_delete.saturating_add(1);
+
+        self.release_dependents(id);
+
+        true
+    }
+
+    /// Deletes a reserved job, putting the given result to the job's reply
+    /// queue with the job's priority and TTR and a `job-id` header, or if it
+    /// has none, keeping the result to be fetched by the job's ID until its
+    /// queue's result retention period passes. Fails, leaving the job
+    /// reserved, if the job isn't reserved or the result can't be put.
+    fn complete(&mut self, id: JobId, result: Vec<u8>) -> Result<(), PutError> {
+        let Some((qn, job)) = self.jobs.get(&id) else {
+            return Err(PutError::NotFound);
+        };
+        if !matches!(job.state, JobState::Reserved { .. }) {
+            return Err(PutError::NotFound);
+        }
+
+        if let Some(reply_tube) = job.reply_tube.clone() {
+            let headers = Headers::from([(
+                b"job-id".to_vec(),
+                id.0.to_string().into_bytes(),
+            )]);
+            // Put as for put-with-headers, but keeping the TTR to the
+            // millisecond.
+            let (pri, ttr) = (job.pri, job.ttr);
+            let qn = QueueName(reply_tube);
+            for id in self.put_copies(qn, pri, Duration::ZERO, ttr, result)? {
+                // Panic safety: the job was created just above.
+                self.jobs.get_mut(&id).unwrap().1.headers = headers.clone();
+            }
+        } else {
+            if data_len(&result) > u64::from(self.queue_max_job_size(qn)) {
+                return Err(PutError::JobTooBig);
+            }
+            // Panic safety: a queue must exist if any jobs reference it.
+            let config = &self.queues[qn].config;
+            let retention = config
+                .result_retention
+                .map_or(Self::DEFAULT_RESULT_RETENTION, |secs| {
+                    Duration::from_secs(secs.into())
+                });
+
+            let now = Instant::now();
+            self.expire_results(now);
+            if let Some(expires) = now.checked_add(retention) {
+                self.result_expiry.insert((expires, id));
+                self.results.insert(id, result);
+                self.result_stored.notify_waiters();
+            }
+        }
+
+        self.delete(id);
+
+        Ok(())
+    }
+
+    /// Returns the result of a completed job, if it's still kept.
+    fn result(&mut self, id: JobId) -> Option<&[u8]> {
+        self.expire_results(Instant::now());
+        self.results.get(&id).map(Vec::as_slice)
+    }
+
+    /// Returns a [`Notify`] that's notified whenever a job's result is kept.
+    /// Clients waiting on the result of a job should check [`Server::result`]
+    /// again when notified, giving up if neither the job nor its result exist.
+    fn result_stored(&self) -> Arc<Notify> {
+        Arc::clone(&self.result_stored)
+    }
+
+    /// Forgets any results whose retention period has passed.
+    fn expire_results(&mut self, now: Instant) {
+        while let Some((expires, _)) = self.result_expiry.first() {
+            if *expires > now {
+                break;
+            }
+
+            // Panic safety: the set is non-empty, as first() returned Some.
+            let (_, id) = self.result_expiry.pop_first().unwrap();
+            self.results.remove(&id);
+        }
+    }
+
+    /// Records that a job has been deleted with respect to any jobs waiting on
+    /// it, moving those waiting on no other jobs into the ready or delayed
+    /// queues.
+    fn release_dependents(&mut self, id: JobId) {
+        let Some(dependents) = self.dependents.remove(&id) else {
+            return;
+        };
+        let now = Instant::now();
+
+        for dependent in dependents {
+            // Jobs deleted while waiting are left in the dependents lists.
+            let Some((qn, job)) = self.jobs.get_mut(&dependent) else {
+                continue;
+            };
+            let JobState::Waiting { prerequisites } = job.state else {
+                continue;
+            };
+
+            if prerequisites > 1 {
+                job.state = JobState::Waiting {
+                    prerequisites: prerequisites.saturating_sub(1),
+                };
+                continue;
+            }
+
+            // Panic safety: a queue must exist if any jobs reference it.
+            let queue = self.queues.get_mut(qn).unwrap();
+            queue.take(dependent, job.pri, job.state);
+            job.ready_since = now;
+            job.state = queue.put_ready_or_delayed(
+                dependent,
+                job.pri,
+                job.delay,
+                now,
+                SystemTime::now(),
+            );
+        }
+    }
+
+    /// Changes the priority of a ready, delayed, buried, or waiting job,
+    /// returning a boolean indicating success. Ready jobs are moved to their
+    /// new place in the ready queue.
+    fn set_pri(&mut self, id: JobId, pri: Pri) -> bool {
+        let Some((qn, job)) = self.jobs.get_mut(&id) else {
+            return false;
+        };
+
+        match job.state {
+            JobState::Ready { pos } => {
+                // Panic safety: a queue must exist if any jobs reference it.
+                let queue = self.queues.get_mut(qn).unwrap();
+                job.state = JobState::Ready {
+                    pos: queue.reprioritise_ready(pos, job.pri, pri),
+                };
+            },
+            JobState::Delayed { .. }
+            | JobState::Buried { .. }
+            | JobState::Waiting { .. } => {},
+            JobState::Reserved { .. } => return false,
+        }
+
+        job.pri = pri;
+        true
+    }
+
+    /// Returns statistics about a job by ID, with times in the given unit.
+    fn stats_job(&self, id: JobId, unit: TimeUnit) -> Option<JobStats> {
+        let (qn, job) = self.jobs.get(&id)?;
+        let now = Instant::now();
+
+        let time_left = match job.state {
+            JobState::Delayed { until: t, .. }
+            | JobState::Reserved { deadline: t } => {
+                t.saturating_duration_since(now)
+            },
+            JobState::Ready { .. }
+            | JobState::Buried { .. }
+            | JobState::Waiting { .. } => Duration::ZERO,
+        };
+
+        Some(JobStats {
+            id: id.0.get(),
+            tube: qn.0.clone(),
+            state: job.state,
+            pri: job.pri.into(),
+            age: unit.count(now.saturating_duration_since(job.created)),
+            delay: unit.count(job.delay),
+            ttr: unit.count(job.ttr),
+            time_left: unit.count(time_left),
+            run_at: match job.state {
+                JobState::Delayed { run_at, .. } => Some(unix_secs(run_at)),
+                _ => None,
+            },
+            file: 0,
+            original_tube: job.original_tube.clone(),
+            headers: job
+                .headers
+                .iter()
+                .map(|(k, v)| {
+                    (
+                        String::from_utf8_lossy(k).into_owned(),
+                        String::from_utf8_lossy(v).into_owned(),
+                    )
+                })
+                .collect(),
+            reserves: job.reserves,
+            timeouts: job.timeouts,
+            releases: job.releases,
+            buries: job.buries,
+            kicks: job.kicks,
+        })
+    }
+
+    /// Returns the largest job that may be put to a queue: its own maximum if
+    /// it has one, or else the server-wide maximum.
+    fn queue_max_job_size(&self, qn: &QueueName) -> u32 {
+        self.queues
+            .get(qn)
+            .and_then(|queue| queue.config.max_job_size)
+            .unwrap_or(self.max_job_size)
+    }
+
+    /// Returns statistics about a queue by name, including its settings.
+    fn stats_tube(&self, qn: &QueueName) -> Option<TubeStatsResp> {
+        let queue = self.queues.get(qn)?;
+        let pause_time_left = queue.pause_until.map_or(Duration::ZERO, |t| {
+            t.saturating_duration_since(Instant::now())
+        });
+
+        let current_watching = self
+            .watch_sets
+            .iter()
+            .filter(|(qs, _)| qs.matches(qn))
+            .fold(0u64, |n, (_, &count)| n.saturating_add(count));
+
+        Some(TubeStatsResp {
+            name: qn.0.clone(),
+            ts: TubeStats {
+                current_watching,
+                current_bytes: queue.data_bytes,
+                current_stored_bytes: queue.stored_bytes,
+                ..queue.stats.clone()
+            },
+            pause_time_left: secs_u32(pause_time_left),
+            config: queue.config.clone(),
+        })
+    }
+
+    /// Returns statistics about the server as a whole.
+    fn stats_server(&self) -> ServerStats {
+        let mut stats = ServerStats {
+            max_job_size: self.max_job_size.into(),
+            current_tubes: u64::try_from(self.queues.len()).unwrap_or(u64::MAX),
+            pid: std::process::id(),
+            version: env!("CARGO_PKG_VERSION"),
+            uptime: secs_u32(self.started.elapsed()),
+            draining: self.is_draining,
+            id: self.id.as_bytes().to_vec(),
+            os: std::env::consts::OS.as_bytes().to_vec(),
+            platform: std::env::consts::ARCH.as_bytes().to_vec(),
+            // TODO: report the hostname, resource usage and binlog once
+            // implemented.
+            ..self.stats.clone()
+        };
+
+        for queue in self.queues.values() {
+            let ts = &queue.stats;
+            for (total, n) in [
+                (&mut stats.current_jobs_urgent, ts.current_jobs_urgent),
+                (&mut stats.current_jobs_ready, ts.current_jobs_ready),
+                (&mut stats.current_jobs_reserved, ts.current_jobs_reserved),
+                (&mut stats.current_jobs_delayed, ts.current_jobs_delayed),
+                (&mut stats.current_jobs_buried, ts.current_jobs_buried),
+                (&mut stats.total_jobs, ts.total_jobs),
+            ] {
+                *total = total.saturating_add(n);
+            }
+        }
+
+        stats
+    }
+
+    /// Pauses a queue by name for the given delay, during which no jobs can be
+    /// reserved from it, returning a boolean indicating if the queue exists. A
+    /// delay of zero unpauses the queue.
+    fn pause(&mut self, qn: &QueueName, delay: Duration) -> bool {
+        let Some(queue) = self.queues.get_mut(qn) else {
+            return false;
+        };
+
+        queue.pause_until = Instant::now()
+            .checked_add(delay)
+            .filter(|_| !delay.is_zero());
+        queue.stats.pause = secs_u32(delay);
+        queue.stats.cmd_pause_tube =
+            queue.stats.cmd_pause_tube.saturating_add(1);
+
+        true
+    }
+
+    /// Returns the set of queues watched by a newly connected client, which
+    /// watches only the default queue, and counts the client as watching it.
+    fn new_watch_set(&mut self) -> QueueSet {
+        let qs = QueueSet {
+            patterns: vec![QueuePattern(Self::DEFAULT_QUEUE.to_vec())],
+            mode: ReserveMode::default(),
+        };
+        self.queues
+            .entry(QueueName(Self::DEFAULT_QUEUE.to_vec()))
+            .or_insert_with(TubeState::new);
+        self.count_watch_set(&qs, true);
+
+        qs
+    }
+
+    /// Adds a queue name or pattern to the end of a client's set of watched
+    /// queues, unless already present, returning the number of names and
+    /// patterns in the set.
+    fn watch(&mut self, qs: &mut QueueSet, pattern: QueuePattern) -> usize {
+        if !qs.patterns.contains(&pattern) {
+            self.count_watch_set(qs, false);
+            qs.patterns.push(pattern);
+            self.count_watch_set(qs, true);
+        }
+
+        qs.patterns.len()
+    }
+
+    /// Removes a queue name or pattern from a client's set of watched queues,
+    /// returning the number of names and patterns left in the set, or `None`
+    /// without changing it if that would leave the set empty. Queues matched
+    /// by any of the set's other names or patterns are still watched.
+    fn ignore(
+        &mut self,
+        qs: &mut QueueSet,
+        pattern: &QueuePattern,
+    ) -> Option<usize> {
+        if qs.patterns == [pattern.clone()] {
+            return None;
+        }
+
+        self.count_watch_set(qs, false);
+        qs.patterns.retain(|p| p != pattern);
+        self.count_watch_set(qs, true);
+
+        Some(qs.patterns.len())
+    }
+
+    /// Changes how a client's `reserve` chooses between the queues it watches.
+    fn set_reserve_mode(&mut self, qs: &mut QueueSet, mode: ReserveMode) {
+        self.count_watch_set(qs, false);
+        qs.mode = mode;
+        self.count_watch_set(qs, true);
+    }
+
+    /// Stops counting a disconnected client as watching its set of queues.
+    fn forget_watch_set(&mut self, qs: &QueueSet) {
+        self.count_watch_set(qs, false);
+    }
+
+    /// Counts one more or one fewer client as watching the set of queues.
+    fn count_watch_set(&mut self, qs: &QueueSet, add: bool) {
+        let count = self.watch_sets.entry(qs.clone()).or_default();
+        *count = if add {
+            count.saturating_add(1)
+        } else {
+            count.saturating_sub(1)
+        };
+        if *count == 0 {
+            self.watch_sets.remove(qs);
+        }
+    }
+
+    /// Moves a ready, delayed, buried, or waiting job to another queue,
+    /// creating it if required, and returning a boolean indicating success.
+    /// The job keeps its ID, priority, remaining delay, prerequisites, expiry
+    /// time, and statistics, but joins the back of the ready or buried queue
+    /// it moves to. The destination's job and byte quotas aren't checked, so
+    /// that moving a job never fails for lack of room.
+    fn move_job(&mut self, id: JobId, to: QueueName) -> bool {
+        let Some((qn, job)) = self.jobs.get_mut(&id) else {
+            return false;
+        };
+
+        if matches!(job.state, JobState::Reserved { .. }) {
+            return false;
+        }
+        if *qn == to {
+            return true;
+        }
+
+        // Panic safety: a queue must exist if any jobs reference it.
+        let from = self.queues.get_mut(qn).unwrap();
+        from.take(id, job.pri, job.state);
+        from.take_data(&job.data);
+        if let Some(expires) = job.expires {
+            from.take_expiring(id, expires);
+        }
+
+        let dest = self.queues.entry(to.clone()).or_insert_with(TubeState::new);
+        dest.add_data(&job.data);
+        if let Some(expires) = job.expires {
+            dest.put_expiring(id, expires);
+        }
+        job.state = match job.state {
+            JobState::Ready { .. } => {
+                job.ready_since = Instant::now();
+                JobState::Ready {
+                    pos: dest.put_ready(id, job.pri, job.ready_since),
+                }
+            },
+            JobState::Delayed { until, run_at } => {
+                dest.put_delayed(id, until);
+                JobState::Delayed { until, run_at }
+            },
+            JobState::Buried { .. } => JobState::Buried {
+                pos: dest.put_buried(id),
+            },
+            JobState::Waiting { prerequisites } => {
+                dest.put_waiting();
+                JobState::Waiting { prerequisites }
+            },
+            JobState::Reserved { .. } => unreachable!(),
+        };
+        *qn = to;
+
+        true
+    }
+
+    /// Moves up to `bound` jobs from one queue to another, taking ready jobs
+    /// in queue order, then delayed jobs, then buried jobs. Returns the number
+    /// of jobs moved.
+    fn move_jobs(
+        &mut self,
+        from: &QueueName,
+        to: &QueueName,
+        bound: u64,
+    ) -> u64 {
+        let Some(queue) = self.queues.get(from) else {
+            return 0;
+        };
+        if from == to {
+            return 0;
+        }
+
+        let ids: Vec<JobId> = queue
+            .ready
+            .values()
+            .copied()
+            .chain(queue.delayed.iter().map(|&(_, id)| id))
+            .chain(queue.buried.values().copied())
+            .take(usize::try_from(bound).unwrap_or(usize::MAX))
+            .collect();
+
+        let mut moved = 0u64;
+        for id in ids {
+            if self.move_job(id, to.clone()) {
+                moved = moved.saturating_add(1);
+            }
+        }
+
+        moved
+    }
+
+    /// Reserves a job by ID, returning its contents. Fails if the job isn't
+    /// ready, or its queue is at its limit of reserved jobs.
+    fn reserve_by_id(&mut self, id: JobId) -> Option<&Job> {
+        let (qn, job) = self.jobs.get_mut(&id)?;
+
+        let JobState::Ready { pos } = job.state else {
+            return None;
+        };
+
+        // Panic safety: a queue must exist if any jobs reference it, so this
+        // should be safe if correctly implemented.
+        let queue = self.queues.get_mut(qn).unwrap();
+        if queue.is_at_max_reserved() {
+            return None;
+        }
+
+        queue.take_ready(job.pri, pos);
+        if let Some(expires) = job.expires.take() {
+            queue.take_expiring(id, expires);
+        }
+        job.state = JobState::Reserved {
+            deadline: queue.put_reserved(id, job.ttr, Instant::now()),
+        };
+        job.reserves = job.reserves.saturating_add(1);
+        queue.stats.total_reserves =
+            queue.stats.total_reserves.saturating_add(1);
+
+        Some(job)
+    }
+
+    /// Releases a reserved job by ID with a new priority and delay, returning
+    /// a boolean indicating success. The job is dead-lettered instead if this
+    /// takes it to its queue's release limit.
+    fn release(&mut self, id: JobId, pri: Pri, delay: u32) -> bool {
+        self.release_timed(id, pri, from_secs(delay))
+    }
+
+    /// As [`Server::release`], but with the delay as a duration, for clients
+    /// giving times in milliseconds.
+    fn release_timed(&mut self, id: JobId, pri: Pri, delay: Duration) -> bool {
+        let Some((qn, job)) = self.jobs.get_mut(&id) else {
+            return false;
+        };
+        let JobState::Reserved { deadline } = job.state else {
+            return false;
+        };
+
+        // Panic safety: a queue must exist if any jobs reference it.
+        let queue = self.queues.get_mut(qn).unwrap();
+        queue.take_reserved(id, deadline);
+
+        job.pri = pri;
+        job.delay = delay;
+        job.releases = job.releases.saturating_add(1);
+
+        if queue.config.dead_letters(job) {
+            self.dead_letter(id);
+        } else {
+            job.ready_since = Instant::now();
+            job.state = queue.put_ready_or_delayed(
+                id,
+                pri,
+                delay,
+                job.ready_since,
+                SystemTime::now(),
+            );
+        }
+
+        true
+    }
+
+    /// Returns a disconnected client's reserved jobs to their ready queues,
+    /// with their current priority and no delay. Like a timeout, this doesn't
+    /// count as a release. Returns the number of jobs returned.
+    fn release_disconnected(&mut self, ids: &[JobId]) -> usize {
+        let now = Instant::now();
+        let mut released = 0usize;
+
+        for &id in ids {
+            let Some((qn, job)) = self.jobs.get_mut(&id) else {
+                continue;
+            };
+            let JobState::Reserved { deadline } = job.state else {
+                continue;
+            };
+
+            // Panic safety: a queue must exist if any jobs reference it.
+            let queue = self.queues.get_mut(qn).unwrap();
+            queue.take_reserved(id, deadline);
+            job.ready_since = now;
+            job.state = JobState::Ready {
+                pos: queue.put_ready(id, job.pri, now),
+            };
+            released = released.saturating_add(1);
+        }
+
+        released
+    }
+
+    /// Buries a job that's been taken out of its queue, in the dead-letter
+    /// queue configured for its queue, or otherwise in its own queue. As for
+    /// [`Server::move_job`], the dead-letter queue's quotas aren't checked.
+    fn dead_letter(&mut self, id: JobId) {
+        let Some((qn, job)) = self.jobs.get_mut(&id) else {
+            return;
+        };
+
+        let dlq = self
+            .queues
+            .get(qn)
+            .and_then(|q| q.config.dead_letter_tube.clone())
+            .map(QueueName);
+        if let Some(dlq) = dlq.filter(|dlq| dlq != qn) {
+            // Panic safety: a queue must exist if any jobs reference it.
+            let from = self.queues.get_mut(qn).unwrap();
+            from.take_data(&job.data);
+
+            let dest = self
+                .queues
+                .entry(dlq.clone())
+                .or_insert_with(TubeState::new);
+            dest.add_data(&job.data);
+
+            job.original_tube = Some(std::mem::replace(qn, dlq).0);
+        }
+
+        let queue =
+            self.queues.entry(qn.clone()).or_insert_with(TubeState::new);
+        job.state = JobState::Buried {
+            pos: queue.put_buried(id),
+        };
+    }
+
+    /// Reserves the highest-priority ready job from the provided QueueSet,
+    /// ignoring paused queues and those at their rate or reserved job limits.
+    ///
+    /// Stochastic fairness is supported: when scanning for the highest-priority
+    /// job in the queueset, the provided [BuildHasher] is used to randomise
+    /// which queue wins.
+    ///
+    /// If any of the queues with ready jobs has a weight, the queue is instead
+    /// chosen at random in proportion to the queues' weights, counting queues
+    /// without one as having a weight of 1, and its highest-priority job is
+    /// reserved. The [`BuildHasher`] drives the random choice, so a hasher with
+    /// fixed keys makes the sequence of choices deterministic.
+    fn reserve_by_queue(
+        &mut self,
+        qs: QueueSet,
+        h: impl BuildHasher,
+    ) -> Option<&Job> {
+        let id = self.reserve_next(&qs, &h)?;
+
+        self.jobs.get(&id).map(|(_, job)| job)
+    }
+
+    /// Reserves up to `count` jobs from the provided [`QueueSet`], choosing
+    /// each as [`Server::reserve_by_queue`] does, and returns their IDs in the
+    /// order they were reserved.
+    fn reserve_batch(
+        &mut self,
+        qs: &QueueSet,
+        count: u32,
+        h: &impl BuildHasher,
+    ) -> Vec<JobId> {
+        let mut ids = Vec::new();
+        for _ in 0..count {
+            let Some(id) = self.reserve_next(qs, h) else {
+                break;
+            };
+            ids.push(id);
+        }
+
+        ids
+    }
+
+    /// Reserves the job chosen by [`Server::reserve_by_queue`], returning its
+    /// ID. In the ordered reserve mode, the queues matched by each of the
+    /// set's names and patterns are tried in turn, choosing between the
+    /// queues matched by a pattern as usual.
+    fn reserve_next(
+        &mut self,
+        qs: &QueueSet,
+        h: &impl BuildHasher,
+    ) -> Option<JobId> {
+        match qs.mode {
+            ReserveMode::Priority => {
+                self.reserve_from(&qs.resolve(&self.queues), h)
+            },
+            ReserveMode::Ordered => qs
+                .resolve_each(&self.queues)
+                .iter()
+                .find_map(|names| self.reserve_from(names, h)),
+        }
+    }
+
+    /// Reserves the highest-priority ready job from the named queues, or one
+    /// chosen by weight, as described for [`Server::reserve_by_queue`].
+    fn reserve_from(
+        &mut self,
+        names: &BTreeSet<QueueName>,
+        h: &impl BuildHasher,
+    ) -> Option<JobId> {
+        let now = Instant::now();
+        let mut best: Option<((u64, u64), &QueueName, JobId)> = None;
+        let mut weighted: Vec<(&QueueName, JobId, u64)> = Vec::new();
+        let mut any_weight = false;
+
+        for qn in names {
+            let Some(queue) = self.queues.get_mut(qn) else {
+                continue;
+            };
+            let Some((pri, id)) = queue.peek_ready_pri() else {
+                continue;
+            };
+            if queue.is_paused(now)
+                || queue.is_at_max_reserved()
+                || queue.throttled_until(now).is_some()
+            {
+                continue;
+            }
+
+            let key = (pri, h.hash_one(qn));
+            if best.is_none_or(|(best_key, _, _)| key < best_key) {
+                best = Some((key, qn, id));
+            }
+
+            any_weight |= queue.config.weight.is_some();
+            weighted.push((qn, id, queue.config.weight.map_or(1, u64::from)));
+        }
+
+        let (qn, id) = if any_weight {
+            self.choose_weighted(&weighted, h)?
+        } else {
+            let (_, qn, id) = best?;
+            (qn, id)
+        };
+        // Panic safety: the queue was found in the loop above.
+        self.queues.get_mut(qn).unwrap().take_reserve_token(now);
+        self.reserve_by_id(id)?;
+
+        Some(id)
+    }
+
+    /// Chooses one of the given queues and its job at random, in proportion to
+    /// the given weights, using the hasher as a source of randomness.
+    fn choose_weighted<'a>(
+        &mut self,
+        choices: &[(&'a QueueName, JobId, u64)],
+        h: &impl BuildHasher,
+    ) -> Option<(&'a QueueName, JobId)> {
+        let total = choices
+            .iter()
+            .fold(0u64, |total, &(_, _, weight)| total.saturating_add(weight));
+        let mut draw = h.hash_one(self.weighted_draws).checked_rem(total)?;
+        self.weighted_draws = self.weighted_draws.wrapping_add(1);
+
+        for &(qn, id, weight) in choices {
+            if draw < weight {
+                return Some((qn, id));
+            }
+            draw = draw.saturating_sub(weight);
+        }
+
+        None
+    }
+
+    /// Counts a reserve as held back by the rate limit of each queue in the
+    /// provided [`QueueSet`] that has ready jobs but is at its limit. This
+    /// should be run once for each reserve that found no job.
+    fn count_throttled(&mut self, qs: &QueueSet) {
+        let now = Instant::now();
+
+        for qn in qs.resolve(&self.queues) {
+            let Some(queue) = self.queues.get_mut(&qn) else {
+                continue;
+            };
+            if !queue.ready.is_empty()
+                && !queue.is_paused(now)
+                && queue.throttled_until(now).is_some()
+            {
+                queue.stats.reserves_throttled =
+                    queue.stats.reserves_throttled.saturating_add(1);
+            }
+        }
+    }
+
+    /// Returns the earliest time at which a queue in the provided [`QueueSet`]
+    /// that has ready jobs, but is at its rate limit, can next be reserved
+    /// from. Clients waiting to reserve from the set should retry then.
+    fn throttled_until(&self, qs: &QueueSet) -> Option<Instant> {
+        let now = Instant::now();
+
+        qs.resolve(&self.queues)
+            .iter()
+            .filter_map(|qn| self.queues.get(qn))
+            .filter(|q| !q.ready.is_empty() && !q.is_paused(now))
+            .filter_map(|q| q.throttled_until(now))
+            .min()
+    }
+
+    /// Buries a reserved job by ID with a new priority, returning a boolean
+    /// indicating if this occurred. The job is moved to its queue's dead-letter
+    /// queue if this takes it to the bury limit.
+    fn bury(&mut self, id: JobId, pri: Pri) -> bool {
+        let Some((qn, job)) = self.jobs.get_mut(&id) else {
+            return false;
+        };
+        let JobState::Reserved { deadline } = job.state else {
+            return false;
+        };
+
+        // Panic safety: a queue must exist if any jobs reference it.
+        let queue = self.queues.get_mut(qn).unwrap();
+        queue.take_reserved(id, deadline);
+
+        job.pri = pri;
+        job.buries = job.buries.saturating_add(1);
+
+        if queue.config.dead_letters(job) {
+            self.dead_letter(id);
+        } else {
+            job.state = JobState::Buried {
+                pos: queue.put_buried(id),
+            };
+        }
+
+        true
+    }
+
+    /// Kicks a buried or delayed job by ID into its ready queue, returning a
+    /// boolean indicating if this occurred.
+    fn kick(&mut self, id: JobId) -> bool {
+        let Some((qn, job)) = self.jobs.get_mut(&id) else {
+            return false;
+        };
+
+        // Panic safety: a queue must exist if any jobs reference it.
+        let queue = self.queues.get_mut(qn).unwrap();
+        match job.state {
+            JobState::Buried { pos } => queue.take_buried(&pos),
+            JobState::Delayed { until, .. } => queue.take_delayed(id, until),
+            _ => return false,
+        }
+
+        job.kicks = job.kicks.saturating_add(1);
+        job.ready_since = Instant::now();
+        job.state = JobState::Ready {
+            pos: queue.put_ready(id, job.pri, job.ready_since),
+        };
+
+        true
+    }
+
+    /// Kicks up to `bound` jobs from the named queue, returning the number
+    /// kicked. Buried jobs are kicked, oldest first, unless there are none, in
+    /// which case delayed jobs are kicked in the order they'd become ready.
+    fn kick_jobs(&mut self, qn: &QueueName, bound: u64) -> u64 {
+        let Some(queue) = self.queues.get(qn) else {
+            return 0;
+        };
+
+        let ids: Vec<JobId> = if queue.buried.is_empty() {
+            queue.delayed.iter().map(|&(_, id)| id).collect()
+        } else {
+            queue.buried.values().copied().collect()
+        };
+
+        let mut kicked = 0u64;
+        for id in ids
+            .into_iter()
+            .take(usize::try_from(bound).unwrap_or(usize::MAX))
+        {
+            if self.kick(id) {
+                kicked = kicked.saturating_add(1);
+            }
+        }
+
+        kicked
+    }
+
+    /// Refreshes a reserved job's TTR, returning its new deadline, or `None` if
+    /// it isn't reserved.
+    fn touch(&mut self, id: JobId) -> Option<Instant> {
+        let (qn, job) = self.jobs.get_mut(&id)?;
+        let JobState::Reserved { deadline } = job.state else {
+            return None;
+        };
+
+        // Panic safety: a queue must exist if any jobs reference it.
+        let queue = self.queues.get_mut(qn).unwrap();
+        queue.take_reserved(id, deadline);
+        let deadline = queue.put_reserved(id, job.ttr, Instant::now());
+        job.state = JobState::Reserved { deadline };
+
+        Some(deadline)
+    }
+
+    /// Returns any reserved jobs that have passed their deadline to the front
+    /// or back of the ready queue, as configured for their queue or the server,
+    /// or dead-letters them if they've reached their queue's timeout limit.
+    fn handle_timed_out_jobs(&mut self) {
+        let now = Instant::now();
+
+        let timed_out: Vec<JobId> = self
+            .queues
+            .values()
+            .flat_map(|q| {
+                q.reserved
+                    .iter()
+                    .take_while(|&&(deadline, _)| deadline <= now)
+                    .map(|&(_, id)| id)
+            })
+            .collect();
+
+        for id in timed_out {
+            // Panic safety: reserved jobs must exist.
+            let (qn, job) = self.jobs.get_mut(&id).unwrap();
+            let JobState::Reserved { deadline } = job.state else {
+                continue;
+            };
+
+            // Panic safety: a queue must exist if any jobs reference it.
+            let queue = self.queues.get_mut(qn).unwrap();
+            queue.take_reserved(id, deadline);
+            job.timeouts = job.timeouts.saturating_add(1);
+            self.stats.job_timeouts = self.stats.job_timeouts.saturating_add(1);
+
+            if queue.config.dead_letters(job) {
+                self.dead_letter(id);
+                continue;
+            }
+
+            let requeue =
+                queue.config.timeout_requeue.unwrap_or(self.timeout_requeue);
+            // Jobs requeued at the front keep the aging they'd accrued.
+            let pos = match requeue {
+                RequeuePos::Front => {
+                    queue.put_ready_front(id, job.pri, job.ready_since)
+                },
+                RequeuePos::Back => {
+                    job.ready_since = now;
+                    queue.put_ready(id, job.pri, now)
+                },
+            };
+            job.state = JobState::Ready { pos };
+        }
+    }
+
+    /// Expires any jobs that have passed their expiry time without being
+    /// reserved, whether ready, delayed, or waiting. Each is moved to the back
+    /// of the ready queue of its queue's expired-jobs queue, if configured,
+    /// regardless of that queue's quotas, or otherwise deleted. Jobs in paused
+    /// queues don't expire until the queue is unpaused.
+    fn handle_expired_jobs(&mut self) {
+        let now = Instant::now();
+
+        let due: Vec<JobId> = self
+            .queues
+            .values()
+            .filter(|q| !q.is_paused(now))
+            .flat_map(|q| {
+                q.expiring
+                    .iter()
+                    .take_while(|&&(expires, _)| expires <= now)
+                    .map(|&(_, id)| id)
+            })
+            .collect();
+
+        for id in due {
+            // Panic safety: jobs with an expiry time must exist.
+            let (qn, job) = self.jobs.get_mut(&id).unwrap();
+            let Some(expires) = job.expires.take() else {
+                continue;
+            };
+
+            // Panic safety: a queue must exist if any jobs reference it.
+            let queue = self.queues.get_mut(qn).unwrap();
+            queue.take_expiring(id, expires);
+            queue.take(id, job.pri, job.state);
+            queue.stats.total_jobs_expired =
+                queue.stats.total_jobs_expired.saturating_add(1);
+
+            queue.take_data(&job.data);
+
+            let expired_tube = queue
+                .config
+                .expired_tube
+                .clone()
+                .map(QueueName)
+                .filter(|to| to != qn);
+            if let Some(to) = expired_tube {
+                let dest = self
+                    .queues
+                    .entry(to.clone())
+                    .or_insert_with(TubeState::new);
+                dest.add_data(&job.data);
+                job.ready_since = now;
+                job.state = JobState::Ready {
+                    pos: dest.put_ready(id, job.pri, now),
+                };
+                *qn = to;
+            } else {
+                self.jobs.remove(&id);
+                self.release_dependents(id);
+            }
+        }
+    }
+
+    /// Pushes any delayed jobs that have become ready into the ready queue.
+    fn handle_delayed_jobs(&mut self) {
+        let now = Instant::now();
+
+        let due: Vec<JobId> = self
+            .queues
+            .values()
+            .flat_map(|q| {
+                q.delayed
+                    .iter()
+                    .take_while(|&&(until, _)| until <= now)
+                    .map(|&(_, id)| id)
+            })
+            .collect();
+
+        for id in due {
+            // Panic safety: delayed jobs must exist.
+            let (qn, job) = self.jobs.get_mut(&id).unwrap();
+            let JobState::Delayed { until, .. } = job.state else {
+                continue;
+            };
+
+            // Panic safety: a queue must exist if any jobs reference it.
+            let queue = self.queues.get_mut(qn).unwrap();
+            queue.take_delayed(id, until);
+            job.ready_since = now;
+            job.state = JobState::Ready {
+                pos: queue.put_ready(id, job.pri, now),
+            };
+        }
+    }
+
+    /// Returns the earliest time at which a delayed job becomes ready, a
+    /// reserved job times out, a job expires, a paused queue is unpaused, or a
+    /// recurring job is due, if there is one. [`Server::tick`] should be run at
+    /// that time.
+    fn next_deadline(&self, now: SystemTime) -> Option<Instant> {
+        let instant_now = Instant::now();
+        let queue_deadlines = self.queues.values().flat_map(|q| {
+            // Jobs in paused queues don't expire until the queue is unpaused.
+            let expires = q.expiring.first().map(|&(expires, _)| {
+                q.pause_until.map_or(expires, |until| expires.max(until))
+            });
+            [
+                q.delayed.first().map(|&(until, _)| until),
+                q.reserved.first().map(|&(deadline, _)| deadline),
+                expires,
+                q.pause_until.filter(|&until| until > instant_now),
+            ]
+        });
+
+        let recurring = self
+            .recurring
+            .values()
+            .filter_map(|r| r.next_run)
+            .min()
+            .and_then(|next_run| {
+                let at =
+                    UNIX_EPOCH.checked_add(Duration::from_secs(next_run))?;
+                let wait = at.duration_since(now).unwrap_or_default();
+                Instant::now().checked_add(wait)
+            });
+
+        queue_deadlines.chain([recurring]).flatten().min()
+    }
+
+    /// Puts any recurring jobs that are due, then makes ready any delayed jobs,
+    /// times out any reserved jobs, and expires any jobs whose times have
+    /// passed.
+    fn tick(&mut self, now: SystemTime) {
+        self.handle_recurring_jobs(now);
+        self.handle_delayed_jobs();
+        self.handle_timed_out_jobs();
+        self.handle_expired_jobs();
+    }
+}
+
+/// Converts a wall-clock time to whole seconds since the Unix epoch, or zero if
+/// it's earlier than that.
+fn unix_secs(t: SystemTime) -> u64 {
+    t.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+/// Converts a whole number of seconds to a duration.
+fn from_secs(n: u32) -> Duration {
+    Duration::from_secs(n.into())
+}
+
+/// Converts a duration to whole seconds, saturating at `u32::MAX`.
+fn secs_u32(d: Duration) -> u32 {
+    u32::try_from(d.as_secs()).unwrap_or(u32::MAX)
+}
+
+/// Returns the ID reported for a put, that of the first job it created.
+fn first_id(ids: &[JobId]) -> JobId {
+    // Panic safety: puts create at least one job when they succeed.
+    *ids.first().unwrap()
+}
+
+/// Returns the size of a job's data in bytes.
+fn data_len(data: &[u8]) -> u64 {
+    u64::try_from(data.len()).unwrap_or(u64::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::hash::{BuildHasherDefault, DefaultHasher, RandomState};
+
+    use futures::FutureExt;
+
+    use super::*;
+
+    fn secs(n: u64) -> Duration {
+        Duration::from_secs(n)
+    }
+
+    fn queue_name(name: &str) -> QueueName {
+        QueueName(name.into())
+    }
+
+    #[test]
+    fn test_set_pri() {
+        let mut s = Server::new("test");
+        let qn = queue_name("default");
+
+        let a = s.put(qn.clone(), 2000.into(), 0, 60, b"a".into()).unwrap();
+        let b = s.put(qn.clone(), 2000.into(), 0, 60, b"b".into()).unwrap();
+        let c = s.put(qn.clone(), 2000.into(), 0, 60, b"c".into()).unwrap();
+        let delayed =
+            s.put(qn.clone(), 2000.into(), 60, 60, b"d".into()).unwrap();
+
+        let queue = &s.queues[&qn];
+        assert_eq!(queue.peek_ready(), Some(a));
+        assert_eq!(queue.stats.current_jobs_urgent, 0);
+
+        // Promote the last job into the urgent range.
+        assert!(s.set_pri(c, 10.into()));
+        let queue = &s.queues[&qn];
+        assert_eq!(queue.peek_ready(), Some(c));
+        assert_eq!(queue.stats.current_jobs_urgent, 1);
+        assert_eq!(queue.stats.current_jobs_ready, 3);
+        assert_eq!(s.stats_job(c, TimeUnit::Seconds).unwrap().pri, 10);
+
+        // Demote it again: it returns behind a and b, as it was put last.
+        assert!(s.set_pri(c, 5000.into()));
+        let queue = &s.queues[&qn];
+        assert_eq!(queue.peek_ready(), Some(a));
+        assert_eq!(queue.stats.current_jobs_urgent, 0);
+        assert!(s.set_pri(a, 2001.into()));
+        assert_eq!(s.queues[&qn].peek_ready(), Some(b));
+
+        // Delayed jobs don't count towards the urgent total until ready.
+        assert!(s.set_pri(delayed, 0.into()));
+        assert_eq!(s.queues[&qn].stats.current_jobs_urgent, 0);
+        assert_eq!(s.stats_job(delayed, TimeUnit::Seconds).unwrap().pri, 0);
+
+        assert!(!s.set_pri(JobId(NonZeroU64::MAX), 0.into()));
+    }
+
+    #[test]
+    fn test_put_unique() {
+        let mut s = Server::new("test");
+        let qn = queue_name("default");
+        let other = queue_name("other");
+
+        let a = s
+            .put_unique(
+                qn.clone(),
+                b"k".into(),
+                0.into(),
+                secs(0),
+                secs(60),
+                b"a".into(),
+            )
+            .unwrap();
+        let b = s
+            .put_unique(
+                qn.clone(),
+                b"k".into(),
+                0.into(),
+                secs(0),
+                secs(60),
+                b"b".into(),
+            )
+            .unwrap();
+        assert_eq!(a, b);
+        assert_eq!(s.jobs.len(), 1);
+        assert_eq!(s.queues[&qn].stats.total_jobs, 1);
+
+        // Keys are scoped to a queue.
+        let c = s
+            .put_unique(
+                other.clone(),
+                b"k".into(),
+                0.into(),
+                secs(0),
+                secs(60),
+                b"c".into(),
+            )
+            .unwrap();
+        assert_ne!(a, c);
+
+        // Keys outlive the job they refer to, until the window passes.
+        s.jobs.remove(&a);
+        assert_eq!(
+            s.put_unique(
+                qn.clone(),
+                b"k".into(),
+                0.into(),
+                secs(0),
+                secs(60),
+                b"d".into()
+            ),
+            Ok(a)
+        );
+
+        s.apply_config(&Config::from_yaml("dedup-window: 0").unwrap());
+        let first = s
+            .put_unique(
+                qn.clone(),
+                b"j".into(),
+                0.into(),
+                secs(0),
+                secs(60),
+                b"e".into(),
+            )
+            .unwrap();
+        let second = s
+            .put_unique(
+                qn.clone(),
+                b"j".into(),
+                0.into(),
+                secs(0),
+                secs(60),
+                b"f".into(),
+            )
+            .unwrap();
+        assert_ne!(first, second);
+        assert_eq!(s.dedup_keys[&(qn, b"j".into())], second);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_dedup_window() {
+        let mut s = Server::new("test");
+        let qn = queue_name("default");
+        s.apply_config(&Config::from_yaml("dedup-window: 10").unwrap());
+
+        let mut put = |data: &[u8]| {
+            s.put_unique(
+                qn.clone(),
+                b"k".into(),
+                0.into(),
+                secs(0),
+                secs(60),
+                data.into(),
+            )
+            .unwrap()
+        };
+        let first = put(b"a");
+        tokio::time::advance(secs(9)).await;
+        assert_eq!(put(b"b"), first);
+
+        // The window runs from the first put, not the latest.
+        tokio::time::advance(secs(1)).await;
+        assert_ne!(put(b"c"), first);
+    }
+
+    #[test]
+    fn test_put_with_headers() {
+        let mut s = Server::new("test");
+        let qn = queue_name("default");
+        let headers = Headers::from([(b"trace-id".to_vec(), b"abc".to_vec())]);
+
+        let id = s
+            .put_with_headers(
+                qn.clone(),
+                &headers,
+                0.into(),
+                secs(0),
+                secs(60),
+                b"a".into(),
+            )
+            .unwrap();
+        let stats = s.stats_job(id, TimeUnit::Seconds).unwrap();
+        assert_eq!(stats.headers["trace-id"], "abc");
+        assert_eq!(s.reserve_by_id(id).unwrap().headers.len(), 1);
+
+        // Jobs put without headers have none.
+        let id = s.put(qn, 0.into(), 0, 60, b"b".into()).unwrap();
+        assert!(
+            s.stats_job(id, TimeUnit::Seconds)
+                .unwrap()
+                .headers
+                .is_empty()
+        );
+        assert!(s.jobs[&id].1.headers.is_empty());
+    }
+
+    #[test]
+    fn test_put_after() {
+        let mut s = Server::new("test");
+        let qn = queue_name("default");
+
+        let a = s.put(qn.clone(), 0.into(), 0, 60, b"a".into()).unwrap();
+        let b = s.put(qn.clone(), 0.into(), 0, 60, b"b".into()).unwrap();
+        let unknown = JobId(NonZeroU64::MAX);
+
+        // Unknown prerequisites are rejected without creating a job.
+        assert_eq!(
+            s.put_after(
+                qn.clone(),
+                &[a, unknown],
+                0.into(),
+                secs(0),
+                secs(60),
+                b"".into()
+            ),
+            Err(PutError::NotFound)
+        );
+        assert_eq!(s.jobs.len(), 2);
+
+        let after_ab = s
+            .put_after(
+                qn.clone(),
+                &[a, b, a],
+                0.into(),
+                secs(0),
+                secs(60),
+                b"after_ab".into(),
+            )
+            .unwrap();
+        let after_c = s
+            .put_after(
+                qn.clone(),
+                &[after_ab],
+                0.into(),
+                secs(0),
+                secs(60),
+                b"after_c".into(),
+            )
+            .unwrap();
+        assert_eq!(
+            s.stats_job(after_ab, TimeUnit::Seconds).unwrap().state,
+            JobState::Waiting { prerequisites: 2 }
+        );
+        assert_eq!(s.queues[&qn].stats.current_jobs_waiting, 2);
+        assert_eq!(s.queues[&qn].stats.current_jobs_ready, 2);
+
+        assert!(s.delete(a));
+        assert!(matches!(
+            s.jobs[&after_ab].1.state,
+            JobState::Waiting { .. }
+        ));
+
+        assert!(s.delete(b));
+        assert!(matches!(s.jobs[&after_ab].1.state, JobState::Ready { .. }));
+        assert!(matches!(s.jobs[&after_c].1.state, JobState::Waiting { .. }));
+        assert_eq!(s.queues[&qn].peek_ready(), Some(after_ab));
+        assert_eq!(s.queues[&qn].stats.current_jobs_waiting, 1);
+
+        // Deleting a waiting job leaves nothing behind.
+        assert!(s.delete(after_c));
+        assert!(s.delete(after_ab));
+        assert_eq!(s.queues[&qn].stats.current_jobs_waiting, 0);
+        assert_eq!(s.queues[&qn].stats.current_jobs_ready, 0);
+        assert!(s.dependents.is_empty());
+    }
+
+    #[test]
+    fn test_recurring_jobs() {
+        let mut s = Server::new("test");
+        let qn = queue_name("default");
+        let at = |secs| UNIX_EPOCH + Duration::from_secs(secs);
+
+        let every_minute = Cron::parse(b"* * * * *").unwrap();
+        s.schedule(
+            b"tick".into(),
+            RecurringJob::new(
+                qn.clone(),
+                5.into(),
+                secs(60),
+                b"tick".into(),
+                every_minute,
+                at(30),
+            ),
+        )
+        .unwrap();
+        assert_eq!(s.list_schedules(TimeUnit::Seconds)[0].next_run, Some(60));
+
+        s.handle_recurring_jobs(at(59));
+        assert!(s.jobs.is_empty());
+
+        s.handle_recurring_jobs(at(60));
+        assert_eq!(s.jobs.len(), 1);
+        assert_eq!(s.list_schedules(TimeUnit::Seconds)[0].next_run, Some(120));
+
+        // Several missed runs only put one job.
+        s.handle_recurring_jobs(at(600));
+        assert_eq!(s.jobs.len(), 2);
+        assert_eq!(s.list_schedules(TimeUnit::Seconds)[0].next_run, Some(660));
+        s.handle_recurring_jobs(at(659));
+        assert_eq!(s.jobs.len(), 2);
+
+        let (job_qn, job) = s.jobs.values().next().unwrap();
+        assert_eq!(*job_qn, qn);
+        assert_eq!(
+            (job.pri, job.ttr, &job.data.get()[..]),
+            (5.into(), secs(60), &b"tick"[..])
+        );
+
+        assert!(s.unschedule(b"tick"));
+        assert!(!s.unschedule(b"tick"));
+        s.handle_recurring_jobs(at(6000));
+        assert_eq!(s.jobs.len(), 2);
+
+        // Templates larger than their queue allows are refused.
+        s.configure_tube(qn.clone(), TubeSetting::MaxJobSize(Some(2)));
+        let template = RecurringJob::new(
+            qn.clone(),
+            0.into(),
+            secs(60),
+            b"tock".into(),
+            Cron::parse(b"* * * * *").unwrap(),
+            at(6000),
+        );
+        assert_eq!(
+            s.schedule(b"tock".into(), template),
+            Err(PutError::JobTooBig)
+        );
+        assert!(s.list_schedules(TimeUnit::Seconds).is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_timeout_requeue() {
+        let mut s = Server::new("test");
+        let qn = queue_name("default");
+        let ready = |s: &Server| -> Vec<JobId> {
+            s.queues[&qn].ready.values().copied().collect()
+        };
+
+        let a = s.put(qn.clone(), 0.into(), 0, 1, b"a".into()).unwrap();
+        let b = s.put(qn.clone(), 0.into(), 0, 1, b"b".into()).unwrap();
+        let c = s.put(qn.clone(), 0.into(), 0, 1, b"c".into()).unwrap();
+        let lower = s.put(qn.clone(), 1.into(), 0, 1, b"l".into()).unwrap();
+
+        // By default, timed out jobs go to the back.
+        assert!(s.reserve_by_id(a).is_some());
+        tokio::time::advance(Duration::from_secs(1)).await;
+        s.handle_timed_out_jobs();
+        assert_eq!(ready(&s), [b, c, a, lower]);
+
+        // Set to the front, they go ahead of other jobs of the same priority
+        // only, with the last to time out first.
+        s.configure_tube(
+            qn.clone(),
+            TubeSetting::TimeoutRequeue(Some(RequeuePos::Front)),
+        );
+        assert!(s.reserve_by_id(a).is_some());
+        assert!(s.reserve_by_id(lower).is_some());
+        tokio::time::advance(Duration::from_secs(1)).await;
+        s.handle_timed_out_jobs();
+        assert_eq!(ready(&s), [a, b, c, lower]);
+        assert!(s.reserve_by_id(c).is_some());
+        tokio::time::advance(Duration::from_secs(1)).await;
+        s.handle_timed_out_jobs();
+        assert_eq!(ready(&s), [c, a, b, lower]);
+
+        // New jobs still join the back.
+        let newest = s.put(qn.clone(), 0.into(), 0, 1, b"d".into()).unwrap();
+        assert_eq!(ready(&s), [c, a, b, newest, lower]);
+
+        // The server-wide setting applies to queues without their own.
+        s.configure_tube(qn.clone(), TubeSetting::TimeoutRequeue(None));
+        let config = Config::from_yaml("timeout-requeue: front").unwrap();
+        s.apply_config(&config);
+        assert!(s.reserve_by_id(newest).is_some());
+        tokio::time::advance(Duration::from_secs(1)).await;
+        s.handle_timed_out_jobs();
+        assert_eq!(ready(&s), [newest, c, a, b, lower]);
+
+        s.configure_tube(
+            qn.clone(),
+            TubeSetting::TimeoutRequeue(Some(RequeuePos::Back)),
+        );
+        assert!(s.reserve_by_id(newest).is_some());
+        tokio::time::advance(Duration::from_secs(1)).await;
+        s.handle_timed_out_jobs();
+        assert_eq!(ready(&s), [c, a, b, newest, lower]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_pri_aging() {
+        let mut s = Server::new("test");
+        let qn = queue_name("default");
+        s.configure_tube(qn.clone(), TubeSetting::PriAging(Some(100)));
+
+        let bulk = s.put(qn.clone(), 1000.into(), 0, 60, b"a".into()).unwrap();
+        tokio::time::advance(Duration::from_secs(5)).await;
+
+        // After 5s, the bulk job's effective priority is 500.
+        let p600 = s.put(qn.clone(), 600.into(), 0, 60, b"b".into()).unwrap();
+        let p400 = s.put(qn.clone(), 400.into(), 0, 60, b"c".into()).unwrap();
+        let order: Vec<JobId> = s.queues[&qn].ready.values().copied().collect();
+        assert_eq!(order, [p400, bulk, p600]);
+        assert_eq!(s.stats_job(bulk, TimeUnit::Seconds).unwrap().pri, 1000);
+
+        // Changing priority keeps the aging already accrued.
+        assert!(s.set_pri(bulk, 300.into()));
+        assert_eq!(s.queues[&qn].peek_ready(), Some(bulk));
+        assert!(s.set_pri(bulk, 1000.into()));
+
+        // Jobs re-entering the ready queue start aging afresh.
+        assert!(s.reserve_by_id(bulk).is_some());
+        assert!(s.release(bulk, 1000.into(), 0));
+        let order: Vec<JobId> = s.queues[&qn].ready.values().copied().collect();
+        assert_eq!(order, [p400, p600, bulk]);
+        tokio::time::advance(Duration::from_secs(5)).await;
+        let late = s.put(qn.clone(), 600.into(), 0, 60, b"d".into()).unwrap();
+        assert_eq!(s.queues[&qn].ready.values().last(), Some(&late));
+
+        // Without aging, priority alone orders jobs.
+        let other = queue_name("other");
+        let bulk = s
+            .put(other.clone(), 1000.into(), 0, 60, b"e".into())
+            .unwrap();
+        tokio::time::advance(Duration::from_hours(1)).await;
+        let p999 = s
+            .put(other.clone(), 999.into(), 0, 60, b"f".into())
+            .unwrap();
+        let order: Vec<JobId> =
+            s.queues[&other].ready.values().copied().collect();
+        assert_eq!(order, [p999, bulk]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_pri_aging_requeue_front() {
+        let mut s = Server::new("test");
+        let qn = queue_name("default");
+        s.configure_tube(qn.clone(), TubeSetting::PriAging(Some(1)));
+        s.configure_tube(
+            qn.clone(),
+            TubeSetting::TimeoutRequeue(Some(RequeuePos::Front)),
+        );
+
+        let old = s.put(qn.clone(), 100.into(), 0, 10, b"a".into()).unwrap();
+        tokio::time::advance(secs(50)).await;
+        let new = s.put(qn.clone(), 100.into(), 0, 10, b"b".into()).unwrap();
+
+        // A job timing out to the front keeps the aging it had accrued, rather
+        // than starting again behind jobs that have waited less.
+        assert!(s.reserve_by_id(old).is_some());
+        tokio::time::advance(secs(10)).await;
+        s.handle_timed_out_jobs();
+        let order: Vec<JobId> = s.queues[&qn].ready.values().copied().collect();
+        assert_eq!(order, [old, new]);
+        assert_eq!(s.queues[&qn].peek_ready_pri(), Some((40, old)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_pri_aging_changed() {
+        let mut s = Server::new("test");
+        let qn = queue_name("default");
+        let ready = |s: &Server| -> Vec<JobId> {
+            s.queues[&qn].ready.values().copied().collect()
+        };
+
+        let a = s.put(qn.clone(), 100.into(), 0, 60, b"a".into()).unwrap();
+        tokio::time::advance(secs(50)).await;
+        let b = s.put(qn.clone(), 80.into(), 0, 60, b"b".into()).unwrap();
+        assert_eq!(ready(&s), [b, a]);
+
+        // Enabling aging ages waiting jobs from when they started waiting, so
+        // a has already waited long enough to overtake b.
+        s.configure_tube(qn.clone(), TubeSetting::PriAging(Some(1)));
+        assert_eq!(ready(&s), [a, b]);
+        assert_eq!(s.queues[&qn].peek_ready_pri(), Some((50, a)));
+        let c = s.put(qn.clone(), 60.into(), 0, 60, b"c".into()).unwrap();
+        assert_eq!(ready(&s), [a, c, b]);
+
+        // Disabling it again orders jobs by priority alone.
+        s.configure_tube(qn.clone(), TubeSetting::PriAging(None));
+        assert_eq!(ready(&s), [c, b, a]);
+
+        // Jobs' states follow their new positions.
+        assert!(s.reserve_by_id(b).is_some());
+        assert_eq!(ready(&s), [c, a]);
+        assert_eq!(s.queues[&qn].stats.current_jobs_ready, 2);
+    }
+
+    fn queue_set(names: &[&str]) -> QueueSet {
+        QueueSet {
+            patterns: names.iter().map(|name| queue_pattern(name)).collect(),
+            mode: ReserveMode::default(),
+        }
+    }
+
+    fn queue_pattern(pattern: &str) -> QueuePattern {
+        QueuePattern(pattern.into())
+    }
+
+    #[test]
+    fn test_queue_pattern() {
+        let matches =
+            |pattern, name| queue_pattern(pattern).matches(&queue_name(name));
+
+        assert!(matches("billing", "billing"));
+        assert!(!matches("billing", "billing.acme"));
+        assert!(matches("billing.*", "billing.acme"));
+        assert!(matches("billing.*", "billing."));
+        assert!(!matches("billing.*", "billing"));
+        assert!(!matches("billing.*", "shipping.acme"));
+        assert!(matches("*.acme", "billing.acme"));
+        assert!(!matches("*.acme", "billing.acme.old"));
+        assert!(matches("*", "anything"));
+        assert!(matches("b*l*g", "billing"));
+        assert!(matches("a*b*b", "aabbb"));
+        assert!(!matches("a*bc*cd", "abcd"));
+        assert!(matches("a**b", "ab"));
+    }
+
+    #[test]
+    fn test_watch_patterns() {
+        let mut s = Server::new("test");
+        let (acme, globex) =
+            (queue_name("billing.acme"), queue_name("billing.globex"));
+        let watching = |s: &Server, qn: &QueueName| {
+            s.stats_tube(qn).unwrap().ts.current_watching
+        };
+
+        s.put(acme.clone(), 5.into(), 0, 60, b"a".into()).unwrap();
+        let mut qs = s.new_watch_set();
+        let mut other = s.new_watch_set();
+        assert_eq!(watching(&s, &queue_name("default")), 2);
+        assert_eq!(watching(&s, &acme), 0);
+
+        assert_eq!(s.watch(&mut qs, queue_pattern("billing.*")), 2);
+        assert_eq!(s.watch(&mut qs, queue_pattern("billing.acme")), 3);
+        assert_eq!(s.watch(&mut other, queue_pattern("*.acme")), 2);
+        assert_eq!(watching(&s, &acme), 2);
+
+        // Patterns also match queues created after watching them.
+        let id = s.put(globex.clone(), 0.into(), 0, 60, b"g".into()).unwrap();
+        assert_eq!(watching(&s, &globex), 1);
+        assert_eq!(
+            s.reserve_by_queue(qs.clone(), RandomState::new())
+                .map(|j| j.pri),
+            Some(0.into())
+        );
+        assert!(matches!(s.jobs[&id].1.state, JobState::Reserved { .. }));
+
+        // Ignoring a name leaves queues matched by other patterns watched.
+        assert_eq!(s.ignore(&mut qs, &queue_pattern("billing.acme")), Some(2));
+        assert_eq!(s.ignore(&mut qs, &queue_pattern("default")), Some(1));
+        assert_eq!(watching(&s, &acme), 2);
+        assert_eq!(s.ignore(&mut qs, &queue_pattern("billing.*")), None);
+
+        assert_eq!(qs.list(), vec![b"billing.*".to_vec()]);
+
+        s.forget_watch_set(&qs);
+        s.forget_watch_set(&other);
+        assert_eq!(watching(&s, &acme), 0);
+        assert_eq!(watching(&s, &queue_name("default")), 0);
+        assert!(s.watch_sets.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_reserve_by_queue() {
+        let mut s = Server::new("test");
+        let (one, two) = (queue_name("one"), queue_name("two"));
+        let h = RandomState::new;
+
+        let low = s.put(one.clone(), 20.into(), 0, 60, b"a".into()).unwrap();
+        let high = s.put(two.clone(), 10.into(), 0, 60, b"b".into()).unwrap();
+        s.put(queue_name("three"), 0.into(), 0, 60, b"c".into())
+            .unwrap();
+
+        // The highest priority job across the watched queues wins.
+        let qs = || queue_set(&["one", "two", "missing"]);
+        let job = s.reserve_by_queue(qs(), h()).unwrap();
+        assert_eq!(job.data.get(), &b"b"[..]);
+        assert_eq!(s.stats_job(high, TimeUnit::Seconds).unwrap().reserves, 1);
+
+        // Paused queues are skipped.
+        s.queues.get_mut(&one).unwrap().pause_until =
+            Some(Instant::now() + Duration::from_secs(10));
+        assert!(s.reserve_by_queue(qs(), h()).is_none());
+        tokio::time::advance(Duration::from_secs(10)).await;
+        assert!(s.reserve_by_queue(qs(), h()).is_some());
+        assert!(matches!(s.jobs[&low].1.state, JobState::Reserved { .. }));
+        assert!(s.reserve_by_queue(qs(), h()).is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_reserve_batch() {
+        let mut s = Server::new("test");
+        let (one, two) = (queue_name("one"), queue_name("two"));
+        let qs = queue_set(&["one", "two"]);
+
+        let ids = [
+            s.put(one.clone(), 30.into(), 0, 60, b"a".into()).unwrap(),
+            s.put(two.clone(), 10.into(), 0, 120, b"b".into()).unwrap(),
+            s.put(one.clone(), 20.into(), 0, 60, b"c".into()).unwrap(),
+        ];
+
+        // Jobs are reserved in priority order, each with its own TTR.
+        let batch = s.reserve_batch(&qs, 2, &RandomState::new());
+        assert_eq!(batch, [ids[1], ids[2]]);
+        assert_eq!(
+            s.stats_job(ids[1], TimeUnit::Seconds).unwrap().time_left,
+            120
+        );
+        assert_eq!(
+            s.stats_job(ids[2], TimeUnit::Seconds).unwrap().time_left,
+            60
+        );
+        assert_eq!(s.stats_job(ids[2], TimeUnit::Seconds).unwrap().reserves, 1);
+
+        // Fewer jobs than requested may be returned.
+        assert_eq!(s.reserve_batch(&qs, 10, &RandomState::new()), [ids[0]]);
+        assert!(s.reserve_batch(&qs, 10, &RandomState::new()).is_empty());
+        assert_eq!(s.queues[&one].stats.current_jobs_reserved, 2);
+    }
+
+    #[test]
+    fn test_ordered_reserve() {
+        let mut s = Server::new("test");
+        let h = RandomState::new();
+        let mut qs = s.new_watch_set();
+        s.watch(&mut qs, queue_pattern("urgent.*"));
+        s.watch(&mut qs, queue_pattern("normal"));
+        s.set_reserve_mode(&mut qs, ReserveMode::Ordered);
+        assert_eq!(s.ignore(&mut qs, &queue_pattern("default")), Some(2));
+        assert_eq!(qs.list(), vec![b"urgent.*".to_vec(), b"normal".to_vec()]);
+
+        let normal = s
+            .put(queue_name("normal"), 0.into(), 0, 60, b"n".into())
+            .unwrap();
+        let low = s
+            .put(queue_name("urgent.b"), 200.into(), 0, 60, b"b".into())
+            .unwrap();
+        let high = s
+            .put(queue_name("urgent.a"), 100.into(), 0, 60, b"a".into())
+            .unwrap();
+
+        // Earlier queues win regardless of priority, but queues matched by the
+        // same pattern are compared by priority.
+        assert_eq!(s.reserve_batch(&qs, 3, &h), vec![high, low, normal]);
+
+        // Paused queues are skipped.
+        let urgent = s
+            .put(queue_name("urgent.a"), 0.into(), 0, 60, b"u".into())
+            .unwrap();
+        let normal = s
+            .put(queue_name("normal"), 0.into(), 0, 60, b"n".into())
+            .unwrap();
+        s.queues
+            .get_mut(&queue_name("urgent.a"))
+            .unwrap()
+            .pause_until = Some(Instant::now() + Duration::from_secs(10));
+        assert_eq!(s.reserve_batch(&qs, 2, &h), vec![normal]);
+        assert!(matches!(s.jobs[&urgent].1.state, JobState::Ready { .. }));
+
+        // The mode doesn't affect who's counted as watching.
+        assert_eq!(
+            s.stats_tube(&queue_name("normal"))
+                .unwrap()
+                .ts
+                .current_watching,
+            1
+        );
+        s.set_reserve_mode(&mut qs, ReserveMode::Priority);
+        assert_eq!(
+            s.stats_tube(&queue_name("normal"))
+                .unwrap()
+                .ts
+                .current_watching,
+            1
+        );
+    }
+
+    #[test]
+    fn test_weighted_reserve() {
+        let h = BuildHasherDefault::<DefaultHasher>::default();
+        let qs = queue_set(&["a", "b", "c"]);
+        let setup = || {
+            let mut s = Server::new("test");
+            s.configure_tube(queue_name("a"), TubeSetting::Weight(Some(3)));
+            for _ in 0..400 {
+                // Priority only matters within a queue.
+                s.put(queue_name("a"), 100.into(), 0, 60, b"a".into())
+                    .unwrap();
+                s.put(queue_name("b"), 0.into(), 0, 60, b"b".into())
+                    .unwrap();
+            }
+            s
+        };
+
+        let mut s = setup();
+        let ids = s.reserve_batch(&qs, 400, &h);
+        assert_eq!(ids.len(), 400);
+
+        let reserves = |qn| s.queues[&queue_name(qn)].stats.total_reserves;
+        assert!((270..=330).contains(&reserves("a")), "{}", reserves("a"));
+        assert_eq!(reserves("a") + reserves("b"), 400);
+
+        // The same hasher gives the same choices.
+        assert_eq!(setup().reserve_batch(&qs, 400, &h), ids);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_reserve_rate() {
+        let mut s = Server::new("test");
+        let qn = queue_name("default");
+        let h = RandomState::new;
+        let qs = || queue_set(&["default"]);
+
+        s.configure_tube(qn.clone(), TubeSetting::ReserveRate(Some(2)));
+        for _ in 0..5 {
+            s.put(qn.clone(), 0.into(), 0, 60, b"a".into()).unwrap();
+        }
+
+        // A second's worth of reservations can be made at once.
+        assert!(s.reserve_by_queue(qs(), h()).is_some());
+        assert!(s.reserve_by_queue(qs(), h()).is_some());
+        assert!(s.reserve_by_queue(qs(), h()).is_none());
+        assert_eq!(s.queues[&qn].stats.current_jobs_ready, 3);
+        s.count_throttled(&qs());
+        assert_eq!(s.queues[&qn].stats.reserves_throttled, 1);
+
+        // Then one more each half second.
+        let retry = s.throttled_until(&qs()).unwrap();
+        assert_eq!(retry, Instant::now() + Duration::from_millis(500));
+        tokio::time::advance(Duration::from_millis(499)).await;
+        assert!(s.reserve_by_queue(qs(), h()).is_none());
+        tokio::time::advance(Duration::from_millis(1)).await;
+        assert_eq!(s.throttled_until(&qs()), None);
+        assert!(s.reserve_by_queue(qs(), h()).is_some());
+        assert!(s.reserve_by_queue(qs(), h()).is_none());
+
+        // Unused allowance builds up to at most a second's worth.
+        tokio::time::advance(Duration::from_secs(10)).await;
+        assert!(s.reserve_by_queue(qs(), h()).is_some());
+        assert!(s.reserve_by_queue(qs(), h()).is_some());
+        assert_eq!(s.queues[&qn].stats.current_jobs_ready, 0);
+        assert_eq!(s.throttled_until(&qs()), None);
+
+        // Reserving by ID isn't limited.
+        let id = s.put(qn.clone(), 0.into(), 0, 60, b"b".into()).unwrap();
+        assert!(s.reserve_by_id(id).is_some());
+
+        // The limit can apply over a longer interval, of which a whole
+        // interval's worth of reservations can be made at once.
+        s.configure_tube(
+            qn.clone(),
+            TubeSetting::ReserveRateInterval(Some(60)),
+        );
+        tokio::time::advance(secs(60)).await;
+        for _ in 0..3 {
+            s.put(qn.clone(), 0.into(), 0, 60, b"c".into()).unwrap();
+        }
+        assert!(s.reserve_by_queue(qs(), h()).is_some());
+        assert!(s.reserve_by_queue(qs(), h()).is_some());
+        assert!(s.reserve_by_queue(qs(), h()).is_none());
+        let retry = s.throttled_until(&qs()).unwrap();
+        assert_eq!(retry, Instant::now() + secs(30));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_max_reserved() {
+        let mut s = Server::new("test");
+        let qn = queue_name("default");
+        let h = RandomState::new;
+        let qs = || queue_set(&["default"]);
+
+        s.configure_tube(qn.clone(), TubeSetting::MaxReserved(Some(2)));
+        let ids: Vec<JobId> = (0..6)
+            .map(|_| s.put(qn.clone(), 0.into(), 0, 60, b"a".into()).unwrap())
+            .collect();
+
+        assert!(s.reserve_by_queue(qs(), h()).is_some());
+        assert!(s.reserve_by_queue(qs(), h()).is_some());
+        assert!(s.reserve_by_queue(qs(), h()).is_none());
+        assert!(s.reserve_by_id(ids[2]).is_none());
+        assert_eq!(s.queues[&qn].stats.current_jobs_reserved, 2);
+
+        // Another job can be reserved whenever a reserved job is released,
+        // deleted, buried, or times out.
+        assert!(s.release(ids[0], 0.into(), 0));
+        assert!(s.reserve_by_queue(qs(), h()).is_some());
+
+        assert!(s.delete(ids[1]));
+        assert!(s.reserve_by_queue(qs(), h()).is_some());
+
+        let (_, reserved) = *s.queues[&qn].reserved.first().unwrap();
+        assert!(s.bury(reserved, 0.into()));
+        assert!(s.reserve_by_queue(qs(), h()).is_some());
+        assert!(s.reserve_by_queue(qs(), h()).is_none());
+
+        tokio::time::advance(Duration::from_mins(1)).await;
+        s.handle_timed_out_jobs();
+        assert_eq!(s.queues[&qn].stats.current_jobs_reserved, 0);
+        assert!(s.reserve_by_queue(qs(), h()).is_some());
+        assert!(s.reserve_by_queue(qs(), h()).is_some());
+        assert!(s.reserve_by_queue(qs(), h()).is_none());
+
+        // Without the limit, any ready job can be reserved.
+        s.configure_tube(qn.clone(), TubeSetting::MaxReserved(None));
+        assert!(s.reserve_by_queue(qs(), h()).is_some());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_dead_letter() {
+        let mut s = Server::new("test");
+        let qn = queue_name("work");
+        let dlq = queue_name("dlq");
+
+        s.configure_tube(
+            qn.clone(),
+            TubeSetting::DeadLetterTube(Some(dlq.0.clone())),
+        );
+        s.configure_tube(qn.clone(), TubeSetting::DeadLetterReleases(Some(2)));
+        s.configure_tube(qn.clone(), TubeSetting::DeadLetterTimeouts(Some(1)));
+
+        // The second release dead-letters the job.
+        let released = s.put(qn.clone(), 0.into(), 0, 60, b"a".into()).unwrap();
+        assert!(s.reserve_by_id(released).is_some());
+        assert!(s.release(released, 0.into(), 0));
+        assert!(s.reserve_by_id(released).is_some());
+        assert!(s.release(released, 0.into(), 0));
+
+        let stats = s.stats_job(released, TimeUnit::Seconds).unwrap();
+        assert_eq!(stats.tube, b"dlq");
+        assert_eq!(stats.original_tube.as_deref(), Some(&b"work"[..]));
+        assert!(matches!(stats.state, JobState::Buried { .. }));
+        assert_eq!(s.queues[&qn].stats.current_jobs_reserved, 0);
+        assert_eq!(s.queues[&qn].stats.current_jobs_ready, 0);
+        assert_eq!(s.queues[&dlq].stats.current_jobs_buried, 1);
+
+        // Timing out dead-letters the job too.
+        let timed_out = s.put(qn.clone(), 0.into(), 0, 1, b"b".into()).unwrap();
+        assert!(s.reserve_by_id(timed_out).is_some());
+        s.handle_timed_out_jobs();
+        assert_eq!(s.jobs[&timed_out].0, qn);
+        tokio::time::advance(Duration::from_secs(1)).await;
+        s.handle_timed_out_jobs();
+        assert_eq!(s.jobs[&timed_out].0, dlq);
+        assert_eq!(s.jobs[&timed_out].1.timeouts, 1);
+        assert_eq!(s.queues[&dlq].stats.current_jobs_buried, 2);
+
+        // Without a dead-letter queue, jobs are buried in place.
+        s.configure_tube(qn.clone(), TubeSetting::DeadLetterTube(None));
+        s.configure_tube(qn.clone(), TubeSetting::DeadLetterBuries(Some(2)));
+        let buried = s.put(qn.clone(), 0.into(), 0, 60, b"c".into()).unwrap();
+        assert!(s.reserve_by_id(buried).is_some());
+        assert!(s.bury(buried, 0.into()));
+        assert_eq!(s.jobs[&buried].0, qn);
+        assert_eq!(
+            s.stats_job(buried, TimeUnit::Seconds)
+                .unwrap()
+                .original_tube,
+            None
+        );
+        assert_eq!(s.queues[&qn].stats.current_jobs_buried, 1);
+
+        // Jobs that aren't reserved can't be released or buried.
+        assert!(!s.release(buried, 0.into(), 0));
+        assert!(!s.bury(buried, 0.into()));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_tick() {
+        let mut s = Server::new("test");
+        let qn = queue_name("default");
+        let now = UNIX_EPOCH + Duration::from_secs(1000);
+        assert_eq!(s.next_deadline(now), None);
+
+        let start = Instant::now();
+        let delayed = s.put(qn.clone(), 0.into(), 10, 60, b"a".into()).unwrap();
+        let reserved = s.put(qn.clone(), 0.into(), 0, 5, b"b".into()).unwrap();
+        assert!(s.reserve_by_id(reserved).is_some());
+        let expiring = s
+            .put_with_ttl(
+                qn.clone(),
+                0.into(),
+                secs(0),
+                secs(60),
+                secs(20),
+                b"c".into(),
+            )
+            .unwrap();
+        assert_eq!(s.next_deadline(now), Some(start + Duration::from_secs(5)));
+
+        // Nothing happens before a deadline.
+        tokio::time::advance(Duration::from_secs(4)).await;
+        s.tick(now);
+        assert!(matches!(
+            s.jobs[&reserved].1.state,
+            JobState::Reserved { .. }
+        ));
+
+        // The reserved job times out, then the delayed job becomes ready.
+        tokio::time::advance(Duration::from_secs(1)).await;
+        s.tick(now);
+        assert!(matches!(s.jobs[&reserved].1.state, JobState::Ready { .. }));
+        assert_eq!(s.next_deadline(now), Some(start + Duration::from_secs(10)));
+        tokio::time::advance(Duration::from_secs(5)).await;
+        s.tick(now);
+        assert!(matches!(s.jobs[&delayed].1.state, JobState::Ready { .. }));
+        assert_eq!(s.queues[&qn].stats.current_jobs_delayed, 0);
+        assert_eq!(s.queues[&qn].stats.current_jobs_ready, 3);
+
+        // Expiry in a paused queue waits for the pause to end.
+        s.queues.get_mut(&qn).unwrap().pause_until =
+            Some(start + Duration::from_secs(30));
+        assert_eq!(s.next_deadline(now), Some(start + Duration::from_secs(30)));
+
+        // Recurring jobs are due at the start of their minute, found from the
+        // wall clock.
+        let now = now + Duration::from_secs(10);
+        s.schedule(
+            b"tick".into(),
+            RecurringJob::new(
+                qn.clone(),
+                0.into(),
+                secs(60),
+                b"d".into(),
+                Cron::parse(b"* * * * *").unwrap(),
+                now,
+            ),
+        )
+        .unwrap();
+        assert_eq!(s.next_deadline(now), Some(start + Duration::from_secs(20)));
+        tokio::time::advance(Duration::from_secs(10)).await;
+        s.tick(now + Duration::from_secs(10));
+        assert_eq!(s.queues[&qn].stats.current_jobs_ready, 4);
+        assert!(s.jobs.contains_key(&expiring));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_expired_jobs() {
+        let mut s = Server::new("test");
+        let qn = queue_name("work");
+        let stale = queue_name("stale");
+
+        let ready = s
+            .put_with_ttl(
+                qn.clone(),
+                0.into(),
+                secs(0),
+                secs(60),
+                secs(10),
+                b"a".into(),
+            )
+            .unwrap();
+        let delayed = s
+            .put_with_ttl(
+                qn.clone(),
+                0.into(),
+                secs(20),
+                secs(60),
+                secs(10),
+                b"b".into(),
+            )
+            .unwrap();
+        let reserved = s
+            .put_with_ttl(
+                qn.clone(),
+                0.into(),
+                secs(0),
+                secs(60),
+                secs(10),
+                b"c".into(),
+            )
+            .unwrap();
+        let waiting = s
+            .put_after(
+                qn.clone(),
+                &[ready],
+                0.into(),
+                secs(0),
+                secs(60),
+                b"d".into(),
+            )
+            .unwrap();
+        assert!(s.reserve_by_id(reserved).is_some());
+
+        tokio::time::advance(Duration::from_secs(9)).await;
+        s.handle_expired_jobs();
+        assert_eq!(s.queues[&qn].stats.total_jobs_expired, 0);
+
+        // Unreserved jobs are deleted, releasing any jobs waiting on them.
+        tokio::time::advance(Duration::from_secs(1)).await;
+        s.handle_expired_jobs();
+        assert!(!s.jobs.contains_key(&ready));
+        assert!(!s.jobs.contains_key(&delayed));
+        assert!(s.jobs.contains_key(&reserved));
+        assert!(matches!(s.jobs[&waiting].1.state, JobState::Ready { .. }));
+        assert_eq!(s.queues[&qn].stats.total_jobs_expired, 2);
+        assert_eq!(s.queues[&qn].stats.current_jobs_delayed, 0);
+        assert_eq!(s.queues[&qn].data_bytes, 2);
+
+        // Jobs in paused queues expire only once the queue is unpaused.
+        s.configure_tube(
+            qn.clone(),
+            TubeSetting::ExpiredTube(Some(stale.0.clone())),
+        );
+        let moved = s
+            .put_with_ttl(
+                qn.clone(),
+                5.into(),
+                secs(0),
+                secs(60),
+                secs(10),
+                b"e".into(),
+            )
+            .unwrap();
+        s.queues.get_mut(&qn).unwrap().pause_until =
+            Some(Instant::now() + Duration::from_secs(20));
+        tokio::time::advance(Duration::from_secs(10)).await;
+        s.handle_expired_jobs();
+        assert_eq!(s.jobs[&moved].0, qn);
+
+        // With an expired-jobs queue configured, jobs are moved there.
+        tokio::time::advance(Duration::from_secs(10)).await;
+        s.handle_expired_jobs();
+        let stats = s.stats_job(moved, TimeUnit::Seconds).unwrap();
+        assert_eq!(stats.tube, b"stale");
+        assert_eq!(stats.pri, 5);
+        assert!(matches!(stats.state, JobState::Ready { .. }));
+        assert_eq!(s.queues[&qn].stats.total_jobs_expired, 3);
+        assert_eq!(s.queues[&stale].stats.current_jobs_ready, 1);
+        assert_eq!(s.queues[&stale].data_bytes, 1);
+        assert!(s.queues[&stale].expiring.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_complete() {
+        let mut s = Server::new("test");
+        let qn = queue_name("rpc");
+        let replies = queue_name("replies");
+
+        // Results of jobs without a reply queue are kept by job ID.
+        let kept = s.put(qn.clone(), 0.into(), 0, 60, b"a".into()).unwrap();
+        assert_eq!(s.complete(kept, b"x".into()), Err(PutError::NotFound));
+        let notified = s.result_stored();
+        let mut notified = Box::pin(notified.notified());
+        notified.as_mut().enable();
+        assert!(s.reserve_by_id(kept).is_some());
+        assert_eq!(s.complete(kept, b"done".into()), Ok(()));
+        assert!(notified.now_or_never().is_some());
+        assert!(!s.jobs.contains_key(&kept));
+        assert_eq!(s.result(kept), Some(&b"done"[..]));
+
+        // ...until the retention period passes.
+        tokio::time::advance(Server::DEFAULT_RESULT_RETENTION).await;
+        assert_eq!(s.result(kept), None);
+
+        // Results of jobs with a reply queue are put there.
+        let with_reply = s
+            .put_with_reply(
+                qn.clone(),
+                &replies,
+                7.into(),
+                secs(0),
+                secs(60),
+                b"b".into(),
+            )
+            .unwrap();
+        assert!(s.reserve_by_id(with_reply).is_some());
+        assert_eq!(s.complete(with_reply, b"ok".into()), Ok(()));
+        assert_eq!(s.result(with_reply), None);
+        let reply = s.queues[&replies].peek_ready().unwrap();
+        let (_, job) = &s.jobs[&reply];
+        assert_eq!(job.data.get(), &b"ok"[..]);
+        assert_eq!(job.pri, 7.into());
+        assert_eq!(
+            job.headers[&b"job-id"[..]],
+            with_reply.0.to_string().as_bytes()
+        );
+
+        // Failing to deliver the result leaves the job reserved.
+        s.configure_tube(replies.clone(), TubeSetting::MaxJobs(Some(1)));
+        let full = s
+            .put_with_reply(
+                qn.clone(),
+                &replies,
+                0.into(),
+                secs(0),
+                secs(60),
+                b"c".into(),
+            )
+            .unwrap();
+        assert!(s.reserve_by_id(full).is_some());
+        assert_eq!(s.complete(full, b"x".into()), Err(PutError::QuotaExceeded));
+        assert!(matches!(s.jobs[&full].1.state, JobState::Reserved { .. }));
+    }
+
+    #[test]
+    fn test_broadcast() {
+        let mut s = Server::new("test");
+        let qn = queue_name("events");
+        let (a, b) = (queue_name("cache-a"), queue_name("cache-b"));
+
+        assert!(s.subscribe(qn.clone(), b.clone()));
+        assert!(s.subscribe(qn.clone(), a.clone()));
+        assert!(!s.subscribe(qn.clone(), a.clone()));
+
+        // Each put creates a copy in every subscriber, reporting the first.
+        let id = s
+            .put_with_ttl(
+                qn.clone(),
+                3.into(),
+                secs(0),
+                secs(60),
+                secs(10),
+                b"flush".into(),
+            )
+            .unwrap();
+        assert_eq!(s.jobs[&id].0, a);
+        let copy = s.queues[&b].peek_ready().unwrap();
+        assert_ne!(copy, id);
+        assert_eq!(s.jobs[&copy].1.data.get(), &b"flush"[..]);
+        assert_eq!(s.jobs[&copy].1.pri, 3.into());
+        assert!(s.jobs[&copy].1.expires.is_some());
+        assert_eq!(s.queues[&qn].stats.total_jobs, 0);
+
+        let stats = s.stats_tube(&qn).unwrap();
+        assert_eq!(stats.config.subscribers.len(), 2);
+
+        // No copies are made if any subscriber is full, or the job is larger
+        // than the broadcast queue allows.
+        s.configure_tube(b.clone(), TubeSetting::MaxJobs(Some(1)));
+        assert_eq!(
+            s.put(qn.clone(), 0.into(), 0, 60, b"x".into()),
+            Err(PutError::QuotaExceeded),
+        );
+        s.configure_tube(b.clone(), TubeSetting::MaxJobs(None));
+        s.configure_tube(qn.clone(), TubeSetting::MaxJobSize(Some(4)));
+        assert_eq!(
+            s.put(qn.clone(), 0.into(), 0, 60, b"large".into()),
+            Err(PutError::JobTooBig),
+        );
+        assert_eq!(s.queues[&a].stats.current_jobs_ready, 1);
+        assert_eq!(s.queues[&b].stats.current_jobs_ready, 1);
+
+        // Without subscribers, the queue behaves normally.
+        assert!(s.unsubscribe(&qn, &a));
+        assert!(s.unsubscribe(&qn, &b));
+        assert!(!s.unsubscribe(&qn, &b));
+        let id = s.put(qn.clone(), 0.into(), 0, 60, b"y".into()).unwrap();
+        assert_eq!(s.jobs[&id].0, qn);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_put_at() {
+        let mut s = Server::new("test");
+        let qn = queue_name("default");
+        let at = |t| UNIX_EPOCH + Duration::from_secs(t);
+
+        let id = s
+            .put_at(
+                qn.clone(),
+                0.into(),
+                at(1060),
+                secs(60),
+                b"a".into(),
+                at(1000),
+            )
+            .unwrap();
+        let stats = s.stats_job(id, TimeUnit::Seconds).unwrap();
+        assert_eq!((stats.delay, stats.time_left), (60, 60));
+        assert_eq!(stats.run_at, Some(1060));
+
+        // Times that have passed make the job ready immediately.
+        let past = s
+            .put_at(
+                qn.clone(),
+                0.into(),
+                at(900),
+                secs(60),
+                b"b".into(),
+                at(1000),
+            )
+            .unwrap();
+        assert!(matches!(s.jobs[&past].1.state, JobState::Ready { .. }));
+        assert_eq!(s.stats_job(past, TimeUnit::Seconds).unwrap().run_at, None);
+
+        // Only delayed jobs can be rescheduled.
+        assert!(s.reschedule(id, at(2000), at(1010)));
+        let stats = s.stats_job(id, TimeUnit::Seconds).unwrap();
+        assert_eq!((stats.time_left, stats.run_at), (990, Some(2000)));
+        assert_eq!(s.queues[&qn].stats.current_jobs_delayed, 1);
+        assert!(!s.reschedule(past, at(2000), at(1010)));
+
+        assert!(s.reschedule(id, at(1000), at(1010)));
+        assert!(matches!(s.jobs[&id].1.state, JobState::Ready { .. }));
+        assert_eq!(s.queues[&qn].stats.current_jobs_delayed, 0);
+        assert_eq!(s.queues[&qn].stats.current_jobs_ready, 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_millisecond_times() {
+        let mut s = Server::new("test");
+        let qn = queue_name("default");
+        let ms = TimeUnit::Milliseconds;
+
+        let delayed = s
+            .put_timed(
+                qn.clone(),
+                0.into(),
+                ms.duration(250),
+                ms.duration(1500),
+                b"a".into(),
+            )
+            .unwrap();
+        let stats = s.stats_job(delayed, ms).unwrap();
+        assert_eq!((stats.delay, stats.ttr, stats.time_left), (250, 1500, 250));
+        let stats = s.stats_job(delayed, TimeUnit::Seconds).unwrap();
+        assert_eq!((stats.delay, stats.ttr, stats.time_left), (0, 1, 0));
+
+        // A TTR of 0 is still treated as 1 second, but shorter TTRs are kept.
+        let id = s
+            .put_timed(
+                qn.clone(),
+                0.into(),
+                Duration::ZERO,
+                ms.duration(0),
+                b"b".into(),
+            )
+            .unwrap();
+        assert!(s.reserve_by_id(id).is_some());
+        assert_eq!(s.stats_job(id, ms).unwrap().time_left, 1000);
+        assert!(s.release_timed(id, 0.into(), Duration::ZERO));
+
+        let id = s
+            .put_timed(
+                qn.clone(),
+                0.into(),
+                Duration::ZERO,
+                ms.duration(200),
+                b"c".into(),
+            )
+            .unwrap();
+        assert!(s.reserve_by_id(id).is_some());
+        tokio::time::advance(Duration::from_millis(150)).await;
+        assert_eq!(s.stats_job(id, ms).unwrap().time_left, 50);
+        assert_eq!(s.stats_job(id, ms).unwrap().age, 150);
+
+        assert_eq!(ms.count(Duration::from_secs(u64::MAX)), u32::MAX);
+    }
+
+    #[test]
+    fn test_release_disconnected() {
+        let mut s = Server::new("test");
+        let qn = queue_name("default");
+
+        let a = s.put(qn.clone(), 10.into(), 0, 60, b"a".into()).unwrap();
+        let b = s.put(qn.clone(), 20.into(), 0, 60, b"b".into()).unwrap();
+        let ready = s.put(qn.clone(), 30.into(), 0, 60, b"c".into()).unwrap();
+        assert!(s.reserve_by_id(a).is_some());
+        assert!(s.reserve_by_id(b).is_some());
+        assert!(s.delete(b));
+
+        // Deleted and unreserved jobs are skipped.
+        assert_eq!(s.release_disconnected(&[a, b, ready]), 1);
+        assert_eq!(s.queues[&qn].stats.current_jobs_reserved, 0);
+        assert_eq!(s.queues[&qn].stats.current_jobs_ready, 2);
+        assert_eq!(s.queues[&qn].peek_ready(), Some(a));
+        assert_eq!(s.jobs[&a].1.releases, 0);
+        assert_eq!(s.jobs[&a].1.pri, 10.into());
+    }
+
+    #[test]
+    fn test_move_job() {
+        let mut s = Server::new("test");
+        let src = queue_name("src");
+        let dest = queue_name("dest");
+
+        let a = s.put(src.clone(), 10.into(), 0, 60, b"a".into()).unwrap();
+        let b = s.put(src.clone(), 20.into(), 0, 60, b"b".into()).unwrap();
+        let delayed =
+            s.put(src.clone(), 30.into(), 60, 60, b"c".into()).unwrap();
+        let JobState::Delayed { until, run_at } = s.jobs[&delayed].1.state
+        else {
+            panic!("expected delayed job");
+        };
+
+        // Moving creates the destination queue and keeps the job's ID,
+        // priority and statistics.
+        assert!(s.move_job(b, dest.clone()));
+        assert_eq!(s.queues[&dest].peek_ready(), Some(b));
+        assert_eq!(s.queues[&dest].stats.current_jobs_ready, 1);
+        assert_eq!(s.queues[&dest].stats.current_jobs_urgent, 1);
+        assert_eq!(s.queues[&src].stats.current_jobs_ready, 1);
+        let stats = s.stats_job(b, TimeUnit::Seconds).unwrap();
+        assert_eq!(stats.tube, b"dest");
+        assert_eq!(stats.pri, 20);
+
+        // Moving to the same queue is a no-op.
+        assert!(s.move_job(b, dest.clone()));
+        assert_eq!(s.queues[&dest].stats.current_jobs_ready, 1);
+
+        // Delayed jobs keep their remaining delay.
+        assert!(s.move_job(delayed, dest.clone()));
+        assert_eq!(
+            s.jobs[&delayed].1.state,
+            JobState::Delayed { until, run_at }
+        );
+        assert_eq!(s.queues[&dest].stats.current_jobs_delayed, 1);
+        assert_eq!(s.queues[&src].stats.current_jobs_delayed, 0);
+
+        // Bulk moves respect their bound.
+        let c = s.put(src.clone(), 10.into(), 0, 60, b"d".into()).unwrap();
+        assert_eq!(s.move_jobs(&src, &dest, 1), 1);
+        assert_eq!(s.jobs[&a].0, dest);
+        assert_eq!(s.jobs[&c].0, src);
+        assert_eq!(s.move_jobs(&src, &dest, 10), 1);
+        assert_eq!(s.queues[&src].stats.current_jobs_ready, 0);
+        assert_eq!(s.queues[&dest].stats.current_jobs_ready, 3);
+        assert_eq!(s.move_jobs(&queue_name("missing"), &dest, 10), 0);
+
+        assert!(!s.move_job(JobId(NonZeroU64::MAX), dest));
+    }
+
+    #[test]
+    fn test_tube_limits() {
+        let mut s = Server::new("test");
+        let qn = queue_name("default");
+        let config = Config::from_yaml(
+            "
+tubes:
+  default:
+    default-ttr: 120
+    max-jobs: 2
+    max-bytes: 5
+",
+        )
+        .unwrap();
+        s.apply_config(&config);
+
+        // A TTR of zero takes the queue's default.
+        let a = s.put(qn.clone(), 0.into(), 0, 0, b"aa".into()).unwrap();
+        assert_eq!(s.stats_job(a, TimeUnit::Seconds).unwrap().ttr, 120);
+        let b = s.put(qn.clone(), 0.into(), 0, 30, b"bb".into()).unwrap();
+        assert_eq!(s.stats_job(b, TimeUnit::Seconds).unwrap().ttr, 30);
+
+        // The job count limit counts jobs in every state.
+        assert!(s.reserve_by_id(a).is_some());
+        assert_eq!(
+            s.put(qn.clone(), 0.into(), 0, 0, b"c".into()),
+            Err(PutError::QuotaExceeded)
+        );
+        assert!(s.delete(b));
+
+        // The byte limit counts the data of all jobs in the queue.
+        assert_eq!(
+            s.put(qn.clone(), 0.into(), 0, 0, b"cccc".into()),
+            Err(PutError::QuotaExceeded)
+        );
+        assert!(s.put(qn.clone(), 0.into(), 0, 0, b"ccc".into()).is_ok());
+        assert_eq!(s.queues[&qn].data_bytes, 5);
+
+        // Queues may raise or lower the server-wide maximum job size.
+        let big = vec![0; 70_000];
+        assert_eq!(
+            s.put(queue_name("other"), 0.into(), 0, 0, big.clone()),
+            Err(PutError::JobTooBig)
+        );
+        let setting = TubeSetting::MaxJobSize(Some(100_000));
+        s.configure_tube(queue_name("other"), setting);
+        assert!(s.put(queue_name("other"), 0.into(), 0, 0, big).is_ok());
+
+        let stats = s.stats_tube(&qn).unwrap();
+        assert_eq!(stats.config, config.tubes["default"]);
+        assert_eq!(stats.ts.current_jobs_reserved, 1);
+        assert!(s.stats_tube(&queue_name("missing")).is_none());
+
+        // Quotas don't stop jobs being moved or dead-lettered into a queue.
+        let full = queue_name("full");
+        s.configure_tube(full.clone(), TubeSetting::MaxJobs(Some(1)));
+        let moved = s.put(full.clone(), 0.into(), 0, 0, b"d".into()).unwrap();
+        assert!(s.move_job(moved, qn.clone()));
+        assert_eq!(s.queues[&qn].current_jobs(), 3);
+        assert_eq!(s.queues[&qn].data_bytes, 6);
+
+        let other = queue_name("other");
+        s.configure_tube(
+            other.clone(),
+            TubeSetting::DeadLetterTube(Some(qn.0.clone())),
+        );
+        s.configure_tube(other.clone(), TubeSetting::DeadLetterBuries(Some(1)));
+        let buried = s.put(other.clone(), 0.into(), 0, 0, b"e".into()).unwrap();
+        assert!(s.reserve_by_id(buried).is_some());
+        assert!(s.bury(buried, 0.into()));
+        assert_eq!(s.jobs[&buried].0, qn);
+        assert_eq!(s.queues[&qn].current_jobs(), 4);
+    }
+
+    #[test]
+    fn test_compression() {
+        let mut s = Server::new("test");
+        let qn = queue_name("default");
+        let config = Config::from_yaml(
+            "
+compress-threshold: 100
+tubes:
+  default:
+    max-bytes: 2300
+",
+        )
+        .unwrap();
+        s.apply_config(&config);
+
+        // Large jobs are compressed, but small ones and those that don't
+        // compress aren't.
+        let json = br#"{"id": 1, "status": "ok"}"#.repeat(80);
+        let noise: Vec<u8> = (0..200u32)
+            .map(|n| n.wrapping_mul(2_654_435_761).to_be_bytes()[0])
+            .collect();
+        let big = s.put(qn.clone(), 0.into(), 0, 60, json.clone()).unwrap();
+        let data = &s.jobs[&big].1.data;
+        assert!(data.stored_len() < data.len());
+        let small =
+            s.put(qn.clone(), 0.into(), 0, 60, b"small".into()).unwrap();
+        assert_eq!(s.jobs[&small].1.data.stored_len(), 5);
+        let random = s.put(qn.clone(), 1.into(), 0, 60, noise.clone()).unwrap();
+        assert_eq!(s.jobs[&random].1.data.stored_len(), 200);
+
+        // Stats report both the original and stored sizes.
+        let stored = s.jobs[&big].1.data.stored_len();
+        let stats = s.stats_tube(&qn).unwrap().ts;
+        assert_eq!(stats.current_bytes, 2000 + 5 + 200);
+        assert_eq!(stats.current_stored_bytes, stored + 5 + 200);
+
+        // Limits apply to the original size.
+        assert_eq!(
+            s.put(qn.clone(), 0.into(), 0, 60, json.clone()),
+            Err(PutError::QuotaExceeded)
+        );
+
+        // Reserving a job gives its original data.
+        let job = s.reserve_by_id(big).unwrap();
+        assert_eq!(job.data.get(), &json[..]);
+
+        // Moving a job moves its sizes with it.
+        assert!(s.move_job(random, queue_name("other")));
+        let stats = s.stats_tube(&queue_name("other")).unwrap().ts;
+        assert_eq!(
+            (stats.current_bytes, stats.current_stored_bytes),
+            (200, 200)
+        );
+
+        assert!(s.delete(big));
+        assert!(s.delete(small));
+        let stats = s.stats_tube(&qn).unwrap().ts;
+        assert_eq!((stats.current_bytes, stats.current_stored_bytes), (0, 0));
     }
 }
@@ -0,0 +1,2392 @@
+//! session connects clients to the [`Server`] they share.
+use std::collections::{BTreeMap, BTreeSet};
+use std::hash::RandomState;
+use std::num::NonZeroU64;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+use tokio::select;
+use tokio::sync::Notify;
+use tokio::time::{Instant, sleep_until};
+use tokio_util::sync::CancellationToken;
+
+use super::{
+    JobId, PutError, QueueName, QueuePattern, QueueSet, RecurringJob,
+    ReserveMode, Server, TimeUnit, TubeState,
+};
+use crate::types::config::Config;
+use crate::types::job::{Headers, Job};
+use crate::types::states::JobState;
+use crate::wire::protocol::{Command, ReservedJob, Response, WatchList};
+
+/// The server, shared by the [`Session`] of every connected client and the
+/// task running its timers.
+pub struct SharedServer {
+    server: Mutex<Server>,
+    // Notified whenever a job may have become reservable, after which clients
+    // waiting to reserve should retry.
+    jobs_changed: Notify,
+    // Notified whenever the server's next deadline may have moved earlier.
+    deadlines_changed: Notify,
+}
+
+impl SharedServer {
+    /// Creates a server with the settings from a configuration file, which
+    /// accepts jobs of up to `max_job_size` bytes unless a tube's settings say
+    /// otherwise.
+    #[must_use]
+    pub fn new(config: &Config, max_job_size: u32) -> Arc<Self> {
+        let mut server = Server::new(env!("CARGO_PKG_NAME"));
+        server.max_job_size = max_job_size;
+        server.apply_config(config);
+
+        Arc::new(Self {
+            server: Mutex::new(server),
+            jobs_changed: Notify::new(),
+            deadlines_changed: Notify::new(),
+        })
+    }
+
+    /// Starts a session for a newly connected client, which uses and watches
+    /// the default tube.
+    #[must_use]
+    pub fn session(self: &Arc<Self>) -> Session {
+        let mut server = self.lock();
+        let watching = server.new_watch_set();
+        let stats = &mut server.stats;
+        stats.current_connections = stats.current_connections.saturating_add(1);
+        stats.total_connections = stats.total_connections.saturating_add(1);
+        drop(server);
+
+        Session {
+            shared: Arc::clone(self),
+            using: QueueName(Server::DEFAULT_QUEUE.to_vec()),
+            watching,
+            reserved: BTreeMap::new(),
+            headers: false,
+            unit: TimeUnit::Seconds,
+            hasher: RandomState::new(),
+            producer: false,
+            worker: false,
+        }
+    }
+
+    /// Runs [`Server::tick`] whenever the server's next deadline passes, until
+    /// cancelled.
+    pub async fn run_timers(&self, cancel: CancellationToken) {
+        loop {
+            let changed = self.deadlines_changed.notified();
+            let deadline = {
+                let mut server = self.lock();
+                let now = SystemTime::now();
+                server.tick(now);
+                server.next_deadline(now)
+            };
+            self.jobs_changed.notify_waiters();
+
+            select! {
+                () = sleep_until_some(deadline) => {},
+                () = changed => {},
+                () = cancel.cancelled() => return,
+            }
+        }
+    }
+
+    /// Wakes any clients waiting to reserve, and the timers, after the server
+    /// has changed.
+    fn changed(&self) {
+        self.jobs_changed.notify_waiters();
+        self.deadlines_changed.notify_waiters();
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Server> {
+        // Commands leave the server consistent before any point at which they
+        // could panic, so it's safe to use if a holder of the lock panicked.
+        self.server.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// A connected client's view of the server: the tube it uses, the tubes it
+/// watches, and the jobs it has reserved. Dropping a session releases its
+/// reserved jobs and stops counting the client as watching its tubes.
+pub struct Session {
+    shared: Arc<SharedServer>,
+    using: QueueName,
+    watching: QueueSet,
+    // Jobs are only held while still reserved with the same deadline, as a job
+    // that timed out may have been reserved by another client since.
+    reserved: BTreeMap<JobId, Instant>, // job ID -> deadline when reserved
+    headers: bool,  // whether the client has sent enable-headers
+    unit: TimeUnit, // milliseconds once the client sends enable-milliseconds
+    hasher: RandomState,
+    producer: bool, // whether the client has put a job
+    worker: bool,   // whether the client has reserved a job
+}
+
+impl Session {
+    /// Returns the name of the tube this client uses.
+    #[must_use]
+    pub fn using(&self) -> &[u8] {
+        &self.using.0
+    }
+
+    /// Returns the tube names and patterns this client watches, in the order
+    /// they were watched.
+    #[must_use]
+    pub fn watching(&self) -> Vec<Vec<u8>> {
+        self.watching.list()
+    }
+
+    /// Returns the IDs of the jobs this client holds, forgetting any it has
+    /// lost to a timeout.
+    pub fn reserved(&mut self) -> BTreeSet<u64> {
+        let server = self.shared.lock();
+        self.reserved
+            .retain(|&id, &mut deadline| is_held(&server, id, deadline));
+
+        self.reserved.keys().map(|id| id.0.get()).collect()
+    }
+
+    /// Returns the largest job data that may follow a command in the put
+    /// family, so that larger data can be refused as soon as the command is
+    /// read. For `complete`, this is the limit of the tube the result goes to.
+    #[must_use]
+    pub fn max_job_size(&self, cmd: &Command) -> u32 {
+        let server = self.shared.lock();
+        let qn = match cmd {
+            Command::Complete { id, .. } => job_id(*id)
+                .and_then(|id| server.jobs.get(&id))
+                .map(|(qn, job)| match &job.reply_tube {
+                    Some(reply_tube) => QueueName(reply_tube.clone()),
+                    None => qn.clone(),
+                }),
+            _ => None,
+        };
+
+        server.queue_max_job_size(qn.as_ref().unwrap_or(&self.using))
+    }
+
+    /// Carries out a command, given the job data that followed it, if any,
+    /// returning the responses to send. Commands that wait, such as `reserve`,
+    /// are cancelled by dropping the returned future.
+    pub async fn handle(
+        &mut self,
+        cmd: Command,
+        data: Vec<u8>,
+    ) -> Vec<Response> {
+        self.count_command(&cmd);
+
+        let resps = match cmd {
+            Command::Reserve => self.reserve(None).await,
+            Command::ReserveWithTimeout { timeout } => {
+                self.reserve(Some(self.unit.duration(timeout))).await
+            },
+            Command::ReserveBatch { count, timeout } => {
+                self.reserve_batch(count, self.unit.duration(timeout)).await
+            },
+            Command::GetResult { id, timeout } => {
+                self.get_result(id, self.unit.duration(timeout)).await
+            },
+            cmd => self.handle_now(cmd, data),
+        };
+        self.shared.changed();
+
+        resps
+    }
+
+    /// Carries out a command that doesn't wait.
+    #[allow(clippy::too_many_lines)]
+    fn handle_now(&mut self, cmd: Command, data: Vec<u8>) -> Vec<Response> {
+        let shared = Arc::clone(&self.shared);
+        let mut server = shared.lock();
+        let using = self.using.clone();
+
+        let resp = match cmd {
+            Command::Use { tube } => {
+                self.using = QueueName(tube.clone());
+                Response::Using { tube }
+            },
+            Command::ListTubeUsed => Response::Using { tube: using.0 },
+            Command::Watch { tube } => {
+                let count =
+                    server.watch(&mut self.watching, QueuePattern(tube));
+                Response::Watching {
+                    count: u32::try_from(count).unwrap_or(u32::MAX),
+                }
+            },
+            Command::Ignore { tube } => {
+                match server.ignore(&mut self.watching, &QueuePattern(tube)) {
+                    Some(count) => Response::Watching {
+                        count: u32::try_from(count).unwrap_or(u32::MAX),
+                    },
+                    None => Response::NotIgnored,
+                }
+            },
+            Command::ListTubesWatched => match self.watching.mode {
+                ReserveMode::Priority => Response::OkListTubes {
+                    tubes: self.watching.list(),
+                },
+                ReserveMode::Ordered => Response::OkWatchList {
+                    data: WatchList {
+                        mode: self.watching.mode,
+                        tubes: self.watching.list(),
+                    },
+                },
+            },
+            Command::ReserveMode { mode } => {
+                server.set_reserve_mode(&mut self.watching, mode);
+                Response::ReserveModeSet { mode }
+            },
+            Command::EnableHeaders => {
+                self.headers = true;
+                Response::HeadersEnabled
+            },
+            Command::EnableMilliseconds => {
+                self.unit = TimeUnit::Milliseconds;
+                Response::MillisecondsEnabled
+            },
+
+            Command::Put {
+                pri, delay, ttr, ..
+            } => inserted(server.put_timed(
+                using,
+                pri.into(),
+                self.unit.duration(delay),
+                self.unit.duration(ttr),
+                data,
+            )),
+            Command::PutUnique {
+                key,
+                pri,
+                delay,
+                ttr,
+                ..
+            } => inserted(server.put_unique(
+                using,
+                key,
+                pri.into(),
+                self.unit.duration(delay),
+                self.unit.duration(ttr),
+                data,
+            )),
+            Command::PutWithHeaders {
+                pri,
+                delay,
+                ttr,
+                headers,
+                ..
+            } => inserted(server.put_with_headers(
+                using,
+                &headers,
+                pri.into(),
+                self.unit.duration(delay),
+                self.unit.duration(ttr),
+                data,
+            )),
+            Command::PutWithTtl {
+                pri,
+                delay,
+                ttr,
+                ttl,
+                ..
+            } => inserted(server.put_with_ttl(
+                using,
+                pri.into(),
+                self.unit.duration(delay),
+                self.unit.duration(ttr),
+                self.unit.duration(ttl),
+                data,
+            )),
+            Command::PutWithReply {
+                reply_tube,
+                pri,
+                delay,
+                ttr,
+                ..
+            } => inserted(server.put_with_reply(
+                using,
+                &QueueName(reply_tube),
+                pri.into(),
+                self.unit.duration(delay),
+                self.unit.duration(ttr),
+                data,
+            )),
+            Command::PutAt {
+                run_at, pri, ttr, ..
+            } => {
+                let run_at = UNIX_EPOCH
+                    .checked_add(Duration::from_secs(run_at))
+                    .unwrap_or(UNIX_EPOCH);
+                inserted(server.put_at(
+                    using,
+                    pri.into(),
+                    run_at,
+                    self.unit.duration(ttr),
+                    data,
+                    SystemTime::now(),
+                ))
+            },
+            Command::PutAfter {
+                pri,
+                delay,
+                ttr,
+                after,
+                ..
+            } => {
+                match after.into_iter().map(job_id).collect::<Option<Vec<_>>>()
+                {
+                    Some(after) => inserted(server.put_after(
+                        using,
+                        &after,
+                        pri.into(),
+                        self.unit.duration(delay),
+                        self.unit.duration(ttr),
+                        data,
+                    )),
+                    None => Response::NotFound,
+                }
+            },
+            Command::Reschedule { id, run_at } => {
+                let run_at = UNIX_EPOCH
+                    .checked_add(Duration::from_secs(run_at))
+                    .unwrap_or(UNIX_EPOCH);
+                let now = SystemTime::now();
+                if job_id(id)
+                    .is_some_and(|id| server.reschedule(id, run_at, now))
+                {
+                    Response::Rescheduled
+                } else {
+                    Response::NotFound
+                }
+            },
+            Command::Schedule {
+                name,
+                pri,
+                ttr,
+                cron,
+                ..
+            } => {
+                let template = RecurringJob::new(
+                    using,
+                    pri.into(),
+                    self.unit.duration(ttr),
+                    data,
+                    cron,
+                    SystemTime::now(),
+                );
+                match server.schedule(name, template) {
+                    Ok(()) => Response::Scheduled,
+                    Err(error) => put_error(error),
+                }
+            },
+            Command::Unschedule { name } => {
+                if server.unschedule(&name) {
+                    Response::Unscheduled
+                } else {
+                    Response::NotFound
+                }
+            },
+            Command::ListSchedules => Response::OkListSchedules {
+                schedules: server.list_schedules(self.unit),
+            },
+
+            Command::ReserveJob { id } => {
+                let Some(id) = job_id(id) else {
+                    return vec![Response::NotFound];
+                };
+                return match server.reserve_by_id(id) {
+                    Some(job) => {
+                        self.hold(id, job);
+                        self.send_job(id, job)
+                    },
+                    None => vec![Response::NotFound],
+                };
+            },
+            Command::Peek { id } => {
+                return self.send_found(&server, job_id(id));
+            },
+            Command::PeekReady => {
+                let id =
+                    server.queues.get(&using).and_then(TubeState::peek_ready);
+                return self.send_found(&server, id);
+            },
+            Command::PeekDelayed => {
+                let id =
+                    server.queues.get(&using).and_then(TubeState::peek_delayed);
+                return self.send_found(&server, id);
+            },
+            Command::PeekBuried => {
+                let id =
+                    server.queues.get(&using).and_then(TubeState::peek_buried);
+                return self.send_found(&server, id);
+            },
+            Command::Release { id, pri, delay } => {
+                match self.held(&server, id) {
+                    Some(id)
+                        if server.release_timed(
+                            id,
+                            pri.into(),
+                            self.unit.duration(delay),
+                        ) =>
+                    {
+                        self.reserved.remove(&id);
+                        Response::Released
+                    },
+                    _ => Response::NotFound,
+                }
+            },
+            Command::Touch { id } => match self.held(&server, id) {
+                Some(id) => match server.touch(id) {
+                    Some(deadline) => {
+                        self.reserved.insert(id, deadline);
+                        Response::Touched
+                    },
+                    None => Response::NotFound,
+                },
+                None => Response::NotFound,
+            },
+            Command::Delete { id } => {
+                // Jobs reserved by other clients can't be deleted.
+                let id = job_id(id).filter(|&id| {
+                    self.held(&server, id.0.get()).is_some()
+                        || server.jobs.get(&id).is_some_and(|(_, job)| {
+                            !matches!(job.state, JobState::Reserved { .. })
+                        })
+                });
+                match id {
+                    Some(id) if server.delete(id) => {
+                        self.reserved.remove(&id);
+                        Response::Deleted
+                    },
+                    _ => Response::NotFound,
+                }
+            },
+            Command::Bury { id, pri } => match self.held(&server, id) {
+                Some(id) if server.bury(id, pri.into()) => {
+                    self.reserved.remove(&id);
+                    Response::Buried
+                },
+                _ => Response::NotFound,
+            },
+            // The job is only forgotten once completed, as it's still held if
+            // its result is refused.
+            Command::Complete { id, .. } => match self.held(&server, id) {
+                Some(id) => match server.complete(id, data) {
+                    Ok(()) => {
+                        self.reserved.remove(&id);
+                        Response::Completed
+                    },
+                    Err(error) => put_error(error),
+                },
+                None => Response::NotFound,
+            },
+            Command::Kick { bound } => Response::KickedCount {
+                count: server.kick_jobs(&using, bound),
+            },
+            Command::KickJob { id } => {
+                if job_id(id).is_some_and(|id| server.kick(id)) {
+                    Response::Kicked
+                } else {
+                    Response::NotFound
+                }
+            },
+            Command::SetPri { id, pri } => {
+                if job_id(id).is_some_and(|id| server.set_pri(id, pri.into())) {
+                    Response::PriSet
+                } else {
+                    Response::NotFound
+                }
+            },
+            Command::MoveJob { id, tube } => {
+                let to = QueueName(tube);
+                if job_id(id).is_some_and(|id| server.move_job(id, to)) {
+                    Response::Moved
+                } else {
+                    Response::NotFound
+                }
+            },
+            Command::MoveJobs { tube, bound } => Response::MovedCount {
+                count: server.move_jobs(&using, &QueueName(tube), bound),
+            },
+
+            Command::StatsJob { id } => {
+                match job_id(id).and_then(|id| server.stats_job(id, self.unit))
+                {
+                    Some(data) => Response::OkStatsJob { data },
+                    None => Response::NotFound,
+                }
+            },
+            Command::StatsTube { tube } => {
+                match server.stats_tube(&QueueName(tube)) {
+                    Some(data) => Response::OkStatsTube { data },
+                    None => Response::NotFound,
+                }
+            },
+            Command::StatsServer => Response::OkStats {
+                data: server.stats_server(),
+            },
+            Command::ListTubes => Response::OkListTubes {
+                tubes: server.queues.keys().map(|qn| qn.0.clone()).collect(),
+            },
+            Command::PauseTube { tube, delay } => {
+                if server.pause(&QueueName(tube), self.unit.duration(delay)) {
+                    Response::Paused
+                } else {
+                    Response::NotFound
+                }
+            },
+            Command::ConfigTube { tube, setting } => {
+                server.configure_tube(QueueName(tube), setting);
+                Response::Configured
+            },
+            Command::Subscribe { tube, subscriber } => {
+                server.subscribe(QueueName(tube), QueueName(subscriber));
+                Response::Subscribed
+            },
+            Command::Unsubscribe { tube, subscriber } => {
+                if server.unsubscribe(&QueueName(tube), &QueueName(subscriber))
+                {
+                    Response::Unsubscribed
+                } else {
+                    Response::NotFound
+                }
+            },
+
+            // These are handled by handle(), or by the connection itself.
+            Command::Reserve
+            | Command::ReserveWithTimeout { .. }
+            | Command::ReserveBatch { .. }
+            | Command::GetResult { .. }
+            | Command::Quit
+            | Command::ClientName { .. }
+            | Command::ListClients
+            | Command::KillClient { .. }
+            | Command::Capabilities => Response::InternalError,
+        };
+
+        vec![resp]
+    }
+
+    /// Reserves a job from the watched tubes, waiting until one can be
+    /// reserved, or until the timeout passes if one is given.
+    async fn reserve(&mut self, timeout: Option<Duration>) -> Vec<Response> {
+        let deadline = timeout.and_then(|t| Instant::now().checked_add(t));
+        let mut waiting = None;
+
+        loop {
+            let shared = Arc::clone(&self.shared);
+            let changed = shared.jobs_changed.notified();
+            let throttled = {
+                let mut server = shared.lock();
+                if let Some(id) =
+                    server.reserve_next(&self.watching, &self.hasher)
+                {
+                    // Panic safety: the job was reserved just above.
+                    let (_, job) = &server.jobs[&id];
+                    self.hold(id, job);
+                    return self.send_job(id, job);
+                }
+                // A reserve held back by rate limits counts once, however
+                // often it retries.
+                if waiting.is_none() {
+                    server.count_throttled(&self.watching);
+                }
+                server.throttled_until(&self.watching)
+            };
+
+            // Tubes at their rate limit don't notify as tokens refill, so
+            // retry once one can next be reserved from.
+            waiting.get_or_insert_with(|| Waiting::new(&shared));
+            select! {
+                () = changed => {},
+                () = sleep_until_some(throttled) => {},
+                () = sleep_until_some(deadline) => {
+                    return vec![Response::TimedOut];
+                },
+            }
+        }
+    }
+
+    /// Reserves up to `count` jobs from the watched tubes, waiting until at
+    /// least one can be reserved, or until the timeout passes.
+    async fn reserve_batch(
+        &mut self,
+        count: u32,
+        timeout: Duration,
+    ) -> Vec<Response> {
+        let deadline = Instant::now().checked_add(timeout);
+        let mut waiting = None;
+
+        loop {
+            let shared = Arc::clone(&self.shared);
+            let changed = shared.jobs_changed.notified();
+            let throttled = {
+                let mut server = shared.lock();
+                let ids =
+                    server.reserve_batch(&self.watching, count, &self.hasher);
+                if !ids.is_empty() {
+                    let jobs = ids
+                        .into_iter()
+                        .map(|id| {
+                            // Panic safety: the jobs were reserved just above.
+                            let (_, job) = &server.jobs[&id];
+                            self.hold(id, job);
+                            ReservedJob {
+                                id: id.0.get(),
+                                data: Bytes::from(job.data.get().into_owned()),
+                            }
+                        })
+                        .collect();
+                    return vec![Response::ReservedBatch { jobs }];
+                }
+                // A reserve held back by rate limits counts once, however
+                // often it retries.
+                if waiting.is_none() {
+                    server.count_throttled(&self.watching);
+                }
+                server.throttled_until(&self.watching)
+            };
+
+            waiting.get_or_insert_with(|| Waiting::new(&shared));
+            select! {
+                () = changed => {},
+                () = sleep_until_some(throttled) => {},
+                () = sleep_until_some(deadline) => {
+                    return vec![Response::TimedOut];
+                },
+            }
+        }
+    }
+
+    /// Fetches the result of a completed job, waiting until it's completed or
+    /// until the timeout passes.
+    async fn get_result(
+        &mut self,
+        id: u64,
+        timeout: Duration,
+    ) -> Vec<Response> {
+        let Some(id) = job_id(id) else {
+            return vec![Response::NotFound];
+        };
+        let deadline = Instant::now().checked_add(timeout);
+
+        loop {
+            let stored = self.shared.lock().result_stored();
+            let stored = stored.notified();
+            {
+                let mut server = self.shared.lock();
+                if let Some(result) = server.result(id) {
+                    return vec![
+                        Response::JobResult {
+                            id: id.0.get(),
+                            n_bytes: u32::try_from(result.len())
+                                .unwrap_or(u32::MAX),
+                        },
+                        Response::JobChunk(Bytes::copy_from_slice(result)),
+                        Response::JobEnd,
+                    ];
+                }
+                if !server.jobs.contains_key(&id) {
+                    return vec![Response::NotFound];
+                }
+            }
+
+            select! {
+                () = stored => {},
+                () = sleep_until_some(deadline) => {
+                    return vec![Response::TimedOut];
+                },
+            }
+        }
+    }
+
+    /// Counts a command towards the server's statistics, along with this client
+    /// as a producer or worker once it first puts or reserves a job.
+    fn count_command(&mut self, cmd: &Command) {
+        let mut server = self.shared.lock();
+        let stats = &mut server.stats;
+
+        let is_put = matches!(
+            cmd,
+            Command::Put { .. }
+                | Command::PutUnique { .. }
+                | Command::PutWithHeaders { .. }
+                | Command::PutWithTtl { .. }
+                | Command::PutWithReply { .. }
+                | Command::PutAt { .. }
+                | Command::PutAfter { .. }
+        );
+        let is_reserve = matches!(
+            cmd,
+            Command::Reserve
+                | Command::ReserveWithTimeout { .. }
+                | Command::ReserveBatch { .. }
+                | Command::ReserveJob { .. }
+        );
+        if is_put && !self.producer {
+            self.producer = true;
+            stats.current_producers = stats.current_producers.saturating_add(1);
+        }
+        if is_reserve && !self.worker {
+            self.worker = true;
+            stats.current_workers = stats.current_workers.saturating_add(1);
+        }
+
+        let count = match cmd {
+            _ if is_put => &mut stats.cmd_put,
+            Command::Peek { .. } => &mut stats.cmd_peek,
+            Command::PeekReady => &mut stats.cmd_peek_ready,
+            Command::PeekDelayed => &mut stats.cmd_peek_delayed,
+            Command::PeekBuried => &mut stats.cmd_peek_buried,
+            Command::Reserve => &mut stats.cmd_reserve,
+            Command::ReserveWithTimeout { .. } => {
+                &mut stats.cmd_reserve_with_timeout
+            },
+            Command::Touch { .. } => &mut stats.cmd_touch,
+            Command::Use { .. } => &mut stats.cmd_use,
+            Command::Watch { .. } => &mut stats.cmd_watch,
+            Command::Ignore { .. } => &mut stats.cmd_ignore,
+            Command::Delete { .. } => &mut stats.cmd_delete,
+            Command::Release { .. } => &mut stats.cmd_release,
+            Command::Bury { .. } => &mut stats.cmd_bury,
+            Command::Kick { .. } | Command::KickJob { .. } => {
+                &mut stats.cmd_kick
+            },
+            Command::StatsServer => &mut stats.cmd_stats,
+            Command::StatsJob { .. } => &mut stats.cmd_stats_job,
+            Command::StatsTube { .. } => &mut stats.cmd_stats_tube,
+            Command::ListTubes => &mut stats.cmd_list_tubes,
+            Command::ListTubeUsed => &mut stats.cmd_list_tube_used,
+            Command::ListTubesWatched => &mut stats.cmd_list_tubes_watched,
+            Command::PauseTube { .. } => &mut stats.cmd_pause_tube,
+            _ => return,
+        };
+        *count = count.saturating_add(1);
+    }
+
+    /// Records that this client holds a job it's just reserved.
+    fn hold(&mut self, id: JobId, job: &Job) {
+        if let JobState::Reserved { deadline } = job.state {
+            self.reserved.insert(id, deadline);
+        }
+    }
+
+    /// Returns the responses that send a reserved job to this client.
+    fn send_job(&self, id: JobId, job: &Job) -> Vec<Response> {
+        let data = job.data.get();
+
+        vec![
+            Response::ReservedWithHeaders {
+                id: id.0.get(),
+                n_bytes: u32::try_from(data.len()).unwrap_or(u32::MAX),
+                headers: self.headers(job),
+            },
+            Response::JobChunk(Bytes::copy_from_slice(&data)),
+            Response::JobEnd,
+        ]
+    }
+
+    /// Returns the responses that send a peeked job to this client, or
+    /// `NOT_FOUND` if there's no such job.
+    fn send_found(&self, server: &Server, id: Option<JobId>) -> Vec<Response> {
+        let Some((id, (_, job))) =
+            id.and_then(|id| Some((id, server.jobs.get(&id)?)))
+        else {
+            return vec![Response::NotFound];
+        };
+        let data = job.data.get();
+
+        vec![
+            Response::FoundWithHeaders {
+                id: id.0.get(),
+                n_bytes: u32::try_from(data.len()).unwrap_or(u32::MAX),
+                headers: self.headers(job),
+            },
+            Response::JobChunk(Bytes::copy_from_slice(&data)),
+            Response::JobEnd,
+        ]
+    }
+
+    /// Returns a job's headers, if this client has asked to be sent them.
+    fn headers(&self, job: &Job) -> Headers {
+        if self.headers {
+            job.headers.clone()
+        } else {
+            Headers::new()
+        }
+    }
+
+    /// Returns a job's ID if this client holds it. The job is only forgotten
+    /// once the command using it succeeds.
+    fn held(&self, server: &Server, id: u64) -> Option<JobId> {
+        let id = job_id(id)?;
+        let &deadline = self.reserved.get(&id)?;
+
+        is_held(server, id, deadline).then_some(id)
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        let mut server = self.shared.lock();
+        let held: Vec<JobId> = self
+            .reserved
+            .iter()
+            .filter(|&(&id, &deadline)| is_held(&server, id, deadline))
+            .map(|(&id, _)| id)
+            .collect();
+        server.release_disconnected(&held);
+        server.forget_watch_set(&self.watching);
+
+        let stats = &mut server.stats;
+        stats.current_connections = stats.current_connections.saturating_sub(1);
+        if self.producer {
+            stats.current_producers = stats.current_producers.saturating_sub(1);
+        }
+        if self.worker {
+            stats.current_workers = stats.current_workers.saturating_sub(1);
+        }
+        drop(server);
+
+        self.shared.changed();
+    }
+}
+
+/// Counts a client as waiting to reserve a job for as long as it's kept.
+struct Waiting(Arc<SharedServer>);
+
+impl Waiting {
+    fn new(shared: &Arc<SharedServer>) -> Self {
+        let stats = &mut shared.lock().stats;
+        stats.current_waiting = stats.current_waiting.saturating_add(1);
+
+        Self(Arc::clone(shared))
+    }
+}
+
+impl Drop for Waiting {
+    fn drop(&mut self) {
+        let stats = &mut self.0.lock().stats;
+        stats.current_waiting = stats.current_waiting.saturating_sub(1);
+    }
+}
+
+/// Returns true if a job is still reserved with the deadline it was given when
+/// a client reserved it, so is still held by that client.
+fn is_held(server: &Server, id: JobId, deadline: Instant) -> bool {
+    server
+        .jobs
+        .get(&id)
+        .is_some_and(|(_, job)| job.state == JobState::Reserved { deadline })
+}
+
+/// Converts a job ID given by a client, which is never zero for an existing
+/// job.
+fn job_id(id: u64) -> Option<JobId> {
+    NonZeroU64::new(id).map(JobId)
+}
+
+/// Returns the response to a command in the `put` family.
+fn inserted(result: Result<JobId, PutError>) -> Response {
+    match result {
+        Ok(id) => Response::Inserted { id: id.0.get() },
+        Err(error) => put_error(error),
+    }
+}
+
+/// Returns the response to a job that couldn't be put.
+fn put_error(error: PutError) -> Response {
+    match error {
+        PutError::JobTooBig => Response::JobTooBig,
+        PutError::QuotaExceeded => Response::QuotaExceeded,
+        PutError::NotFound => Response::NotFound,
+    }
+}
+
+/// Sleeps until the deadline, if there is one, or otherwise forever.
+async fn sleep_until_some(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::FutureExt;
+
+    use super::*;
+    use crate::types::config::{RequeuePos, TubeSetting};
+    use crate::types::cron::Cron;
+
+    fn put(n_bytes: u32) -> Command {
+        Command::Put {
+            pri: 0,
+            delay: 0,
+            ttr: 60,
+            n_bytes,
+        }
+    }
+
+    fn reserved(id: u64, data: &'static [u8]) -> Vec<Response> {
+        vec![
+            Response::ReservedWithHeaders {
+                id,
+                n_bytes: u32::try_from(data.len()).unwrap(),
+                headers: Headers::new(),
+            },
+            Response::JobChunk(Bytes::from_static(data)),
+            Response::JobEnd,
+        ]
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_session() {
+        let config = Config::from_yaml(
+            "
+tubes:
+  small:
+    max-job-size: 2
+",
+        )
+        .unwrap();
+        let shared = SharedServer::new(&config, 4);
+        let mut producer = shared.session();
+        let mut consumer = shared.session();
+
+        // The server's and tubes' limits are those given.
+        assert_eq!(producer.max_job_size(&put(5)), 4);
+        assert_eq!(
+            producer.handle(put(5), b"large".into()).await,
+            [Response::JobTooBig]
+        );
+        let using = Command::Use {
+            tube: b"small".into(),
+        };
+        producer.handle(using, Vec::new()).await;
+        assert_eq!(producer.max_job_size(&put(3)), 2);
+        assert_eq!(
+            producer.handle(put(3), b"abc".into()).await,
+            [Response::JobTooBig]
+        );
+        assert_eq!(
+            producer.handle(put(2), b"ab".into()).await,
+            [Response::Inserted { id: 1 }]
+        );
+
+        // A waiting reserve completes once a job is put to a watched tube.
+        let watch = Command::Watch {
+            tube: b"small".into(),
+        };
+        assert_eq!(
+            consumer.handle(watch, Vec::new()).await,
+            [Response::Watching { count: 2 }]
+        );
+        assert_eq!(
+            consumer.handle(Command::Reserve, Vec::new()).await,
+            reserved(1, b"ab")
+        );
+        let timeout = Command::ReserveWithTimeout { timeout: 1 };
+        assert_eq!(
+            consumer.handle(timeout.clone(), Vec::new()).await,
+            [Response::TimedOut]
+        );
+
+        let mut reserve = Box::pin(consumer.handle(timeout, Vec::new()));
+        assert!((&mut reserve).now_or_never().is_none());
+        assert_eq!(
+            producer.handle(put(1), b"c".into()).await,
+            [Response::Inserted { id: 2 }]
+        );
+        assert_eq!(reserve.await, reserved(2, b"c"));
+
+        // Jobs are deleted through the server, by the client holding them.
+        let delete = Command::Delete { id: 2 };
+        assert_eq!(
+            producer.handle(delete.clone(), Vec::new()).await,
+            [Response::NotFound]
+        );
+        assert_eq!(
+            consumer.handle(delete.clone(), Vec::new()).await,
+            [Response::Deleted]
+        );
+        assert_eq!(
+            consumer.handle(delete, Vec::new()).await,
+            [Response::NotFound]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_list_tubes_watched() {
+        let shared = SharedServer::new(&Config::default(), 65535);
+        let mut session = shared.session();
+        let watch = Command::Watch {
+            tube: b"other".into(),
+        };
+        session.handle(watch, Vec::new()).await;
+
+        // The watch list is a plain list, as in beanstalkd, unless the client
+        // has asked for the ordered mode.
+        let tubes = vec![b"default".to_vec(), b"other".to_vec()];
+        assert_eq!(
+            session.handle(Command::ListTubesWatched, Vec::new()).await,
+            [Response::OkListTubes {
+                tubes: tubes.clone()
+            }]
+        );
+        let mode = Command::ReserveMode {
+            mode: ReserveMode::Ordered,
+        };
+        session.handle(mode, Vec::new()).await;
+        assert_eq!(
+            session.handle(Command::ListTubesWatched, Vec::new()).await,
+            [Response::OkWatchList {
+                data: WatchList {
+                    mode: ReserveMode::Ordered,
+                    tubes,
+                },
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_complete_refused() {
+        let config = Config::from_yaml(
+            "
+tubes:
+  default:
+    max-job-size: 2
+",
+        )
+        .unwrap();
+        let shared = SharedServer::new(&config, 65535);
+        let mut session = shared.session();
+        session.handle(put(1), b"a".into()).await;
+        session.handle(Command::Reserve, Vec::new()).await;
+
+        // A refused result leaves the job held by the client, which can
+        // complete it with a smaller result.
+        let complete = Command::Complete { id: 1, n_bytes: 3 };
+        assert_eq!(
+            session.handle(complete, b"abc".into()).await,
+            [Response::JobTooBig]
+        );
+        assert_eq!(session.reserved(), [1].into());
+        let complete = Command::Complete { id: 1, n_bytes: 2 };
+        assert_eq!(
+            session.handle(complete, b"ok".into()).await,
+            [Response::Completed]
+        );
+        assert!(session.reserved().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_schedule() {
+        let config = Config::from_yaml(
+            "
+tubes:
+  default:
+    max-job-size: 2
+",
+        )
+        .unwrap();
+        let shared = SharedServer::new(&config, 65535);
+        let mut session = shared.session();
+
+        // Templates too large for the used tube are refused when scheduled.
+        let schedule = |n_bytes| Command::Schedule {
+            name: b"tick".into(),
+            pri: 0,
+            ttr: 60,
+            n_bytes,
+            cron: Cron::parse(b"* * * * *").unwrap(),
+        };
+        assert_eq!(
+            session.handle(schedule(3), b"abc".into()).await,
+            [Response::JobTooBig]
+        );
+        assert_eq!(
+            session.handle(Command::ListSchedules, Vec::new()).await,
+            [Response::OkListSchedules {
+                schedules: Vec::new()
+            }]
+        );
+        assert_eq!(
+            session.handle(schedule(2), b"ab".into()).await,
+            [Response::Scheduled]
+        );
+
+        // Templates are listed until unscheduled.
+        let [Response::OkListSchedules { schedules }] =
+            &session.handle(Command::ListSchedules, Vec::new()).await[..]
+        else {
+            panic!("expected schedules");
+        };
+        assert_eq!(schedules.len(), 1);
+        assert_eq!(schedules[0].name, b"tick");
+        let unschedule = Command::Unschedule {
+            name: b"tick".into(),
+        };
+        assert_eq!(
+            session.handle(unschedule.clone(), Vec::new()).await,
+            [Response::Unscheduled]
+        );
+        assert_eq!(
+            session.handle(unschedule, Vec::new()).await,
+            [Response::NotFound]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_peek() {
+        let shared = SharedServer::new(&Config::default(), 65535);
+        let mut session = shared.session();
+        let headers = Headers::from([(b"k".to_vec(), b"v".to_vec())]);
+        let with_headers = Command::PutWithHeaders {
+            pri: 0,
+            delay: 0,
+            ttr: 60,
+            n_bytes: 1,
+            headers: headers.clone(),
+        };
+        session.handle(with_headers, b"a".into()).await;
+        let delayed = Command::Put {
+            pri: 0,
+            delay: 60,
+            ttr: 60,
+            n_bytes: 1,
+        };
+        session.handle(delayed, b"b".into()).await;
+        session.handle(put(1), b"c".into()).await;
+        session
+            .handle(Command::ReserveJob { id: 3 }, Vec::new())
+            .await;
+        let bury = Command::Bury { id: 3, pri: 0 };
+        session.handle(bury, Vec::new()).await;
+
+        // Jobs are found by ID, or as the next of each state in the used tube,
+        // with their headers only for clients that have asked for them.
+        let found = |id, data: &'static [u8], headers| {
+            vec![
+                Response::FoundWithHeaders {
+                    id,
+                    n_bytes: u32::try_from(data.len()).unwrap(),
+                    headers,
+                },
+                Response::JobChunk(Bytes::from_static(data)),
+                Response::JobEnd,
+            ]
+        };
+        assert_eq!(
+            session.handle(Command::PeekReady, Vec::new()).await,
+            found(1, b"a", Headers::new())
+        );
+        session.handle(Command::EnableHeaders, Vec::new()).await;
+        assert_eq!(
+            session.handle(Command::Peek { id: 1 }, Vec::new()).await,
+            found(1, b"a", headers)
+        );
+        assert_eq!(
+            session.handle(Command::PeekDelayed, Vec::new()).await,
+            found(2, b"b", Headers::new())
+        );
+        assert_eq!(
+            session.handle(Command::PeekBuried, Vec::new()).await,
+            found(3, b"c", Headers::new())
+        );
+        assert_eq!(
+            session.handle(Command::Peek { id: 4 }, Vec::new()).await,
+            [Response::NotFound]
+        );
+
+        let using = Command::Use {
+            tube: b"other".into(),
+        };
+        session.handle(using, Vec::new()).await;
+        for peek in [
+            Command::PeekReady,
+            Command::PeekDelayed,
+            Command::PeekBuried,
+        ] {
+            assert_eq!(
+                session.handle(peek, Vec::new()).await,
+                [Response::NotFound]
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_max_reserved() {
+        let config = Config::from_yaml(
+            "
+tubes:
+  default:
+    max-reserved: 1
+",
+        )
+        .unwrap();
+        let shared = SharedServer::new(&config, 65535);
+        let mut first = shared.session();
+        let mut second = shared.session();
+        first.handle(put(1), b"a".into()).await;
+        first.handle(put(1), b"b".into()).await;
+        first.handle(put(1), b"c".into()).await;
+        assert_eq!(
+            first.handle(Command::Reserve, Vec::new()).await,
+            reserved(1, b"a")
+        );
+
+        // A client waiting on the tube's limit reserves a job once another
+        // client deletes or releases the job it holds.
+        let mut reserve = Box::pin(second.handle(Command::Reserve, Vec::new()));
+        assert!((&mut reserve).now_or_never().is_none());
+        first.handle(Command::Delete { id: 1 }, Vec::new()).await;
+        assert_eq!(reserve.await, reserved(2, b"b"));
+
+        let mut reserve = Box::pin(first.handle(Command::Reserve, Vec::new()));
+        assert!((&mut reserve).now_or_never().is_none());
+        let release = Command::Release {
+            id: 2,
+            pri: 0,
+            delay: 0,
+        };
+        second.handle(release, Vec::new()).await;
+        assert_eq!(reserve.await, reserved(3, b"c"));
+    }
+
+    #[tokio::test]
+    async fn test_kick() {
+        let shared = SharedServer::new(&Config::default(), 65535);
+        let mut session = shared.session();
+        let delayed = Command::Put {
+            pri: 0,
+            delay: 60,
+            ttr: 60,
+            n_bytes: 1,
+        };
+        session.handle(delayed, b"a".into()).await;
+        session.handle(put(1), b"b".into()).await;
+        session.handle(Command::Reserve, Vec::new()).await;
+        let bury = Command::Bury { id: 2, pri: 0 };
+        session.handle(bury.clone(), Vec::new()).await;
+
+        // Buried jobs are kicked before any delayed jobs.
+        let kick = Command::Kick { bound: 10 };
+        assert_eq!(
+            session.handle(kick.clone(), Vec::new()).await,
+            [Response::KickedCount { count: 1 }]
+        );
+        assert_eq!(
+            session.handle(kick.clone(), Vec::new()).await,
+            [Response::KickedCount { count: 1 }]
+        );
+        assert_eq!(
+            session.handle(kick, Vec::new()).await,
+            [Response::KickedCount { count: 0 }]
+        );
+
+        // Only buried and delayed jobs can be kicked by ID.
+        let kick_job = Command::KickJob { id: 2 };
+        assert_eq!(
+            session.handle(kick_job.clone(), Vec::new()).await,
+            [Response::NotFound]
+        );
+        session
+            .handle(Command::ReserveJob { id: 2 }, Vec::new())
+            .await;
+        session.handle(bury, Vec::new()).await;
+        assert_eq!(
+            session.handle(kick_job, Vec::new()).await,
+            [Response::Kicked]
+        );
+        let [Response::OkStatsJob { data }] = &session
+            .handle(Command::StatsJob { id: 2 }, Vec::new())
+            .await[..]
+        else {
+            panic!("expected job stats");
+        };
+        assert_eq!(data.kicks, 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_touch() {
+        let shared = SharedServer::new(&Config::default(), 65535);
+        let cancel = CancellationToken::new();
+        let timers = tokio::spawn({
+            let shared = Arc::clone(&shared);
+            let cancel = cancel.clone();
+            async move { shared.run_timers(cancel).await }
+        });
+        let mut first = shared.session();
+        let mut second = shared.session();
+        let short_ttr = Command::Put {
+            pri: 0,
+            delay: 0,
+            ttr: 2,
+            n_bytes: 1,
+        };
+        first.handle(short_ttr, b"a".into()).await;
+        first.handle(Command::Reserve, Vec::new()).await;
+
+        // Touching a held job restarts its TTR, and keeps it held.
+        let touch = Command::Touch { id: 1 };
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert_eq!(
+            second.handle(touch.clone(), Vec::new()).await,
+            [Response::NotFound]
+        );
+        assert_eq!(
+            first.handle(touch.clone(), Vec::new()).await,
+            [Response::Touched]
+        );
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        assert_eq!(first.reserved(), BTreeSet::from([1]));
+
+        let start = Instant::now();
+        assert_eq!(
+            second.handle(Command::Reserve, Vec::new()).await,
+            reserved(1, b"a")
+        );
+        assert_eq!(start.elapsed(), Duration::from_millis(500));
+        assert_eq!(first.handle(touch, Vec::new()).await, [Response::NotFound]);
+
+        cancel.cancel();
+        timers.await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_pause_tube() {
+        let shared = SharedServer::new(&Config::default(), 65535);
+        let cancel = CancellationToken::new();
+        let timers = tokio::spawn({
+            let shared = Arc::clone(&shared);
+            let cancel = cancel.clone();
+            async move { shared.run_timers(cancel).await }
+        });
+        let mut session = shared.session();
+        session.handle(put(1), b"a".into()).await;
+
+        // Jobs in a paused tube are reserved once the pause ends.
+        let pause = Command::PauseTube {
+            tube: Server::DEFAULT_QUEUE.into(),
+            delay: 5,
+        };
+        assert_eq!(session.handle(pause, Vec::new()).await, [Response::Paused]);
+        let start = Instant::now();
+        assert_eq!(
+            session.handle(Command::Reserve, Vec::new()).await,
+            reserved(1, b"a")
+        );
+        assert_eq!(start.elapsed(), Duration::from_secs(5));
+
+        let stats = Command::StatsTube {
+            tube: Server::DEFAULT_QUEUE.into(),
+        };
+        let [Response::OkStatsTube { data }] =
+            &session.handle(stats, Vec::new()).await[..]
+        else {
+            panic!("expected tube stats");
+        };
+        assert_eq!(data.ts.pause, 5);
+        assert_eq!(data.ts.cmd_pause_tube, 1);
+        assert_eq!(data.pause_time_left, 0);
+
+        let missing = Command::PauseTube {
+            tube: b"missing".into(),
+            delay: 5,
+        };
+        assert_eq!(
+            session.handle(missing, Vec::new()).await,
+            [Response::NotFound]
+        );
+
+        cancel.cancel();
+        timers.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_stats() {
+        let shared = SharedServer::new(&Config::default(), 65535);
+        let mut producer = shared.session();
+        let mut consumer = shared.session();
+        let using = Command::Use {
+            tube: b"other".into(),
+        };
+        producer.handle(using, Vec::new()).await;
+        producer.handle(put(1), b"a".into()).await;
+        assert_eq!(
+            producer.handle(Command::ListTubes, Vec::new()).await,
+            [Response::OkListTubes {
+                tubes: vec![b"default".to_vec(), b"other".to_vec()],
+            }]
+        );
+
+        // A client waiting to reserve is counted until it gets a job.
+        let mut reserve =
+            Box::pin(consumer.handle(Command::Reserve, Vec::new()));
+        assert!((&mut reserve).now_or_never().is_none());
+        let stats = |session: &Session| {
+            let server = session.shared.lock();
+            server.stats_server()
+        };
+        assert_eq!(stats(&producer).current_waiting, 1);
+        drop(reserve);
+        assert_eq!(stats(&producer).current_waiting, 0);
+        let watch = Command::Watch {
+            tube: b"other".into(),
+        };
+        consumer.handle(watch, Vec::new()).await;
+        consumer.handle(Command::Reserve, Vec::new()).await;
+
+        let [Response::OkStats { data }] =
+            &producer.handle(Command::StatsServer, Vec::new()).await[..]
+        else {
+            panic!("expected server stats");
+        };
+        assert_eq!(data.current_jobs_reserved, 1);
+        assert_eq!(data.total_jobs, 1);
+        assert_eq!(data.current_tubes, 2);
+        assert_eq!(data.cmd_put, 1);
+        assert_eq!(data.cmd_reserve, 2);
+        assert_eq!(data.cmd_list_tubes, 1);
+        assert_eq!(data.cmd_stats, 1);
+        assert_eq!(data.current_connections, 2);
+        assert_eq!(data.total_connections, 2);
+        assert_eq!(data.current_producers, 1);
+        assert_eq!(data.current_workers, 1);
+        assert_eq!(data.max_job_size, 65535);
+        assert_eq!(data.version, env!("CARGO_PKG_VERSION"));
+
+        drop(consumer);
+        let data = stats(&producer);
+        assert_eq!(data.current_jobs_ready, 1);
+        assert_eq!(data.current_connections, 1);
+        assert_eq!(data.total_connections, 2);
+        assert_eq!(data.current_workers, 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_run_timers() {
+        let shared = SharedServer::new(&Config::default(), 65535);
+        let cancel = CancellationToken::new();
+        let timers = tokio::spawn({
+            let shared = Arc::clone(&shared);
+            let cancel = cancel.clone();
+            async move { shared.run_timers(cancel).await }
+        });
+        let mut session = shared.session();
+
+        // A delayed job is reserved once its delay passes.
+        let delayed = Command::Put {
+            pri: 0,
+            delay: 5,
+            ttr: 60,
+            n_bytes: 1,
+        };
+        session.handle(delayed, b"a".into()).await;
+        let start = Instant::now();
+        assert_eq!(
+            session.handle(Command::Reserve, Vec::new()).await,
+            reserved(1, b"a")
+        );
+        assert_eq!(start.elapsed(), Duration::from_secs(5));
+
+        cancel.cancel();
+        timers.await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_compression() {
+        let config = Config::from_yaml("compress-threshold: 100").unwrap();
+        let shared = SharedServer::new(&config, 65535);
+        let mut session = shared.session();
+
+        // Large job data is stored compressed, but sent to clients as put.
+        session.handle(put(200), [0; 200].into()).await;
+        let stats = Command::StatsTube {
+            tube: Server::DEFAULT_QUEUE.into(),
+        };
+        let [Response::OkStatsTube { data }] =
+            &session.handle(stats, Vec::new()).await[..]
+        else {
+            panic!("expected tube stats");
+        };
+        assert_eq!(data.ts.current_bytes, 200);
+        assert!(data.ts.current_stored_bytes < 200);
+        assert_eq!(
+            session.handle(Command::Peek { id: 1 }, Vec::new()).await,
+            [
+                Response::FoundWithHeaders {
+                    id: 1,
+                    n_bytes: 200,
+                    headers: Headers::new(),
+                },
+                Response::JobChunk(Bytes::from_static(&[0; 200])),
+                Response::JobEnd,
+            ]
+        );
+        assert_eq!(
+            session.handle(Command::Reserve, Vec::new()).await,
+            reserved(1, &[0; 200])
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_reserve_rate() {
+        let config = Config::from_yaml(
+            "
+tubes:
+  default:
+    reserve-rate: 1
+",
+        )
+        .unwrap();
+        let shared = SharedServer::new(&config, 65535);
+        let mut session = shared.session();
+        session.handle(put(1), b"a".into()).await;
+        session.handle(put(1), b"b".into()).await;
+
+        // A waiting reserve completes once the tube's rate limit allows.
+        assert_eq!(
+            session.handle(Command::Reserve, Vec::new()).await,
+            reserved(1, b"a")
+        );
+        let start = Instant::now();
+        assert_eq!(
+            session.handle(Command::Reserve, Vec::new()).await,
+            reserved(2, b"b")
+        );
+        assert_eq!(start.elapsed(), Duration::from_secs(1));
+
+        // The reserve that waited is counted as throttled once.
+        let stats = Command::StatsTube {
+            tube: Server::DEFAULT_QUEUE.into(),
+        };
+        let [Response::OkStatsTube { data }] =
+            &session.handle(stats, Vec::new()).await[..]
+        else {
+            panic!("expected tube stats");
+        };
+        assert_eq!(data.ts.reserves_throttled, 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_milliseconds() {
+        let shared = SharedServer::new(&Config::default(), 65535);
+        let cancel = CancellationToken::new();
+        let timers = tokio::spawn({
+            let shared = Arc::clone(&shared);
+            let cancel = cancel.clone();
+            async move { shared.run_timers(cancel).await }
+        });
+        let mut session = shared.session();
+        assert_eq!(
+            session
+                .handle(Command::EnableMilliseconds, Vec::new())
+                .await,
+            [Response::MillisecondsEnabled]
+        );
+
+        // Reserve timeouts and put delays are in milliseconds.
+        let start = Instant::now();
+        let timeout = Command::ReserveWithTimeout { timeout: 100 };
+        assert_eq!(
+            session.handle(timeout, Vec::new()).await,
+            [Response::TimedOut]
+        );
+        assert_eq!(start.elapsed(), Duration::from_millis(100));
+
+        let delayed = Command::Put {
+            pri: 0,
+            delay: 250,
+            ttr: 1000,
+            n_bytes: 1,
+        };
+        session.handle(delayed, b"a".into()).await;
+        let start = Instant::now();
+        assert_eq!(
+            session.handle(Command::Reserve, Vec::new()).await,
+            reserved(1, b"a")
+        );
+        assert_eq!(start.elapsed(), Duration::from_millis(250));
+
+        // As are those of the other put commands, along with TTRs and TTLs.
+        let unique = Command::PutUnique {
+            key: b"k".into(),
+            pri: 0,
+            delay: 150,
+            ttr: 1500,
+            n_bytes: 1,
+        };
+        session.handle(unique, b"b".into()).await;
+        let start = Instant::now();
+        assert_eq!(
+            session.handle(Command::Reserve, Vec::new()).await,
+            reserved(2, b"b")
+        );
+        assert_eq!(start.elapsed(), Duration::from_millis(150));
+        let [Response::OkStatsJob { data }] = &session
+            .handle(Command::StatsJob { id: 2 }, Vec::new())
+            .await[..]
+        else {
+            panic!("expected job stats");
+        };
+        assert_eq!(data.ttr, 1500);
+
+        let expiring = Command::PutWithTtl {
+            pri: 0,
+            delay: 0,
+            ttr: 1000,
+            ttl: 300,
+            n_bytes: 1,
+        };
+        session.handle(expiring, b"c".into()).await;
+        tokio::time::sleep(Duration::from_millis(350)).await;
+        let timeout = Command::ReserveWithTimeout { timeout: 0 };
+        assert_eq!(
+            session.handle(timeout, Vec::new()).await,
+            [Response::TimedOut]
+        );
+
+        // As are pauses.
+        let pause = Command::PauseTube {
+            tube: Server::DEFAULT_QUEUE.into(),
+            delay: 200,
+        };
+        session.handle(pause, Vec::new()).await;
+        session.handle(put(1), b"d".into()).await;
+        let start = Instant::now();
+        assert_eq!(
+            session.handle(Command::Reserve, Vec::new()).await,
+            reserved(4, b"d")
+        );
+        assert_eq!(start.elapsed(), Duration::from_millis(200));
+
+        cancel.cancel();
+        timers.await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_held_jobs() {
+        let shared = SharedServer::new(&Config::default(), 65535);
+        let cancel = CancellationToken::new();
+        let timers = tokio::spawn({
+            let shared = Arc::clone(&shared);
+            let cancel = cancel.clone();
+            async move { shared.run_timers(cancel).await }
+        });
+        let mut first = shared.session();
+        let mut second = shared.session();
+
+        let short_ttr = Command::Put {
+            pri: 0,
+            delay: 0,
+            ttr: 1,
+            n_bytes: 1,
+        };
+        first.handle(short_ttr, b"a".into()).await;
+        assert_eq!(
+            first.handle(Command::Reserve, Vec::new()).await,
+            reserved(1, b"a")
+        );
+        assert_eq!(first.reserved(), BTreeSet::from([1]));
+
+        // Other clients can't release or delete a held job.
+        let release = Command::Release {
+            id: 1,
+            pri: 0,
+            delay: 0,
+        };
+        assert_eq!(
+            second.handle(release.clone(), Vec::new()).await,
+            [Response::NotFound]
+        );
+        assert_eq!(
+            second.handle(Command::Delete { id: 1 }, Vec::new()).await,
+            [Response::NotFound]
+        );
+
+        // A job that times out is no longer held, so isn't released when its
+        // first holder disconnects after another client has reserved it.
+        assert_eq!(
+            second.handle(Command::Reserve, Vec::new()).await,
+            reserved(1, b"a")
+        );
+        assert!(first.reserved().is_empty());
+        drop(first);
+        assert_eq!(second.reserved(), BTreeSet::from([1]));
+        assert_eq!(
+            second.handle(release, Vec::new()).await,
+            [Response::Released]
+        );
+
+        // Jobs held by a client are ready again once it disconnects.
+        let mut third = shared.session();
+        assert_eq!(
+            second.handle(Command::Reserve, Vec::new()).await,
+            reserved(1, b"a")
+        );
+        drop(second);
+        let timeout = Command::ReserveWithTimeout { timeout: 0 };
+        assert_eq!(third.handle(timeout, Vec::new()).await, reserved(1, b"a"));
+
+        cancel.cancel();
+        timers.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_set_pri() {
+        let shared = SharedServer::new(&Config::default(), 65535);
+        let mut session = shared.session();
+        session.handle(put(1), b"a".into()).await;
+        session.handle(put(1), b"b".into()).await;
+
+        // A ready job's new priority decides when it's reserved.
+        let set_pri = Command::SetPri { id: 1, pri: 5 };
+        assert_eq!(
+            session.handle(set_pri, Vec::new()).await,
+            [Response::PriSet]
+        );
+        assert_eq!(
+            session.handle(Command::Reserve, Vec::new()).await,
+            reserved(2, b"b")
+        );
+        let [Response::OkStatsJob { data }] = &session
+            .handle(Command::StatsJob { id: 1 }, Vec::new())
+            .await[..]
+        else {
+            panic!("expected job stats");
+        };
+        assert_eq!(data.pri, 5);
+
+        let set_pri = Command::SetPri { id: 3, pri: 0 };
+        assert_eq!(
+            session.handle(set_pri, Vec::new()).await,
+            [Response::NotFound]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_move_jobs() {
+        let shared = SharedServer::new(&Config::default(), 65535);
+        let mut session = shared.session();
+        for data in [b"a", b"b", b"c"] {
+            session.handle(put(1), data.into()).await;
+        }
+
+        // Jobs move from the used tube to another, singly or in bulk.
+        let move_job = Command::MoveJob {
+            id: 1,
+            tube: b"other".into(),
+        };
+        assert_eq!(
+            session.handle(move_job, Vec::new()).await,
+            [Response::Moved]
+        );
+        let move_jobs = Command::MoveJobs {
+            tube: b"other".into(),
+            bound: 1,
+        };
+        assert_eq!(
+            session.handle(move_jobs, Vec::new()).await,
+            [Response::MovedCount { count: 1 }]
+        );
+        let [Response::OkStatsJob { data }] = &session
+            .handle(Command::StatsJob { id: 2 }, Vec::new())
+            .await[..]
+        else {
+            panic!("expected job stats");
+        };
+        assert_eq!(data.tube, b"other");
+
+        let watch = Command::Watch {
+            tube: b"other".into(),
+        };
+        session.handle(watch, Vec::new()).await;
+        let ignore = Command::Ignore {
+            tube: Server::DEFAULT_QUEUE.into(),
+        };
+        session.handle(ignore, Vec::new()).await;
+        assert_eq!(
+            session.handle(Command::Reserve, Vec::new()).await,
+            reserved(1, b"a")
+        );
+        assert_eq!(
+            session.handle(Command::Reserve, Vec::new()).await,
+            reserved(2, b"b")
+        );
+
+        let move_job = Command::MoveJob {
+            id: 4,
+            tube: b"other".into(),
+        };
+        assert_eq!(
+            session.handle(move_job, Vec::new()).await,
+            [Response::NotFound]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_put_unique() {
+        let shared = SharedServer::new(&Config::default(), 65535);
+        let mut session = shared.session();
+        let unique = |key: &[u8]| Command::PutUnique {
+            key: key.to_vec(),
+            pri: 0,
+            delay: 0,
+            ttr: 60,
+            n_bytes: 1,
+        };
+
+        // A repeated key returns the ID of the job first put with it.
+        assert_eq!(
+            session.handle(unique(b"k"), b"a".into()).await,
+            [Response::Inserted { id: 1 }]
+        );
+        assert_eq!(
+            session.handle(unique(b"k"), b"b".into()).await,
+            [Response::Inserted { id: 1 }]
+        );
+        assert_eq!(
+            session.handle(unique(b"j"), b"c".into()).await,
+            [Response::Inserted { id: 2 }]
+        );
+
+        // Keys are per tube.
+        let using = Command::Use {
+            tube: b"other".into(),
+        };
+        session.handle(using, Vec::new()).await;
+        assert_eq!(
+            session.handle(unique(b"k"), b"d".into()).await,
+            [Response::Inserted { id: 3 }]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_put_after() {
+        let shared = SharedServer::new(&Config::default(), 65535);
+        let mut session = shared.session();
+        session.handle(put(1), b"a".into()).await;
+        let after = |after| Command::PutAfter {
+            pri: 0,
+            delay: 0,
+            ttr: 60,
+            n_bytes: 1,
+            after,
+        };
+
+        // A dependent job becomes ready once its prerequisite is deleted.
+        assert_eq!(
+            session.handle(after(vec![1]), b"b".into()).await,
+            [Response::Inserted { id: 2 }]
+        );
+        assert_eq!(
+            session.handle(after(vec![1, 3]), b"c".into()).await,
+            [Response::NotFound]
+        );
+        assert_eq!(
+            session.handle(Command::Reserve, Vec::new()).await,
+            reserved(1, b"a")
+        );
+        let timeout = Command::ReserveWithTimeout { timeout: 0 };
+        assert_eq!(
+            session.handle(timeout.clone(), Vec::new()).await,
+            [Response::TimedOut]
+        );
+        session.handle(Command::Delete { id: 1 }, Vec::new()).await;
+        assert_eq!(
+            session.handle(timeout, Vec::new()).await,
+            reserved(2, b"b")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_dead_letter() {
+        let shared = SharedServer::new(&Config::default(), 65535);
+        let mut session = shared.session();
+        for setting in [
+            TubeSetting::DeadLetterReleases(Some(1)),
+            TubeSetting::DeadLetterTube(Some(b"dead".into())),
+        ] {
+            let config = Command::ConfigTube {
+                tube: Server::DEFAULT_QUEUE.into(),
+                setting,
+            };
+            assert_eq!(
+                session.handle(config, Vec::new()).await,
+                [Response::Configured]
+            );
+        }
+        session.handle(put(1), b"a".into()).await;
+        session.handle(Command::Reserve, Vec::new()).await;
+
+        // A job released as often as its tube allows is buried in the
+        // dead-letter tube.
+        let release = Command::Release {
+            id: 1,
+            pri: 0,
+            delay: 0,
+        };
+        assert_eq!(
+            session.handle(release, Vec::new()).await,
+            [Response::Released]
+        );
+        let [Response::OkStatsJob { data }] = &session
+            .handle(Command::StatsJob { id: 1 }, Vec::new())
+            .await[..]
+        else {
+            panic!("expected job stats");
+        };
+        assert_eq!(data.tube, b"dead");
+        assert!(matches!(data.state, JobState::Buried { .. }));
+    }
+
+    #[tokio::test]
+    async fn test_config_tube() {
+        let shared = SharedServer::new(&Config::default(), 65535);
+        let mut session = shared.session();
+        let config = Command::ConfigTube {
+            tube: b"small".into(),
+            setting: TubeSetting::MaxJobs(Some(1)),
+        };
+        assert_eq!(
+            session.handle(config, Vec::new()).await,
+            [Response::Configured]
+        );
+
+        // Settings apply to jobs put afterwards, and are shown in the tube's
+        // stats.
+        let using = Command::Use {
+            tube: b"small".into(),
+        };
+        session.handle(using, Vec::new()).await;
+        assert_eq!(
+            session.handle(put(1), b"a".into()).await,
+            [Response::Inserted { id: 1 }]
+        );
+        assert_eq!(
+            session.handle(put(1), b"b".into()).await,
+            [Response::QuotaExceeded]
+        );
+        let stats = Command::StatsTube {
+            tube: b"small".into(),
+        };
+        let [Response::OkStatsTube { data }] =
+            &session.handle(stats, Vec::new()).await[..]
+        else {
+            panic!("expected tube stats");
+        };
+        assert_eq!(data.config.max_jobs, Some(1));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_reserve_batch() {
+        let shared = SharedServer::new(&Config::default(), 65535);
+        let mut session = shared.session();
+        session.handle(put(1), b"a".into()).await;
+        session.handle(put(1), b"b".into()).await;
+        session.handle(put(1), b"c".into()).await;
+
+        // Up to the given count of jobs are reserved and held at once.
+        let batch = Command::ReserveBatch {
+            count: 2,
+            timeout: 1,
+        };
+        assert_eq!(
+            session.handle(batch.clone(), Vec::new()).await,
+            [Response::ReservedBatch {
+                jobs: vec![
+                    ReservedJob {
+                        id: 1,
+                        data: Bytes::from_static(b"a"),
+                    },
+                    ReservedJob {
+                        id: 2,
+                        data: Bytes::from_static(b"b"),
+                    },
+                ],
+            }]
+        );
+        assert_eq!(session.reserved(), BTreeSet::from([1, 2]));
+        assert_eq!(
+            session.handle(batch.clone(), Vec::new()).await,
+            [Response::ReservedBatch {
+                jobs: vec![ReservedJob {
+                    id: 3,
+                    data: Bytes::from_static(b"c"),
+                }],
+            }]
+        );
+
+        let start = Instant::now();
+        assert_eq!(
+            session.handle(batch, Vec::new()).await,
+            [Response::TimedOut]
+        );
+        assert_eq!(start.elapsed(), Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn test_put_with_headers() {
+        let shared = SharedServer::new(&Config::default(), 65535);
+        let mut session = shared.session();
+        let headers = Headers::from([(b"trace".to_vec(), b"abc".to_vec())]);
+        let with_headers = Command::PutWithHeaders {
+            pri: 0,
+            delay: 0,
+            ttr: 60,
+            n_bytes: 1,
+            headers: headers.clone(),
+        };
+        session.handle(with_headers.clone(), b"a".into()).await;
+        session.handle(with_headers, b"b".into()).await;
+
+        // Headers are only sent to clients that have enabled them.
+        assert_eq!(
+            session.handle(Command::Reserve, Vec::new()).await,
+            reserved(1, b"a")
+        );
+        assert_eq!(
+            session.handle(Command::EnableHeaders, Vec::new()).await,
+            [Response::HeadersEnabled]
+        );
+        assert_eq!(
+            session.handle(Command::Reserve, Vec::new()).await,
+            [
+                Response::ReservedWithHeaders {
+                    id: 2,
+                    n_bytes: 1,
+                    headers,
+                },
+                Response::JobChunk(Bytes::from_static(b"b")),
+                Response::JobEnd,
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_put_with_ttl() {
+        let shared = SharedServer::new(&Config::default(), 65535);
+        let cancel = CancellationToken::new();
+        let timers = tokio::spawn({
+            let shared = Arc::clone(&shared);
+            let cancel = cancel.clone();
+            async move { shared.run_timers(cancel).await }
+        });
+        let mut session = shared.session();
+        let config = Command::ConfigTube {
+            tube: Server::DEFAULT_QUEUE.into(),
+            setting: TubeSetting::ExpiredTube(Some(b"expired".into())),
+        };
+        session.handle(config, Vec::new()).await;
+        let expiring = Command::PutWithTtl {
+            pri: 0,
+            delay: 0,
+            ttr: 60,
+            ttl: 5,
+            n_bytes: 1,
+        };
+        session.handle(expiring, b"a".into()).await;
+
+        // A job not reserved within its TTL moves to the expired tube.
+        tokio::time::sleep(Duration::from_secs(6)).await;
+        let timeout = Command::ReserveWithTimeout { timeout: 0 };
+        assert_eq!(
+            session.handle(timeout.clone(), Vec::new()).await,
+            [Response::TimedOut]
+        );
+        let watch = Command::Watch {
+            tube: b"expired".into(),
+        };
+        session.handle(watch, Vec::new()).await;
+        assert_eq!(
+            session.handle(timeout, Vec::new()).await,
+            reserved(1, b"a")
+        );
+
+        cancel.cancel();
+        timers.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_complete_and_result() {
+        let shared = SharedServer::new(&Config::default(), 65535);
+        let mut producer = shared.session();
+        let mut worker = shared.session();
+
+        // A waiting result is fetched once the job is completed.
+        producer.handle(put(1), b"a".into()).await;
+        let result = Command::GetResult { id: 1, timeout: 60 };
+        let mut fetch = Box::pin(producer.handle(result, Vec::new()));
+        assert!((&mut fetch).now_or_never().is_none());
+        worker.handle(Command::Reserve, Vec::new()).await;
+        let complete = Command::Complete { id: 1, n_bytes: 2 };
+        assert_eq!(
+            worker.handle(complete, b"ok".into()).await,
+            [Response::Completed]
+        );
+        assert_eq!(
+            fetch.await,
+            [
+                Response::JobResult { id: 1, n_bytes: 2 },
+                Response::JobChunk(Bytes::from_static(b"ok")),
+                Response::JobEnd,
+            ]
+        );
+
+        // Results of jobs put with a reply tube are put to that tube instead.
+        let with_reply = Command::PutWithReply {
+            reply_tube: b"replies".into(),
+            pri: 0,
+            delay: 0,
+            ttr: 60,
+            n_bytes: 1,
+        };
+        producer.handle(with_reply, b"b".into()).await;
+        worker.handle(Command::Reserve, Vec::new()).await;
+        let complete = Command::Complete { id: 2, n_bytes: 4 };
+        worker.handle(complete, b"done".into()).await;
+        let watch = Command::Watch {
+            tube: b"replies".into(),
+        };
+        producer.handle(watch, Vec::new()).await;
+        assert_eq!(
+            producer.handle(Command::Reserve, Vec::new()).await,
+            reserved(3, b"done")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_subscribe() {
+        let shared = SharedServer::new(&Config::default(), 65535);
+        let mut session = shared.session();
+        for subscriber in [b"a", b"b"] {
+            let subscribe = Command::Subscribe {
+                tube: b"events".into(),
+                subscriber: subscriber.into(),
+            };
+            assert_eq!(
+                session.handle(subscribe, Vec::new()).await,
+                [Response::Subscribed]
+            );
+        }
+
+        // Jobs put to a broadcast tube are copied to each subscriber.
+        let using = Command::Use {
+            tube: b"events".into(),
+        };
+        session.handle(using, Vec::new()).await;
+        assert_eq!(
+            session.handle(put(1), b"x".into()).await,
+            [Response::Inserted { id: 1 }]
+        );
+        for tube in [b"a", b"b"] {
+            let using = Command::Use { tube: tube.into() };
+            session.handle(using, Vec::new()).await;
+            assert_eq!(
+                session.handle(Command::PeekReady, Vec::new()).await[1],
+                Response::JobChunk(Bytes::from_static(b"x"))
+            );
+        }
+
+        let unsubscribe = Command::Unsubscribe {
+            tube: b"events".into(),
+            subscriber: b"a".into(),
+        };
+        assert_eq!(
+            session.handle(unsubscribe.clone(), Vec::new()).await,
+            [Response::Unsubscribed]
+        );
+        assert_eq!(
+            session.handle(unsubscribe, Vec::new()).await,
+            [Response::NotFound]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_wildcard_watch() {
+        let shared = SharedServer::new(&Config::default(), 65535);
+        let mut producer = shared.session();
+        let mut consumer = shared.session();
+        let watch = Command::Watch {
+            tube: b"jobs.*".into(),
+        };
+        assert_eq!(
+            consumer.handle(watch, Vec::new()).await,
+            [Response::Watching { count: 2 }]
+        );
+        let ignore = Command::Ignore {
+            tube: Server::DEFAULT_QUEUE.into(),
+        };
+        consumer.handle(ignore, Vec::new()).await;
+
+        // A pattern watches tubes created after it was watched.
+        let mut reserve =
+            Box::pin(consumer.handle(Command::Reserve, Vec::new()));
+        assert!((&mut reserve).now_or_never().is_none());
+        let using = Command::Use {
+            tube: b"jobs.email".into(),
+        };
+        producer.handle(using, Vec::new()).await;
+        producer.handle(put(1), b"a".into()).await;
+        assert_eq!(reserve.await, reserved(1, b"a"));
+
+        let ignore = Command::Ignore {
+            tube: b"jobs.*".into(),
+        };
+        assert_eq!(
+            consumer.handle(ignore, Vec::new()).await,
+            [Response::NotIgnored]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_ordered_reserve() {
+        let shared = SharedServer::new(&Config::default(), 65535);
+        let mut session = shared.session();
+        let watch = Command::Watch {
+            tube: b"other".into(),
+        };
+        session.handle(watch, Vec::new()).await;
+        let low = Command::Put {
+            pri: 100,
+            delay: 0,
+            ttr: 60,
+            n_bytes: 1,
+        };
+        session.handle(low.clone(), b"a".into()).await;
+        session.handle(low, b"b".into()).await;
+        let using = Command::Use {
+            tube: b"other".into(),
+        };
+        session.handle(using, Vec::new()).await;
+        session.handle(put(1), b"c".into()).await;
+
+        // By priority across watched tubes, unless ordered by watch list.
+        assert_eq!(
+            session.handle(Command::Reserve, Vec::new()).await,
+            reserved(3, b"c")
+        );
+        session.handle(put(1), b"d".into()).await;
+        let mode = Command::ReserveMode {
+            mode: ReserveMode::Ordered,
+        };
+        assert_eq!(
+            session.handle(mode, Vec::new()).await,
+            [Response::ReserveModeSet {
+                mode: ReserveMode::Ordered
+            }]
+        );
+        assert_eq!(
+            session.handle(Command::Reserve, Vec::new()).await,
+            reserved(1, b"a")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_put_at() {
+        let shared = SharedServer::new(&Config::default(), 65535);
+        let mut session = shared.session();
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        let in_an_hour = now.as_secs() + 3600;
+        let put_at = Command::PutAt {
+            run_at: in_an_hour,
+            pri: 0,
+            ttr: 60,
+            n_bytes: 1,
+        };
+        session.handle(put_at, b"a".into()).await;
+
+        // A job put for a future time waits until it's rescheduled to now.
+        let [Response::OkStatsJob { data }] = &session
+            .handle(Command::StatsJob { id: 1 }, Vec::new())
+            .await[..]
+        else {
+            panic!("expected job stats");
+        };
+        assert_eq!(data.run_at, Some(in_an_hour));
+        let timeout = Command::ReserveWithTimeout { timeout: 0 };
+        assert_eq!(
+            session.handle(timeout.clone(), Vec::new()).await,
+            [Response::TimedOut]
+        );
+        let reschedule = Command::Reschedule { id: 1, run_at: 0 };
+        assert_eq!(
+            session.handle(reschedule.clone(), Vec::new()).await,
+            [Response::Rescheduled]
+        );
+        assert_eq!(
+            session.handle(timeout, Vec::new()).await,
+            reserved(1, b"a")
+        );
+        assert_eq!(
+            session.handle(reschedule, Vec::new()).await,
+            [Response::NotFound]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_timeout_requeue() {
+        let shared = SharedServer::new(&Config::default(), 65535);
+        let cancel = CancellationToken::new();
+        let timers = tokio::spawn({
+            let shared = Arc::clone(&shared);
+            let cancel = cancel.clone();
+            async move { shared.run_timers(cancel).await }
+        });
+        let mut session = shared.session();
+        let config = Command::ConfigTube {
+            tube: Server::DEFAULT_QUEUE.into(),
+            setting: TubeSetting::TimeoutRequeue(Some(RequeuePos::Front)),
+        };
+        session.handle(config, Vec::new()).await;
+        let short_ttr = Command::Put {
+            pri: 0,
+            delay: 0,
+            ttr: 1,
+            n_bytes: 1,
+        };
+        session.handle(short_ttr, b"a".into()).await;
+        session.handle(put(1), b"b".into()).await;
+
+        // A job that times out is reserved again ahead of the job behind it.
+        session.handle(Command::Reserve, Vec::new()).await;
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        assert_eq!(
+            session.handle(Command::Reserve, Vec::new()).await,
+            reserved(1, b"a")
+        );
+
+        cancel.cancel();
+        timers.await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_pri_aging() {
+        let shared = SharedServer::new(&Config::default(), 65535);
+        let mut session = shared.session();
+        let config = Command::ConfigTube {
+            tube: Server::DEFAULT_QUEUE.into(),
+            setting: TubeSetting::PriAging(Some(10)),
+        };
+        session.handle(config, Vec::new()).await;
+        let low = Command::Put {
+            pri: 100,
+            delay: 0,
+            ttr: 60,
+            n_bytes: 1,
+        };
+        session.handle(low, b"a".into()).await;
+
+        // A job that has waited long enough is reserved before a newer job of
+        // a better priority.
+        tokio::time::sleep(Duration::from_secs(11)).await;
+        session.handle(put(1), b"b".into()).await;
+        assert_eq!(
+            session.handle(Command::Reserve, Vec::new()).await,
+            reserved(1, b"a")
+        );
+    }
+}
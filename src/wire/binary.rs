@@ -0,0 +1,1805 @@
+//! implements an optional length-prefixed binary framing of the beanstalkd
+//! protocol, carrying the same [`Command`] and [`Response`] values as the text
+//! protocol.
+//!
+//! A binary connection begins with the client sending [`MAGIC`]. From then on,
+//! each message in either direction is a frame: a big-endian `u32` length,
+//! then that many bytes of payload. A payload is an opcode byte followed by
+//! the message's fields, in the order they're declared:
+//!
+//! * integers are unsigned LEB128 varints;
+//! * names and other byte strings are a varint length then the bytes, with an
+//!   empty string standing for "none" where a name is optional;
+//! * limits use `0` for "none", as in the text protocol;
+//! * lists and headers are a varint count then each item in turn;
+//! * enumerations are a single byte.
+//!
+//! Job data follows the command or response introducing it in frames of its
+//! own, with the [`DATA`] opcode and at most [`MAX_DATA_LEN`] bytes of data
+//! each. A client sends exactly `n_bytes` of data in one or more frames; the
+//! server ends a job's data with a [`JOB_END`] frame.
+//! Responses with YAML data carry the same YAML document as the text protocol.
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use serde::ser;
+use tokio_util::codec;
+
+use super::events::BeanstalkClientEvent;
+use super::parser::{is_name, is_name_or_pattern};
+use super::protocol::{
+    Command, MAX_RESERVE_BATCH, ReservedJob, Response, TubeNames,
+};
+use super::{decoder, encoder};
+use crate::types::config::{RequeuePos, TubeSetting};
+use crate::types::cron::Cron;
+use crate::types::job::Headers;
+use crate::types::tube::ReserveMode;
+
+/// Sent by a client to begin a binary connection. The first byte can't begin a
+/// text command, so binary connections can be told apart from text ones; the
+/// last is the version of the binary protocol.
+pub const MAGIC: [u8; 4] = *b"\0bs\x01";
+
+/// The opcode of frames carrying job data, in either direction.
+pub const DATA: u8 = 0;
+
+/// The opcode of the frame ending a job's data sent by the server.
+pub const JOB_END: u8 = 20;
+
+/// The opcode shared by all `OK` responses with YAML data.
+const OK_DATA: u8 = 30;
+
+/// The largest command frame accepted from a client, in bytes.
+const MAX_COMMAND_LEN: usize = 65_536;
+
+/// The most job data carried by a single data frame, in either direction.
+pub const MAX_DATA_LEN: u32 = 65_536;
+
+/// A decoder for a stream of binary protocol client messages, producing the
+/// same events as the text protocol's [`decoder::Decoder`].
+#[derive(Debug, Default)]
+pub enum Decoder {
+    #[default]
+    ExpectMagic,
+    ParseCommand,
+    // Expecting the header of a data frame, or the end of the job's data.
+    ParseJob {
+        remaining: u32,
+    },
+    // Part way through a data frame of which in_frame bytes are still to come.
+    ParseData {
+        remaining: u32,
+        in_frame: u32,
+    },
+}
+
+impl codec::Decoder for Decoder {
+    type Item = BeanstalkClientEvent;
+
+    type Error = decoder::Error;
+
+    fn decode(
+        &mut self,
+        src: &mut BytesMut,
+    ) -> Result<Option<Self::Item>, Self::Error> {
+        match *self {
+            Decoder::ExpectMagic => {
+                if src.len() < MAGIC.len() {
+                    return Ok(None);
+                }
+                if !src.starts_with(&MAGIC) {
+                    return Err(Response::BadFormat.into());
+                }
+
+                src.advance(MAGIC.len());
+                *self = Self::ParseCommand;
+                self.decode(src)
+            },
+            Decoder::ParseCommand => {
+                let Some(frame) = take_frame(src, MAX_COMMAND_LEN)? else {
+                    return Ok(None);
+                };
+
+                let cmd = decode_command(&frame)?;
+                if let Some(n_bytes) = cmd.put_bytes() {
+                    *self = Self::ParseJob { remaining: n_bytes };
+                }
+
+                Ok(Some(Self::Item::Command(cmd)))
+            },
+            Decoder::ParseJob { remaining: 0 } => {
+                *self = Self::ParseCommand;
+                Ok(Some(Self::Item::PutEnd))
+            },
+            Decoder::ParseJob { remaining } => {
+                // Only the frame's header is read here, so a frame's declared
+                // length never causes more than it to be buffered.
+                let Some(&[a, b, c, d, op]) = src.first_chunk::<5>() else {
+                    return Ok(None);
+                };
+
+                // Allows for the opcode as well as the data, and ensures a
+                // PutChunk always contains at least one byte of data.
+                let len = u32::from_be_bytes([a, b, c, d]);
+                let max_len = remaining.min(MAX_DATA_LEN).saturating_add(1);
+                if op != DATA || len < 2 || len > max_len {
+                    return Err(Response::BadFormat.into());
+                }
+
+                src.advance(5);
+                *self = Self::ParseData {
+                    remaining,
+                    in_frame: len.saturating_sub(1),
+                };
+                self.decode(src)
+            },
+            Decoder::ParseData {
+                remaining,
+                in_frame,
+            } => {
+                if src.is_empty() {
+                    return Ok(None);
+                }
+
+                // Panic safety: split_to panics unless taken <= src.len(),
+                // which is assured by taking the minimum.
+                let taken =
+                    u32::try_from(src.len()).unwrap_or(u32::MAX).min(in_frame);
+                let chunk = src.split_to(taken as usize).freeze();
+                *self = match in_frame.saturating_sub(taken) {
+                    0 => Self::ParseJob {
+                        remaining: remaining.saturating_sub(taken),
+                    },
+                    in_frame => Self::ParseData {
+                        remaining: remaining.saturating_sub(taken),
+                        in_frame,
+                    },
+                };
+
+                Ok(Some(Self::Item::PutChunk(chunk)))
+            },
+        }
+    }
+}
+
+/// An encoder to produce binary protocol server messages.
+#[derive(Debug, Default)]
+pub struct Encoder {}
+
+impl codec::Encoder<Response> for Encoder {
+    type Error = encoder::Error;
+
+    fn encode(
+        &mut self,
+        item: Response,
+        dst: &mut BytesMut,
+    ) -> Result<(), Self::Error> {
+        encode_response(item, dst)
+    }
+}
+
+/// Takes the payload of the next frame from `src`, or returns `None` if it
+/// hasn't yet been received in full. Fails if the frame is longer than
+/// `max_len` bytes.
+///
+/// # Errors
+///
+/// Returns [`Response::BadFormat`] if the frame is too long.
+#[allow(clippy::result_large_err)]
+pub fn take_frame(
+    src: &mut BytesMut,
+    max_len: usize,
+) -> Result<Option<BytesMut>, Response> {
+    let Some(&len) = src.first_chunk::<4>() else {
+        return Ok(None);
+    };
+    let len = usize::try_from(u32::from_be_bytes(len)).unwrap_or(usize::MAX);
+    if len > max_len {
+        return Err(Response::BadFormat);
+    }
+
+    if src.len() < len.saturating_add(4) {
+        return Ok(None);
+    }
+
+    src.advance(4);
+    Ok(Some(src.split_to(len)))
+}
+
+/// Writes a frame to `dst`, with the payload written by `f`.
+fn put_frame(dst: &mut BytesMut, f: impl FnOnce(&mut Writer)) {
+    let start = dst.len();
+    dst.put_u32(0);
+
+    f(&mut Writer { dst });
+
+    let len = dst.len().saturating_sub(start).saturating_sub(4);
+    let len = u32::try_from(len).unwrap_or(u32::MAX).to_be_bytes();
+    dst[start..start.saturating_add(4)].copy_from_slice(&len);
+}
+
+/// Writes a command to `dst` as a frame, for clients of the binary protocol.
+/// Any job data the command introduces must follow, see [`encode_data`].
+#[allow(clippy::too_many_lines)]
+pub fn encode_command(cmd: &Command, dst: &mut BytesMut) {
+    use Command as C;
+
+    put_frame(dst, |w| {
+        match cmd {
+            C::Put {
+                pri,
+                delay,
+                ttr,
+                n_bytes,
+            } => w.op(1).u32(*pri).u32(*delay).u32(*ttr).u32(*n_bytes),
+            C::PutUnique {
+                key,
+                pri,
+                delay,
+                ttr,
+                n_bytes,
+            } => w
+                .op(2)
+                .bytes(key)
+                .u32(*pri)
+                .u32(*delay)
+                .u32(*ttr)
+                .u32(*n_bytes),
+            C::PutWithHeaders {
+                pri,
+                delay,
+                ttr,
+                n_bytes,
+                headers,
+            } => w
+                .op(3)
+                .u32(*pri)
+                .u32(*delay)
+                .u32(*ttr)
+                .u32(*n_bytes)
+                .headers(headers),
+            C::PutWithTtl {
+                pri,
+                delay,
+                ttr,
+                ttl,
+                n_bytes,
+            } => w
+                .op(4)
+                .u32(*pri)
+                .u32(*delay)
+                .u32(*ttr)
+                .u32(*ttl)
+                .u32(*n_bytes),
+            C::PutWithReply {
+                reply_tube,
+                pri,
+                delay,
+                ttr,
+                n_bytes,
+            } => w
+                .op(5)
+                .bytes(reply_tube)
+                .u32(*pri)
+                .u32(*delay)
+                .u32(*ttr)
+                .u32(*n_bytes),
+            C::PutAt {
+                run_at,
+                pri,
+                ttr,
+                n_bytes,
+            } => w.op(6).u64(*run_at).u32(*pri).u32(*ttr).u32(*n_bytes),
+            C::Reschedule { id, run_at } => w.op(7).u64(*id).u64(*run_at),
+            C::PutAfter {
+                pri,
+                delay,
+                ttr,
+                n_bytes,
+                after,
+            } => w
+                .op(8)
+                .u32(*pri)
+                .u32(*delay)
+                .u32(*ttr)
+                .u32(*n_bytes)
+                .u64s(after),
+            C::Reserve => w.op(9),
+            C::ReserveWithTimeout { timeout } => w.op(10).u32(*timeout),
+            C::ReserveBatch { count, timeout } => {
+                w.op(11).u32(*count).u32(*timeout)
+            },
+            C::ReserveJob { id } => w.op(12).u64(*id),
+            C::Release { id, pri, delay } => {
+                w.op(13).u64(*id).u32(*pri).u32(*delay)
+            },
+            C::Delete { id } => w.op(14).u64(*id),
+            C::Bury { id, pri } => w.op(15).u64(*id).u32(*pri),
+            C::Touch { id } => w.op(16).u64(*id),
+            C::Watch { tube } => w.op(17).bytes(tube),
+            C::Ignore { tube } => w.op(18).bytes(tube),
+            C::Peek { id } => w.op(19).u64(*id),
+            C::PeekReady => w.op(20),
+            C::PeekDelayed => w.op(21),
+            C::PeekBuried => w.op(22),
+            C::Kick { bound } => w.op(23).u64(*bound),
+            C::KickJob { id } => w.op(24).u64(*id),
+            C::StatsJob { id } => w.op(25).u64(*id),
+            C::StatsTube { tube } => w.op(26).bytes(tube),
+            C::StatsServer => w.op(27),
+            C::ListTubes => w.op(28),
+            C::ListTubeUsed => w.op(29),
+            C::ListTubesWatched => w.op(30),
+            C::Quit => w.op(31),
+            C::PauseTube { tube, delay } => w.op(32).bytes(tube).u32(*delay),
+            C::Use { tube } => w.op(33).bytes(tube),
+            C::SetPri { id, pri } => w.op(34).u64(*id).u32(*pri),
+            C::MoveJob { id, tube } => w.op(35).u64(*id).bytes(tube),
+            C::MoveJobs { tube, bound } => w.op(36).bytes(tube).u64(*bound),
+            C::Schedule {
+                name,
+                pri,
+                ttr,
+                n_bytes,
+                cron,
+            } => w
+                .op(37)
+                .bytes(name)
+                .u32(*pri)
+                .u32(*ttr)
+                .u32(*n_bytes)
+                .bytes(cron.to_string().as_bytes()),
+            C::Unschedule { name } => w.op(38).bytes(name),
+            C::ListSchedules => w.op(39),
+            C::ConfigTube { tube, setting } => {
+                w.op(40).bytes(tube).setting(setting)
+            },
+            C::EnableHeaders => w.op(41),
+            C::EnableMilliseconds => w.op(42),
+            C::Complete { id, n_bytes } => w.op(43).u64(*id).u32(*n_bytes),
+            C::GetResult { id, timeout } => w.op(44).u64(*id).u32(*timeout),
+            C::Subscribe { tube, subscriber } => {
+                w.op(45).bytes(tube).bytes(subscriber)
+            },
+            C::Unsubscribe { tube, subscriber } => {
+                w.op(46).bytes(tube).bytes(subscriber)
+            },
+            C::ReserveMode { mode } => w.op(47).reserve_mode(*mode),
+            C::ClientName { name } => w.op(48).bytes(name),
+            C::ListClients => w.op(49),
+            C::KillClient { id } => w.op(50).u64(*id),
+            C::Capabilities => w.op(51),
+        };
+    });
+}
+
+/// Writes job data to `dst` as one or more frames, for clients of the binary
+/// protocol and job data sent by the server.
+pub fn encode_data(data: &[u8], dst: &mut BytesMut) {
+    for chunk in data.chunks(MAX_DATA_LEN as usize) {
+        put_frame(dst, |w| {
+            w.op(DATA).raw(chunk);
+        });
+    }
+}
+
+/// Decodes a command from the payload of a frame, checking it as strictly as
+/// the text protocol's parser.
+///
+/// # Errors
+///
+/// Returns the response to send to the client if the command is invalid.
+#[allow(clippy::result_large_err, clippy::too_many_lines)]
+pub fn decode_command(frame: &[u8]) -> Result<Command, Response> {
+    use Command as C;
+
+    let mut r = Reader { from: frame };
+
+    let cmd = match r.u8()? {
+        1 => C::Put {
+            pri: r.u32()?,
+            delay: r.u32()?,
+            ttr: r.u32()?,
+            n_bytes: r.u32()?,
+        },
+        2 => C::PutUnique {
+            key: r.name()?,
+            pri: r.u32()?,
+            delay: r.u32()?,
+            ttr: r.u32()?,
+            n_bytes: r.u32()?,
+        },
+        3 => C::PutWithHeaders {
+            pri: r.u32()?,
+            delay: r.u32()?,
+            ttr: r.u32()?,
+            n_bytes: r.u32()?,
+            headers: r.headers()?,
+        },
+        4 => C::PutWithTtl {
+            pri: r.u32()?,
+            delay: r.u32()?,
+            ttr: r.u32()?,
+            ttl: match r.u32()? {
+                0 => return Err(Response::BadFormat),
+                ttl => ttl,
+            },
+            n_bytes: r.u32()?,
+        },
+        5 => C::PutWithReply {
+            reply_tube: r.name()?,
+            pri: r.u32()?,
+            delay: r.u32()?,
+            ttr: r.u32()?,
+            n_bytes: r.u32()?,
+        },
+        6 => C::PutAt {
+            run_at: r.u64()?,
+            pri: r.u32()?,
+            ttr: r.u32()?,
+            n_bytes: r.u32()?,
+        },
+        7 => C::Reschedule {
+            id: r.u64()?,
+            run_at: r.u64()?,
+        },
+        8 => C::PutAfter {
+            pri: r.u32()?,
+            delay: r.u32()?,
+            ttr: r.u32()?,
+            n_bytes: r.u32()?,
+            after: r.u64s()?,
+        },
+        9 => C::Reserve,
+        10 => C::ReserveWithTimeout { timeout: r.u32()? },
+        11 => C::ReserveBatch {
+            count: match r.u32()? {
+                0 => return Err(Response::BadFormat),
+                count if count > MAX_RESERVE_BATCH => {
+                    return Err(Response::BadFormat);
+                },
+                count => count,
+            },
+            timeout: r.u32()?,
+        },
+        12 => C::ReserveJob { id: r.u64()? },
+        13 => C::Release {
+            id: r.u64()?,
+            pri: r.u32()?,
+            delay: r.u32()?,
+        },
+        14 => C::Delete { id: r.u64()? },
+        15 => C::Bury {
+            id: r.u64()?,
+            pri: r.u32()?,
+        },
+        16 => C::Touch { id: r.u64()? },
+        17 => C::Watch {
+            tube: r.name_or_pattern()?,
+        },
+        18 => C::Ignore {
+            tube: r.name_or_pattern()?,
+        },
+        19 => C::Peek { id: r.u64()? },
+        20 => C::PeekReady,
+        21 => C::PeekDelayed,
+        22 => C::PeekBuried,
+        23 => C::Kick { bound: r.u64()? },
+        24 => C::KickJob { id: r.u64()? },
+        25 => C::StatsJob { id: r.u64()? },
+        26 => C::StatsTube { tube: r.name()? },
+        27 => C::StatsServer,
+        28 => C::ListTubes,
+        29 => C::ListTubeUsed,
+        30 => C::ListTubesWatched,
+        31 => C::Quit,
+        32 => C::PauseTube {
+            tube: r.name()?,
+            delay: r.u32()?,
+        },
+        33 => C::Use { tube: r.name()? },
+        34 => C::SetPri {
+            id: r.u64()?,
+            pri: r.u32()?,
+        },
+        35 => C::MoveJob {
+            id: r.u64()?,
+            tube: r.name()?,
+        },
+        36 => C::MoveJobs {
+            tube: r.name()?,
+            bound: r.u64()?,
+        },
+        37 => C::Schedule {
+            name: r.name()?,
+            pri: r.u32()?,
+            ttr: r.u32()?,
+            n_bytes: r.u32()?,
+            cron: Cron::parse(r.bytes()?).ok_or(Response::BadFormat)?,
+        },
+        38 => C::Unschedule { name: r.name()? },
+        39 => C::ListSchedules,
+        40 => C::ConfigTube {
+            tube: r.name()?,
+            setting: r.setting()?,
+        },
+        41 => C::EnableHeaders,
+        42 => C::EnableMilliseconds,
+        43 => C::Complete {
+            id: r.u64()?,
+            n_bytes: r.u32()?,
+        },
+        44 => C::GetResult {
+            id: r.u64()?,
+            timeout: r.u32()?,
+        },
+        45 => C::Subscribe {
+            tube: r.name()?,
+            subscriber: r.name()?,
+        },
+        46 => C::Unsubscribe {
+            tube: r.name()?,
+            subscriber: r.name()?,
+        },
+        47 => C::ReserveMode {
+            mode: r.reserve_mode()?,
+        },
+        48 => C::ClientName { name: r.name()? },
+        49 => C::ListClients,
+        50 => C::KillClient { id: r.u64()? },
+        51 => C::Capabilities,
+        _ => return Err(Response::UnknownCommand),
+    };
+
+    Ok(r.done_and(cmd)?)
+}
+
+/// Writes a response to `dst` as a frame. On failing to serialise YAML data,
+/// writes an `INTERNAL_ERROR` response instead and returns the error.
+///
+/// # Errors
+///
+/// Returns an error if YAML data can't be serialised.
+#[allow(clippy::too_many_lines)]
+pub fn encode_response(
+    resp: Response,
+    dst: &mut BytesMut,
+) -> Result<(), encoder::Error> {
+    use Response as R;
+
+    fn put_ok_and_data(
+        dst: &mut BytesMut,
+        data: impl ser::Serialize,
+    ) -> Result<(), encoder::Error> {
+        match serde_yaml::to_string(&data) {
+            Ok(data) => {
+                put_frame(dst, |w| {
+                    w.op(OK_DATA).raw(data.as_bytes());
+                });
+                Ok(())
+            },
+            Err(err) => {
+                put_frame(dst, |w| {
+                    w.op(2);
+                });
+                Err(err.into())
+            },
+        }
+    }
+
+    match resp {
+        R::OkStatsJob { data } => return put_ok_and_data(dst, data),
+        R::OkStats { data } => return put_ok_and_data(dst, data),
+        R::OkCapabilities { data } => return put_ok_and_data(dst, data),
+        R::OkStatsTube { data } => return put_ok_and_data(dst, data),
+        R::OkListTubes { tubes } => {
+            return put_ok_and_data(dst, TubeNames(&tubes));
+        },
+        R::OkWatchList { data } => return put_ok_and_data(dst, data),
+        R::OkListClients { clients } => return put_ok_and_data(dst, clients),
+        R::OkListSchedules { schedules } => {
+            return put_ok_and_data(dst, schedules);
+        },
+        R::JobChunk(data) => {
+            encode_data(&data, dst);
+            return Ok(());
+        },
+        _ => {},
+    }
+
+    put_frame(dst, |w| {
+        match resp {
+            R::OutOfMemory => w.op(1),
+            R::InternalError => w.op(2),
+            R::BadFormat => w.op(3),
+            R::UnknownCommand => w.op(4),
+            R::Inserted { id } => w.op(5).u64(id),
+            R::BuriedID { id } => w.op(6).u64(id),
+            R::ExpectedCRLF => w.op(7),
+            R::JobTooBig => w.op(8),
+            R::QuotaExceeded => w.op(9),
+            R::Draining => w.op(10),
+            R::Using { tube } => w.op(11).bytes(&tube),
+            R::DeadlineSoon => w.op(12),
+            R::TimedOut => w.op(13),
+            R::Reserved { id } => w.op(14).u64(id),
+            R::ReservedWithHeaders {
+                id,
+                n_bytes,
+                headers,
+            } => w.op(15).u64(id).u32(n_bytes).headers(&headers),
+            R::ReservedBatch { jobs } => {
+                w.op(16).u64(u64::try_from(jobs.len()).unwrap_or(u64::MAX));
+                for job in jobs {
+                    w.u64(job.id).bytes(&job.data);
+                }
+                w
+            },
+            R::Found { id } => w.op(17).u64(id),
+            R::FoundWithHeaders {
+                id,
+                n_bytes,
+                headers,
+            } => w.op(18).u64(id).u32(n_bytes).headers(&headers),
+            R::JobEnd => w.op(JOB_END),
+            R::NotFound => w.op(21),
+            R::Deleted => w.op(22),
+            R::Released => w.op(23),
+            R::Buried => w.op(24),
+            R::Touched => w.op(25),
+            R::Watching { count } => w.op(26).u32(count),
+            R::NotIgnored => w.op(27),
+            R::KickedCount { count } => w.op(28).u64(count),
+            R::Kicked => w.op(29),
+            R::Paused => w.op(31),
+            R::PriSet => w.op(32),
+            R::Moved => w.op(33),
+            R::Rescheduled => w.op(34),
+            R::MovedCount { count } => w.op(35).u64(count),
+            R::Scheduled => w.op(36),
+            R::Unscheduled => w.op(37),
+            R::Configured => w.op(38),
+            R::HeadersEnabled => w.op(39),
+            R::MillisecondsEnabled => w.op(40),
+            R::Completed => w.op(41),
+            R::JobResult { id, n_bytes } => w.op(42).u64(id).u32(n_bytes),
+            R::Subscribed => w.op(43),
+            R::Unsubscribed => w.op(44),
+            R::ReserveModeSet { mode } => w.op(45).reserve_mode(mode),
+            R::Named => w.op(46),
+            R::Killed => w.op(47),
+            R::OkStatsJob { .. }
+            | R::OkStats { .. }
+            | R::OkCapabilities { .. }
+            | R::OkStatsTube { .. }
+            | R::OkListTubes { .. }
+            | R::OkWatchList { .. }
+            | R::OkListClients { .. }
+            | R::OkListSchedules { .. }
+            | R::JobChunk(_) => unreachable!("encoded above"),
+        };
+    });
+
+    Ok(())
+}
+
+/// A response as decoded by a client of the binary protocol.
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, PartialEq)]
+pub enum ClientResponse {
+    /// any response without YAML data
+    Response(Response),
+    /// any of the `OK` responses with YAML data, which is given as the YAML
+    /// document rather than decoded
+    Ok(Bytes),
+}
+
+/// Decodes a response from the payload of a frame, for clients of the binary
+/// protocol.
+///
+/// # Errors
+///
+/// Returns [`Response::BadFormat`] if the response is invalid.
+#[allow(clippy::result_large_err)]
+pub fn decode_response(frame: &[u8]) -> Result<ClientResponse, Response> {
+    use Response as R;
+
+    let mut r = Reader { from: frame };
+
+    let resp = match r.u8()? {
+        DATA => R::JobChunk(Bytes::copy_from_slice(r.rest())),
+        1 => R::OutOfMemory,
+        2 => R::InternalError,
+        3 => R::BadFormat,
+        4 => R::UnknownCommand,
+        5 => R::Inserted { id: r.u64()? },
+        6 => R::BuriedID { id: r.u64()? },
+        7 => R::ExpectedCRLF,
+        8 => R::JobTooBig,
+        9 => R::QuotaExceeded,
+        10 => R::Draining,
+        11 => R::Using {
+            tube: r.bytes()?.to_vec(),
+        },
+        12 => R::DeadlineSoon,
+        13 => R::TimedOut,
+        14 => R::Reserved { id: r.u64()? },
+        15 => R::ReservedWithHeaders {
+            id: r.u64()?,
+            n_bytes: r.u32()?,
+            headers: r.any_headers()?,
+        },
+        16 => {
+            let count = r.u64()?;
+            let mut jobs = Vec::new();
+            for _ in 0..count {
+                jobs.push(ReservedJob {
+                    id: r.u64()?,
+                    data: Bytes::copy_from_slice(r.bytes()?),
+                });
+            }
+            R::ReservedBatch { jobs }
+        },
+        17 => R::Found { id: r.u64()? },
+        18 => R::FoundWithHeaders {
+            id: r.u64()?,
+            n_bytes: r.u32()?,
+            headers: r.any_headers()?,
+        },
+        JOB_END => R::JobEnd,
+        21 => R::NotFound,
+        22 => R::Deleted,
+        23 => R::Released,
+        24 => R::Buried,
+        25 => R::Touched,
+        26 => R::Watching { count: r.u32()? },
+        27 => R::NotIgnored,
+        28 => R::KickedCount { count: r.u64()? },
+        29 => R::Kicked,
+        OK_DATA => {
+            return Ok(ClientResponse::Ok(Bytes::copy_from_slice(r.rest())));
+        },
+        31 => R::Paused,
+        32 => R::PriSet,
+        33 => R::Moved,
+        34 => R::Rescheduled,
+        35 => R::MovedCount { count: r.u64()? },
+        36 => R::Scheduled,
+        37 => R::Unscheduled,
+        38 => R::Configured,
+        39 => R::HeadersEnabled,
+        40 => R::MillisecondsEnabled,
+        41 => R::Completed,
+        42 => R::JobResult {
+            id: r.u64()?,
+            n_bytes: r.u32()?,
+        },
+        43 => R::Subscribed,
+        44 => R::Unsubscribed,
+        45 => R::ReserveModeSet {
+            mode: r.reserve_mode()?,
+        },
+        46 => R::Named,
+        47 => R::Killed,
+        _ => return Err(Response::BadFormat),
+    };
+
+    Ok(r.done_and(ClientResponse::Response(resp))?)
+}
+
+/// Writes the fields of a frame's payload.
+struct Writer<'a> {
+    dst: &'a mut BytesMut,
+}
+
+impl Writer<'_> {
+    fn op(&mut self, op: u8) -> &mut Self {
+        self.dst.put_u8(op);
+        self
+    }
+
+    fn u64(&mut self, mut v: u64) -> &mut Self {
+        // Panic safety: v & 0x7f < 0x80, so fits in a u8.
+        while v >= 0x80 {
+            self.dst.put_u8(u8::try_from(v & 0x7f).unwrap() | 0x80);
+            v >>= 7;
+        }
+        self.dst.put_u8(u8::try_from(v).unwrap());
+        self
+    }
+
+    fn u32(&mut self, v: u32) -> &mut Self {
+        self.u64(v.into())
+    }
+
+    fn bytes(&mut self, v: &[u8]) -> &mut Self {
+        self.u64(u64::try_from(v.len()).unwrap_or(u64::MAX)).raw(v)
+    }
+
+    fn raw(&mut self, v: &[u8]) -> &mut Self {
+        self.dst.put_slice(v);
+        self
+    }
+
+    fn u64s(&mut self, v: &[u64]) -> &mut Self {
+        self.u64(u64::try_from(v.len()).unwrap_or(u64::MAX));
+        for &n in v {
+            self.u64(n);
+        }
+        self
+    }
+
+    fn headers(&mut self, headers: &Headers) -> &mut Self {
+        self.u64(u64::try_from(headers.len()).unwrap_or(u64::MAX));
+        for (key, value) in headers {
+            self.bytes(key).bytes(value);
+        }
+        self
+    }
+
+    fn reserve_mode(&mut self, mode: ReserveMode) -> &mut Self {
+        self.op(match mode {
+            ReserveMode::Priority => 0,
+            ReserveMode::Ordered => 1,
+        })
+    }
+
+    fn setting(&mut self, setting: &TubeSetting) -> &mut Self {
+        use TubeSetting as S;
+
+        let limit = |v: Option<u64>| v.unwrap_or(0);
+        match setting {
+            S::DeadLetterTube(v) => {
+                self.op(1).bytes(v.as_deref().unwrap_or_default())
+            },
+            S::DeadLetterReleases(v) => self.op(2).u64(limit(*v)),
+            S::DeadLetterTimeouts(v) => self.op(3).u64(limit(*v)),
+            S::DeadLetterBuries(v) => self.op(4).u64(limit(*v)),
+            S::DefaultTtr(v) => self.op(5).u64(limit(v.map(u64::from))),
+            S::MaxJobSize(v) => self.op(6).u64(limit(v.map(u64::from))),
+            S::MaxJobs(v) => self.op(7).u64(limit(*v)),
+            S::MaxBytes(v) => self.op(8).u64(limit(*v)),
+            S::TimeoutRequeue(v) => self.op(9).op(match v {
+                None => 0,
+                Some(RequeuePos::Front) => 1,
+                Some(RequeuePos::Back) => 2,
+            }),
+            S::PriAging(v) => self.op(10).u64(limit(v.map(u64::from))),
+            S::ReserveRate(v) => self.op(11).u64(limit(v.map(u64::from))),
+            S::MaxReserved(v) => self.op(12).u64(limit(*v)),
+            S::ExpiredTube(v) => {
+                self.op(13).bytes(v.as_deref().unwrap_or_default())
+            },
+            S::ResultRetention(v) => self.op(14).u64(limit(v.map(u64::from))),
+            S::Weight(v) => self.op(15).u64(limit(v.map(u64::from))),
+            S::ReserveRateInterval(v) => {
+                self.op(16).u64(limit(v.map(u64::from)))
+            },
+        }
+    }
+}
+
+/// The error from reading an invalid payload, sent to the client as
+/// [`Response::BadFormat`].
+#[derive(Debug)]
+struct BadFormat;
+
+impl From<BadFormat> for Response {
+    fn from(_: BadFormat) -> Self {
+        Response::BadFormat
+    }
+}
+
+/// Provides a minimal, zero-copy reader of the fields of a frame's payload,
+/// failing with [`BadFormat`] on invalid input.
+struct Reader<'a> {
+    from: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    /// Asserts there's no more input to take, returning `result` if so.
+    fn done_and<R>(&self, result: R) -> Result<R, BadFormat> {
+        if self.from.is_empty() {
+            Ok(result)
+        } else {
+            Err(BadFormat)
+        }
+    }
+
+    fn u8(&mut self) -> Result<u8, BadFormat> {
+        let (&v, rest) = self.from.split_first().ok_or(BadFormat)?;
+        self.from = rest;
+        Ok(v)
+    }
+
+    fn u64(&mut self) -> Result<u64, BadFormat> {
+        let mut r = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.u8()?;
+            let bits = u64::from(byte & 0x7f);
+            // Rejects varints that overflow a u64.
+            if bits.checked_shl(shift).and_then(|v| v.checked_shr(shift))
+                != Some(bits)
+            {
+                return Err(BadFormat);
+            }
+            r |= bits << shift;
+
+            if byte & 0x80 == 0 {
+                return Ok(r);
+            }
+        }
+
+        Err(BadFormat)
+    }
+
+    fn u32(&mut self) -> Result<u32, BadFormat> {
+        u32::try_from(self.u64()?).or(Err(BadFormat))
+    }
+
+    fn bytes(&mut self) -> Result<&'a [u8], BadFormat> {
+        let len = usize::try_from(self.u64()?).or(Err(BadFormat))?;
+        if len > self.from.len() {
+            return Err(BadFormat);
+        }
+
+        let (v, rest) = self.from.split_at(len);
+        self.from = rest;
+        Ok(v)
+    }
+
+    fn rest(&mut self) -> &'a [u8] {
+        std::mem::take(&mut self.from)
+    }
+
+    fn name(&mut self) -> Result<Vec<u8>, BadFormat> {
+        match self.bytes()? {
+            name if is_name(name) => Ok(name.to_vec()),
+            _ => Err(BadFormat),
+        }
+    }
+
+    fn name_or_pattern(&mut self) -> Result<Vec<u8>, BadFormat> {
+        match self.bytes()? {
+            token if is_name_or_pattern(token) => Ok(token.to_vec()),
+            _ => Err(BadFormat),
+        }
+    }
+
+    /// Reads a name, or an empty string to indicate no name.
+    fn name_or_none(&mut self) -> Result<Option<Vec<u8>>, BadFormat> {
+        match self.bytes()? {
+            b"" => Ok(None),
+            name if is_name(name) => Ok(Some(name.to_vec())),
+            _ => Err(BadFormat),
+        }
+    }
+
+    /// Reads a number, where zero indicates no limit.
+    fn limit<T: TryFrom<u64>>(&mut self) -> Result<Option<T>, BadFormat> {
+        match self.u64()? {
+            0 => Ok(None),
+            v => T::try_from(v).map(Some).or(Err(BadFormat)),
+        }
+    }
+
+    fn u64s(&mut self) -> Result<Vec<u64>, BadFormat> {
+        let count = self.u64()?;
+        if count == 0 {
+            return Err(BadFormat);
+        }
+
+        (0..count).map(|_| self.u64()).collect()
+    }
+
+    /// Reads one or more headers with unique keys, where keys and values must
+    /// be valid names.
+    fn headers(&mut self) -> Result<Headers, BadFormat> {
+        let headers = self.any_headers()?;
+        if headers.is_empty()
+            || !headers.iter().all(|(k, v)| is_name(k) && is_name(v))
+        {
+            return Err(BadFormat);
+        }
+
+        Ok(headers)
+    }
+
+    /// Reads any number of headers, with unique keys.
+    fn any_headers(&mut self) -> Result<Headers, BadFormat> {
+        let mut r = Headers::new();
+        for _ in 0..self.u64()? {
+            let (key, value) = (self.bytes()?, self.bytes()?);
+            if r.insert(key.to_vec(), value.to_vec()).is_some() {
+                return Err(BadFormat);
+            }
+        }
+
+        Ok(r)
+    }
+
+    fn reserve_mode(&mut self) -> Result<ReserveMode, BadFormat> {
+        match self.u8()? {
+            0 => Ok(ReserveMode::Priority),
+            1 => Ok(ReserveMode::Ordered),
+            _ => Err(BadFormat),
+        }
+    }
+
+    fn setting(&mut self) -> Result<TubeSetting, BadFormat> {
+        use TubeSetting as S;
+
+        Ok(match self.u8()? {
+            1 => S::DeadLetterTube(self.name_or_none()?),
+            2 => S::DeadLetterReleases(self.limit()?),
+            3 => S::DeadLetterTimeouts(self.limit()?),
+            4 => S::DeadLetterBuries(self.limit()?),
+            5 => S::DefaultTtr(self.limit()?),
+            6 => S::MaxJobSize(self.limit()?),
+            7 => S::MaxJobs(self.limit()?),
+            8 => S::MaxBytes(self.limit()?),
+            9 => S::TimeoutRequeue(match self.u8()? {
+                0 => None,
+                1 => Some(RequeuePos::Front),
+                2 => Some(RequeuePos::Back),
+                _ => return Err(BadFormat),
+            }),
+            10 => S::PriAging(self.limit()?),
+            11 => S::ReserveRate(self.limit()?),
+            12 => S::MaxReserved(self.limit()?),
+            13 => S::ExpiredTube(self.name_or_none()?),
+            14 => S::ResultRetention(self.limit()?),
+            15 => S::Weight(self.limit()?),
+            16 => S::ReserveRateInterval(self.limit()?),
+            _ => return Err(BadFormat),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use bytes::BufMut;
+    use futures::StreamExt;
+    use tokio::time::Instant;
+    use tokio_util::codec::{Encoder as _, FramedRead};
+
+    use super::*;
+    use crate::types::config::TubeConfig;
+    use crate::types::states::JobState;
+    use crate::types::tube::TubeStats;
+    use crate::wire::protocol::{
+        Capabilities, ClientInfo, JobStats, Limits, ScheduleInfo, ServerStats,
+        TubeStatsResp, WatchList,
+    };
+
+    /// Every command, along with its text protocol form.
+    #[allow(clippy::too_many_lines)]
+    fn commands() -> Vec<(&'static [u8], Command)> {
+        use Command as C;
+
+        let headers = Headers::from([
+            (b"trace-id".to_vec(), b"abc123".to_vec()),
+            (b"type".to_vec(), b"text/plain".to_vec()),
+        ]);
+
+        vec![
+            (
+                b"put 987 654 321 123",
+                C::Put {
+                    pri: 987,
+                    delay: 654,
+                    ttr: 321,
+                    n_bytes: 123,
+                },
+            ),
+            (
+                b"put-unique order-123 987 654 321 123",
+                C::PutUnique {
+                    key: b"order-123".to_vec(),
+                    pri: 987,
+                    delay: 654,
+                    ttr: 321,
+                    n_bytes: 123,
+                },
+            ),
+            (
+                b"put-with-headers 1 2 3 4 trace-id=abc123 type=text/plain",
+                C::PutWithHeaders {
+                    pri: 1,
+                    delay: 2,
+                    ttr: 3,
+                    n_bytes: 4,
+                    headers,
+                },
+            ),
+            (
+                b"put-with-ttl 987 654 321 3600 123",
+                C::PutWithTtl {
+                    pri: 987,
+                    delay: 654,
+                    ttr: 321,
+                    ttl: 3600,
+                    n_bytes: 123,
+                },
+            ),
+            (
+                b"put-with-reply replies 987 654 321 123",
+                C::PutWithReply {
+                    reply_tube: b"replies".to_vec(),
+                    pri: 987,
+                    delay: 654,
+                    ttr: 321,
+                    n_bytes: 123,
+                },
+            ),
+            (
+                b"put-at 1767254400 987 321 123",
+                C::PutAt {
+                    run_at: 1_767_254_400,
+                    pri: 987,
+                    ttr: 321,
+                    n_bytes: 123,
+                },
+            ),
+            (
+                b"reschedule 12 1767254400",
+                C::Reschedule {
+                    id: 12,
+                    run_at: 1_767_254_400,
+                },
+            ),
+            (
+                b"put-after 987 654 321 123 1 22 18446744073709551615",
+                C::PutAfter {
+                    pri: 987,
+                    delay: 654,
+                    ttr: 321,
+                    n_bytes: 123,
+                    after: vec![1, 22, u64::MAX],
+                },
+            ),
+            (b"reserve", C::Reserve),
+            (
+                b"reserve-with-timeout 4294967295",
+                C::ReserveWithTimeout { timeout: u32::MAX },
+            ),
+            (
+                b"reserve-batch 10 5",
+                C::ReserveBatch {
+                    count: 10,
+                    timeout: 5,
+                },
+            ),
+            (b"reserve-job 987", C::ReserveJob { id: 987 }),
+            (
+                b"release 987 654 321",
+                C::Release {
+                    id: 987,
+                    pri: 654,
+                    delay: 321,
+                },
+            ),
+            (b"delete 0", C::Delete { id: 0 }),
+            (b"bury 543 987", C::Bury { id: 543, pri: 987 }),
+            (b"touch 123", C::Touch { id: 123 }),
+            (
+                b"watch billing.*",
+                C::Watch {
+                    tube: b"billing.*".to_vec(),
+                },
+            ),
+            (
+                b"ignore default",
+                C::Ignore {
+                    tube: b"default".to_vec(),
+                },
+            ),
+            (b"peek 1", C::Peek { id: 1 }),
+            (b"peek-ready", C::PeekReady),
+            (b"peek-delayed", C::PeekDelayed),
+            (b"peek-buried", C::PeekBuried),
+            (b"kick 100", C::Kick { bound: 100 }),
+            (b"kick-job 42", C::KickJob { id: 42 }),
+            (b"stats-job 42", C::StatsJob { id: 42 }),
+            (
+                b"stats-tube default",
+                C::StatsTube {
+                    tube: b"default".to_vec(),
+                },
+            ),
+            (b"stats", C::StatsServer),
+            (b"list-tubes", C::ListTubes),
+            (b"list-tube-used", C::ListTubeUsed),
+            (b"list-tubes-watched", C::ListTubesWatched),
+            (b"quit", C::Quit),
+            (
+                b"pause-tube emails 60",
+                C::PauseTube {
+                    tube: b"emails".to_vec(),
+                    delay: 60,
+                },
+            ),
+            (
+                b"use tube_name_here-098+/;.()-",
+                C::Use {
+                    tube: b"tube_name_here-098+/;.()-".to_vec(),
+                },
+            ),
+            (b"set-pri 543 987", C::SetPri { id: 543, pri: 987 }),
+            (
+                b"move-job 5 other",
+                C::MoveJob {
+                    id: 5,
+                    tube: b"other".to_vec(),
+                },
+            ),
+            (
+                b"move-jobs other 10",
+                C::MoveJobs {
+                    tube: b"other".to_vec(),
+                    bound: 10,
+                },
+            ),
+            (
+                b"schedule tick 1 60 4 */5 * * * 1-5",
+                C::Schedule {
+                    name: b"tick".to_vec(),
+                    pri: 1,
+                    ttr: 60,
+                    n_bytes: 4,
+                    cron: Cron::parse(b"*/5 * * * 1-5").unwrap(),
+                },
+            ),
+            (
+                b"unschedule tick",
+                C::Unschedule {
+                    name: b"tick".to_vec(),
+                },
+            ),
+            (b"list-schedules", C::ListSchedules),
+            (
+                b"config-tube foo dead-letter-tube foo-dlq",
+                C::ConfigTube {
+                    tube: b"foo".to_vec(),
+                    setting: TubeSetting::DeadLetterTube(Some(
+                        b"foo-dlq".to_vec(),
+                    )),
+                },
+            ),
+            (b"enable-headers", C::EnableHeaders),
+            (b"enable-milliseconds", C::EnableMilliseconds),
+            (
+                b"complete 987 123",
+                C::Complete {
+                    id: 987,
+                    n_bytes: 123,
+                },
+            ),
+            (
+                b"result 987 10",
+                C::GetResult {
+                    id: 987,
+                    timeout: 10,
+                },
+            ),
+            (
+                b"subscribe events cache-a",
+                C::Subscribe {
+                    tube: b"events".to_vec(),
+                    subscriber: b"cache-a".to_vec(),
+                },
+            ),
+            (
+                b"unsubscribe events cache-a",
+                C::Unsubscribe {
+                    tube: b"events".to_vec(),
+                    subscriber: b"cache-a".to_vec(),
+                },
+            ),
+            (
+                b"reserve-mode ordered",
+                C::ReserveMode {
+                    mode: crate::types::tube::ReserveMode::Ordered,
+                },
+            ),
+            (
+                b"client-name worker-1",
+                C::ClientName {
+                    name: b"worker-1".to_vec(),
+                },
+            ),
+            (b"list-clients", C::ListClients),
+            (b"kill-client 3", C::KillClient { id: 3 }),
+            (b"capabilities", C::Capabilities),
+        ]
+    }
+
+    /// Every tube setting, along with its text protocol form.
+    fn settings() -> Vec<(&'static [u8], TubeSetting)> {
+        use TubeSetting as S;
+
+        vec![
+            (b"dead-letter-tube -", S::DeadLetterTube(None)),
+            (b"dead-letter-releases 3", S::DeadLetterReleases(Some(3))),
+            (b"dead-letter-timeouts 0", S::DeadLetterTimeouts(None)),
+            (b"dead-letter-buries 2", S::DeadLetterBuries(Some(2))),
+            (b"default-ttr 60", S::DefaultTtr(Some(60))),
+            (b"max-job-size 1024", S::MaxJobSize(Some(1024))),
+            (b"max-jobs 10", S::MaxJobs(Some(10))),
+            (b"max-bytes 0", S::MaxBytes(None)),
+            (
+                b"timeout-requeue front",
+                S::TimeoutRequeue(Some(RequeuePos::Front)),
+            ),
+            (b"timeout-requeue -", S::TimeoutRequeue(None)),
+            (b"pri-aging 5", S::PriAging(Some(5))),
+            (b"reserve-rate 100", S::ReserveRate(Some(100))),
+            (
+                b"reserve-rate-interval 60",
+                S::ReserveRateInterval(Some(60)),
+            ),
+            (b"max-reserved 4", S::MaxReserved(Some(4))),
+            (
+                b"expired-tube expired",
+                S::ExpiredTube(Some(b"expired".to_vec())),
+            ),
+            (b"result-retention 300", S::ResultRetention(Some(300))),
+            (b"weight 3", S::Weight(Some(3))),
+        ]
+    }
+
+    fn encode_cmd(cmd: &Command) -> BytesMut {
+        let mut dst = BytesMut::new();
+        encode_command(cmd, &mut dst);
+        dst
+    }
+
+    /// Checks `cmd` parses from `line`, and survives encoding and decoding,
+    /// returning its opcode.
+    fn check_command(line: &[u8], cmd: &Command) -> u8 {
+        let parsed: Result<Command, Response> = line.try_into();
+        assert_eq!(parsed.as_ref(), Ok(cmd), "{}", line.escape_ascii());
+
+        let mut dst = encode_cmd(cmd);
+        let frame = take_frame(&mut dst, MAX_COMMAND_LEN).unwrap().unwrap();
+        assert!(dst.is_empty());
+        assert_eq!(decode_command(&frame).as_ref(), Ok(cmd));
+
+        frame[0]
+    }
+
+    #[test]
+    fn test_commands_round_trip() {
+        let mut opcodes = BTreeSet::new();
+        for (line, cmd) in commands() {
+            assert!(opcodes.insert(check_command(line, &cmd)), "{cmd:?}");
+        }
+        assert_eq!(opcodes, (1..=51).collect());
+
+        for (setting, s) in settings() {
+            let mut line = b"config-tube foo ".to_vec();
+            line.extend_from_slice(setting);
+            let cmd = Command::ConfigTube {
+                tube: b"foo".to_vec(),
+                setting: s,
+            };
+            check_command(&line, &cmd);
+        }
+    }
+
+    #[test]
+    fn test_bad_commands() {
+        let bf = |frame: &[u8]| {
+            assert_eq!(decode_command(frame), Err(Response::BadFormat));
+        };
+
+        assert_eq!(decode_command(&[]), Err(Response::BadFormat));
+        assert_eq!(decode_command(&[0]), Err(Response::UnknownCommand));
+        assert_eq!(decode_command(&[52]), Err(Response::UnknownCommand));
+
+        // Trailing data.
+        bf(&[9, 0]);
+        // Truncated.
+        bf(&[10]);
+        bf(&[10, 0x80]);
+        // Out of range for a u32, but not a u64.
+        bf(&[10, 0x80, 0x80, 0x80, 0x80, 0x10]);
+        assert_eq!(
+            decode_command(&[12, 0x80, 0x80, 0x80, 0x80, 0x10]),
+            Ok(Command::ReserveJob { id: 1 << 32 })
+        );
+        // Out of range for a u64.
+        bf(&[
+            12, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x02,
+        ]);
+        // Invalid names.
+        bf(&[33, 0]);
+        bf(&[33, 4, b'-', b'f', b'o', b'o']);
+        bf(&[33, 3, b'f', b'o', b'o', b'*']);
+        // Zero TTL and count, and too large a count.
+        bf(&[4, 1, 2, 3, 0, 4]);
+        bf(&[11, 0, 5]);
+        bf(&[11, 0xe9, 0x07, 5]);
+        // No prerequisites or headers.
+        bf(&[8, 1, 2, 3, 4, 0]);
+        bf(&[3, 1, 2, 3, 4, 0]);
+        // Duplicate headers.
+        bf(&[3, 1, 2, 3, 4, 2, 1, b'a', 1, b'b', 1, b'a', 1, b'c']);
+        // Invalid settings.
+        bf(&[40, 3, b'f', b'o', b'o', 17, 0]);
+        bf(&[40, 3, b'f', b'o', b'o', 9, 3]);
+        bf(&[40, 3, b'f', b'o', b'o', 5, 0x80, 0x80, 0x80, 0x80, 0x10]);
+        // Invalid reserve mode.
+        bf(&[47, 2]);
+    }
+
+    /// Every response, with some sample data.
+    #[allow(clippy::too_many_lines)]
+    fn responses() -> Vec<Response> {
+        use Response as R;
+
+        let headers = Headers::from([(b"trace-id".to_vec(), b"abc".to_vec())]);
+
+        vec![
+            R::OutOfMemory,
+            R::InternalError,
+            R::BadFormat,
+            R::UnknownCommand,
+            R::Inserted { id: 1 },
+            R::BuriedID { id: u64::MAX },
+            R::ExpectedCRLF,
+            R::JobTooBig,
+            R::QuotaExceeded,
+            R::Draining,
+            R::Using {
+                tube: b"default".to_vec(),
+            },
+            R::DeadlineSoon,
+            R::TimedOut,
+            R::Reserved { id: 3 },
+            R::ReservedWithHeaders {
+                id: 3,
+                n_bytes: 5,
+                headers: headers.clone(),
+            },
+            R::ReservedBatch {
+                jobs: vec![
+                    ReservedJob {
+                        id: 1,
+                        data: Bytes::from_static(b"hello"),
+                    },
+                    ReservedJob {
+                        id: 23,
+                        data: Bytes::new(),
+                    },
+                ],
+            },
+            R::Found { id: 4 },
+            R::FoundWithHeaders {
+                id: 4,
+                n_bytes: 0,
+                headers: Headers::new(),
+            },
+            R::JobChunk(Bytes::from_static(b"\r\n\0binary")),
+            R::JobEnd,
+            R::NotFound,
+            R::Deleted,
+            R::Released,
+            R::Buried,
+            R::Touched,
+            R::Watching { count: 2 },
+            R::NotIgnored,
+            R::KickedCount { count: 7 },
+            R::Kicked,
+            R::OkStatsJob {
+                data: JobStats {
+                    id: 1,
+                    tube: b"default".to_vec(),
+                    state: JobState::Reserved {
+                        deadline: Instant::now(),
+                    },
+                    pri: 2,
+                    age: 3,
+                    delay: 4,
+                    ttr: 5,
+                    time_left: 6,
+                    run_at: Some(7),
+                    file: 0,
+                    original_tube: None,
+                    headers: [("a".to_string(), "b".to_string())].into(),
+                    reserves: 8,
+                    timeouts: 9,
+                    releases: 10,
+                    buries: 11,
+                    kicks: 12,
+                },
+            },
+            R::OkStats {
+                data: ServerStats::default(),
+            },
+            R::OkCapabilities {
+                data: Capabilities {
+                    version: "0.1.0",
+                    extensions: &["binary-protocol"],
+                    limits: Limits {
+                        max_job_size: 65535,
+                        max_tube_name_length: 200,
+                    },
+                    durability: false,
+                    replication: false,
+                },
+            },
+            R::OkStatsTube {
+                data: TubeStatsResp {
+                    name: b"default".to_vec(),
+                    ts: TubeStats::default(),
+                    pause_time_left: 0,
+                    config: TubeConfig::default(),
+                },
+            },
+            R::OkListTubes {
+                tubes: vec![b"default".to_vec(), b"other".to_vec()],
+            },
+            R::OkWatchList {
+                data: WatchList {
+                    mode: ReserveMode::Ordered,
+                    tubes: vec![b"default".to_vec()],
+                },
+            },
+            R::OkListClients {
+                clients: vec![ClientInfo {
+                    id: 1,
+                    peer: "127.0.0.1:1234".to_string(),
+                    name: Some(b"worker".to_vec()),
+                    tube: b"default".to_vec(),
+                    watching: vec![b"default".to_vec()],
+                    reserved: vec![3],
+                    idle: 4,
+                }],
+            },
+            R::Paused,
+            R::PriSet,
+            R::Moved,
+            R::Rescheduled,
+            R::MovedCount { count: 5 },
+            R::Scheduled,
+            R::Unscheduled,
+            R::OkListSchedules {
+                schedules: vec![ScheduleInfo {
+                    name: b"tick".to_vec(),
+                    tube: b"default".to_vec(),
+                    cron: "*/5 * * * 1-5".to_string(),
+                    pri: 1,
+                    ttr: 60,
+                    next_run: None,
+                }],
+            },
+            R::Configured,
+            R::HeadersEnabled,
+            R::MillisecondsEnabled,
+            R::Completed,
+            R::JobResult { id: 9, n_bytes: 10 },
+            R::Subscribed,
+            R::Unsubscribed,
+            R::ReserveModeSet {
+                mode: ReserveMode::Priority,
+            },
+            R::Named,
+            R::Killed,
+        ]
+    }
+
+    fn encode_text(resp: Response) -> BytesMut {
+        let mut dst = BytesMut::new();
+        encoder::Encoder::default().encode(resp, &mut dst).unwrap();
+        dst
+    }
+
+    #[test]
+    fn test_responses_round_trip() {
+        let mut opcodes = BTreeSet::new();
+        let mut documents = Vec::new();
+        for (resp, again) in responses().into_iter().zip(responses()) {
+            let mut dst = BytesMut::new();
+            encode_response(resp, &mut dst).unwrap();
+
+            let frame = take_frame(&mut dst, usize::MAX).unwrap().unwrap();
+            assert!(dst.is_empty());
+            opcodes.insert(frame[0]);
+
+            // Responses with YAML data decode to the same document as the
+            // text protocol sends.
+            match decode_response(&frame).unwrap() {
+                ClientResponse::Response(decoded) => assert_eq!(decoded, again),
+                ClientResponse::Ok(data) => {
+                    let mut text =
+                        format!("OK {}\r\n", data.len()).into_bytes();
+                    text.extend_from_slice(&data);
+                    text.extend_from_slice(b"\r\n");
+                    assert_eq!(&encode_text(again)[..], &text[..]);
+                    documents.push(String::from_utf8(data.to_vec()).unwrap());
+                },
+            }
+        }
+
+        // Names are given as strings. Only the start of the longer documents,
+        // from stats-job, stats and stats-tube, is checked.
+        let [job, stats, caps, tube, rest @ ..] = &documents[..] else {
+            panic!("{documents:?}");
+        };
+        assert!(job.starts_with("id: 1\ntube: default\nstate: reserved\n"));
+        assert!(stats.starts_with("current-jobs-urgent: 0\n"));
+        assert_eq!(
+            caps,
+            "version: 0.1.0\nextensions:\n- binary-protocol\nlimits:\n  \
+             max-job-size: 65535\n  max-tube-name-length: 200\n\
+             durability: false\nreplication: false\n"
+        );
+        assert!(tube.starts_with("name: default\ncurrent-jobs-urgent: 0\n"));
+        assert_eq!(
+            rest,
+            [
+                "- default\n- other\n",
+                "mode: ordered\ntubes:\n- default\n",
+                "- id: 1\n  peer: 127.0.0.1:1234\n  name: worker\n  \
+                 tube: default\n  watching:\n  - default\n  reserved:\n  \
+                 - 3\n  idle: 4\n",
+                "- name: tick\n  tube: default\n  cron: '*/5 * * * 1-5'\n  \
+                 pri: 1\n  ttr: 60\n  next-run: null\n",
+            ]
+        );
+
+        let mut expected: BTreeSet<u8> = (0..=47).collect();
+        expected.remove(&19);
+        assert_eq!(opcodes, expected);
+    }
+
+    #[tokio::test]
+    async fn test_decoder() {
+        use BeanstalkClientEvent as E;
+
+        let mut input = BytesMut::new();
+        input.put_slice(&MAGIC);
+        input.unsplit(encode_cmd(&Command::Put {
+            pri: 1,
+            delay: 2,
+            ttr: 3,
+            n_bytes: 6,
+        }));
+        encode_data(b"abc", &mut input);
+        encode_data(b"def", &mut input);
+        input.unsplit(encode_cmd(&Command::Complete { id: 4, n_bytes: 0 }));
+        input.unsplit(encode_cmd(&Command::Quit));
+
+        let input = input.freeze();
+        let mut reader = FramedRead::new(&input[..], Decoder::default());
+        let mut events = Vec::new();
+        while let Some(evt) = reader.next().await {
+            events.push(evt.unwrap());
+        }
+
+        assert_eq!(
+            events,
+            vec![
+                E::Command(Command::Put {
+                    pri: 1,
+                    delay: 2,
+                    ttr: 3,
+                    n_bytes: 6,
+                }),
+                E::PutChunk(Bytes::from_static(b"abc")),
+                E::PutChunk(Bytes::from_static(b"def")),
+                E::PutEnd,
+                E::Command(Command::Complete { id: 4, n_bytes: 0 }),
+                E::PutEnd,
+                E::Command(Command::Quit),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_data_frames() {
+        use BeanstalkClientEvent as E;
+        use codec::Decoder as _;
+
+        // Data is split into frames of at most MAX_DATA_LEN bytes.
+        let mut dst = BytesMut::new();
+        encode_data(&vec![0; MAX_DATA_LEN as usize + 1], &mut dst);
+        let frame = take_frame(&mut dst, usize::MAX).unwrap().unwrap();
+        assert_eq!(frame.len(), MAX_DATA_LEN as usize + 1);
+        assert_eq!(
+            &take_frame(&mut dst, usize::MAX).unwrap().unwrap()[..],
+            [DATA, 0]
+        );
+        assert!(dst.is_empty());
+
+        // Data is passed on as it arrives, rather than once a whole frame has.
+        let mut decoder = Decoder::default();
+        let put = Command::Put {
+            pri: 1,
+            delay: 2,
+            ttr: 3,
+            n_bytes: 6,
+        };
+        let mut src = BytesMut::from(&MAGIC[..]);
+        src.unsplit(encode_cmd(&put));
+        let mut data = BytesMut::new();
+        encode_data(b"abcdef", &mut data);
+        src.put_slice(&data[..8]);
+
+        assert_eq!(decoder.decode(&mut src).unwrap(), Some(E::Command(put)));
+        assert_eq!(
+            decoder.decode(&mut src).unwrap(),
+            Some(E::PutChunk(Bytes::from_static(b"abc")))
+        );
+        assert_eq!(decoder.decode(&mut src).unwrap(), None);
+        src.put_slice(&data[8..]);
+        assert_eq!(
+            decoder.decode(&mut src).unwrap(),
+            Some(E::PutChunk(Bytes::from_static(b"def")))
+        );
+        assert_eq!(decoder.decode(&mut src).unwrap(), Some(E::PutEnd));
+    }
+
+    #[tokio::test]
+    async fn test_decoder_errors() {
+        async fn first_error(input: &[u8]) -> Response {
+            let mut reader = FramedRead::new(input, Decoder::default());
+            loop {
+                match reader.next().await {
+                    Some(Err(decoder::Error::Client(resp))) => return resp,
+                    Some(Ok(_)) => {},
+                    x => panic!("unexpected {x:?}"),
+                }
+            }
+        }
+
+        // Not binary.
+        assert_eq!(first_error(b"put 1 2 3 4\r\n").await, Response::BadFormat);
+
+        let mut put = BytesMut::new();
+        put.put_slice(&MAGIC);
+        put.unsplit(encode_cmd(&Command::Put {
+            pri: 1,
+            delay: 2,
+            ttr: 3,
+            n_bytes: 2,
+        }));
+
+        // Too much data.
+        let mut input = put.clone();
+        encode_data(b"abc", &mut input);
+        assert_eq!(first_error(&input).await, Response::BadFormat);
+
+        // An empty data frame.
+        let mut input = put.clone();
+        input.put_u32(1);
+        input.put_u8(DATA);
+        assert_eq!(first_error(&input).await, Response::BadFormat);
+
+        // A data frame longer than allowed, even for a job big enough, which
+        // is refused from its header alone.
+        let mut input = BytesMut::from(&MAGIC[..]);
+        input.unsplit(encode_cmd(&Command::Put {
+            pri: 1,
+            delay: 2,
+            ttr: 3,
+            n_bytes: u32::MAX,
+        }));
+        input.put_u32(MAX_DATA_LEN + 2);
+        input.put_u8(DATA);
+        assert_eq!(first_error(&input).await, Response::BadFormat);
+
+        // A command instead of data.
+        let mut input = put;
+        input.unsplit(encode_cmd(&Command::Quit));
+        assert_eq!(first_error(&input).await, Response::BadFormat);
+
+        // An overly long command.
+        let mut input = BytesMut::from(&MAGIC[..]);
+        input.put_u32(u32::MAX);
+        assert_eq!(first_error(&input).await, Response::BadFormat);
+    }
+}
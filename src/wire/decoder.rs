@@ -59,7 +59,7 @@ impl codec::Decoder for Decoder {
 
                         let cmd: Command = cmd.as_ref().try_into()?;
 
-                        if let Command::Put { n_bytes, .. } = cmd {
+                        if let Some(n_bytes) = cmd.put_bytes() {
                             let n_bytes = n_bytes as usize;
 
                             // Reserve up to MAX_BUFFER_RESERVATION bytes to
@@ -218,6 +218,8 @@ mod tests {
             "0000000",
             "put 10002 2 62 6",
             "11\r\n11",
+            "put-unique key 10003 3 63 5",
+            "22222",
             "quit",
         ]);
 
@@ -255,6 +257,15 @@ mod tests {
             }),
             chunk(b"11\r\n11"),
             BeanstalkClientEvent::PutEnd,
+            cmd(Command::PutUnique {
+                key: b"key".into(),
+                pri: 10003,
+                delay: 3,
+                ttr: 63,
+                n_bytes: 5,
+            }),
+            chunk(b"22222"),
+            BeanstalkClientEvent::PutEnd,
             cmd(Command::Quit),
         ];
 
@@ -4,7 +4,9 @@ use bytes::BufMut;
 use serde::ser;
 use tokio_util::codec;
 
-use super::protocol::Response;
+use super::protocol::{ReservedJob, Response, TubeNames};
+use crate::types::job::Headers;
+use crate::types::tube::ReserveMode;
 
 // An encoder to produce Beanstalk client messages
 #[derive(Debug, Default)]
@@ -82,9 +84,80 @@ impl codec::Encoder<Response> for Encoder {
             dst.put_slice(b"\r\n");
         }
 
+        fn put_job_with_headers(
+            dst: &mut bytes::BytesMut,
+            str: &[u8],
+            id: u64,
+            n_bytes: u32,
+            headers: &Headers,
+        ) -> () {
+            //! Writes `"{str} {id} {n_bytes} {key}={value} ...\r\n"` to `dst`
+            let id_str = id.to_string().into_bytes();
+            let n_bytes_str = n_bytes.to_string().into_bytes();
+            // " {key}={value}" for each header
+            let headers_len: usize =
+                headers.iter().map(|(k, v)| 1 + k.len() + 1 + v.len()).sum();
+            // "{str} {id} {n_bytes}{headers}\r\n"
+            dst.reserve(
+                str.len()
+                    + 1
+                    + id_str.len()
+                    + 1
+                    + n_bytes_str.len()
+                    + headers_len
+                    + 2,
+            );
+
+            dst.put_slice(str);
+            dst.put_slice(b" ");
+            dst.extend(id_str);
+            dst.put_slice(b" ");
+            dst.extend(n_bytes_str);
+            for (key, value) in headers {
+                dst.put_slice(b" ");
+                dst.put_slice(key);
+                dst.put_slice(b"=");
+                dst.put_slice(value);
+            }
+            dst.put_slice(b"\r\n");
+        }
+
+        fn put_reserved_batch(
+            dst: &mut bytes::BytesMut,
+            jobs: Vec<ReservedJob>,
+        ) -> () {
+            //! Writes `"RESERVED_BATCH {n}\r\n"`, then for each job,
+            //! `"RESERVED {id} {len}\r\n{data}\r\n"` to `dst`.
+            put_str_and_u64(dst, b"RESERVED_BATCH", jobs.len() as u64);
+
+            for job in jobs {
+                let id_str = job.id.to_string().into_bytes();
+                let len_str = job.data.len().to_string().into_bytes();
+                // "RESERVED {id} {len}\r\n{data}\r\n"
+                dst.reserve(
+                    9 + id_str.len()
+                        + 1
+                        + len_str.len()
+                        + 2
+                        + job.data.len()
+                        + 2,
+                );
+
+                dst.put_slice(b"RESERVED ");
+                dst.extend(id_str);
+                dst.put_slice(b" ");
+                dst.extend(len_str);
+                dst.put_slice(b"\r\n");
+                dst.extend(job.data);
+                dst.put_slice(b"\r\n");
+            }
+        }
+
         Ok(match item {
             BadFormat => dst.put_slice(b"BAD_FORMAT\r\n"),
             Buried => dst.put_slice(b"BURIED\r\n"),
+            Completed => dst.put_slice(b"COMPLETED\r\n"),
+            Configured => dst.put_slice(b"CONFIGURED\r\n"),
             DeadlineSoon => dst.put_slice(b"DEADLINE_SOON\r\n"),
             Deleted => dst.put_slice(b"DELETED\r\n"),
             Draining => dst.put_slice(b"DRAINING\r\n"),
@@ -92,24 +165,43 @@ impl codec::Encoder<Response> for Encoder {
             InternalError => dst.put_slice(b"INTERNAL_ERROR\r\n"),
             JobTooBig => dst.put_slice(b"JOB_TOO_BIG\r\n"),
             Kicked => dst.put_slice(b"KICKED\r\n"),
+            Killed => dst.put_slice(b"KILLED\r\n"),
+            Moved => dst.put_slice(b"MOVED\r\n"),
+            Named => dst.put_slice(b"NAMED\r\n"),
             NotFound => dst.put_slice(b"NOT_FOUND\r\n"),
             NotIgnored => dst.put_slice(b"NOT_IGNORED\r\n"),
             OutOfMemory => dst.put_slice(b"OUT_OF_MEMORY\r\n"),
             Paused => dst.put_slice(b"PAUSED\r\n"),
+            HeadersEnabled => dst.put_slice(b"HEADERS_ENABLED\r\n"),
+            MillisecondsEnabled => {
+                dst.put_slice(b"MILLISECONDS_ENABLED\r\n");
+            },
+            PriSet => dst.put_slice(b"PRI_SET\r\n"),
+            QuotaExceeded => dst.put_slice(b"QUOTA_EXCEEDED\r\n"),
             Released => dst.put_slice(b"RELEASED\r\n"),
+            Rescheduled => dst.put_slice(b"RESCHEDULED\r\n"),
+            Scheduled => dst.put_slice(b"SCHEDULED\r\n"),
+            Subscribed => dst.put_slice(b"SUBSCRIBED\r\n"),
             TimedOut => dst.put_slice(b"TIMED_OUT\r\n"),
             Touched => dst.put_slice(b"TOUCHED\r\n"),
             UnknownCommand => dst.put_slice(b"UNKNOWN_COMMAND\r\n"),
+            Unscheduled => dst.put_slice(b"UNSCHEDULED\r\n"),
+            Unsubscribed => dst.put_slice(b"UNSUBSCRIBED\r\n"),
 
             BuriedID { id } => put_str_and_u64(dst, b"BURIED", id),
             Inserted { id } => put_str_and_u64(dst, b"INSERTED", id),
             KickedCount { count } => put_str_and_u64(dst, b"KICKED", count),
+            MovedCount { count } => put_str_and_u64(dst, b"MOVED", count),
             Watching { count } => put_str_and_u32(dst, b"WATCHING", count),
 
             OkStatsJob { data } => put_ok_and_data(dst, data)?,
             OkStats { data } => put_ok_and_data(dst, data)?,
-            OkListTubes { tubes } => put_ok_and_data(dst, tubes)?,
+            OkCapabilities { data } => put_ok_and_data(dst, data)?,
+            OkListTubes { tubes } => put_ok_and_data(dst, TubeNames(&tubes))?,
+            OkWatchList { data } => put_ok_and_data(dst, data)?,
+            OkListClients { clients } => put_ok_and_data(dst, clients)?,
             OkStatsTube { data } => put_ok_and_data(dst, data)?,
+            OkListSchedules { schedules } => put_ok_and_data(dst, schedules)?,
 
             Using { tube } => {
                 // "USING {tube}\r\n"
@@ -120,8 +212,33 @@ impl codec::Encoder<Response> for Encoder {
                 dst.put_slice(b"\r\n");
             },
 
+            ReserveModeSet { mode } => dst.put_slice(match mode {
+                ReserveMode::Priority => b"RESERVE_MODE priority\r\n",
+                ReserveMode::Ordered => b"RESERVE_MODE ordered\r\n",
+            }),
+
             Reserved { id } => put_str_and_u64(dst, b"RESERVED", id),
+            ReservedBatch { jobs } => put_reserved_batch(dst, jobs),
             Found { id } => put_str_and_u64(dst, b"FOUND", id),
+            JobResult { id, n_bytes } => {
+                put_job_with_headers(
+                    dst,
+                    b"RESULT",
+                    id,
+                    n_bytes,
+                    &Headers::new(),
+                );
+            },
+            ReservedWithHeaders {
+                id,
+                n_bytes,
+                headers,
+            } => put_job_with_headers(dst, b"RESERVED", id, n_bytes, &headers),
+            FoundWithHeaders {
+                id,
+                n_bytes,
+                headers,
+            } => put_job_with_headers(dst, b"FOUND", id, n_bytes, &headers),
             JobChunk(data) => dst.extend(data),
             JobEnd => dst.put_slice(b"\r\n"),
         })
@@ -153,3 +270,287 @@ impl From<serde_yaml::Error> for Error {
         Self::Serde(value)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use bytes::{Bytes, BytesMut};
+    use codec::Encoder as _;
+
+    use super::*;
+
+    #[test]
+    fn test_job_with_headers() {
+        let mut dst = BytesMut::new();
+        let headers = Headers::from([
+            (b"trace-id".to_vec(), b"abc123".to_vec()),
+            (b"content-type".to_vec(), b"application/json".to_vec()),
+        ]);
+
+        let mut encoder = Encoder::default();
+        encoder
+            .encode(
+                Response::ReservedWithHeaders {
+                    id: 1,
+                    n_bytes: 5,
+                    headers: headers.clone(),
+                },
+                &mut dst,
+            )
+            .unwrap();
+        encoder
+            .encode(
+                Response::FoundWithHeaders {
+                    id: 2,
+                    n_bytes: 0,
+                    headers: Headers::new(),
+                },
+                &mut dst,
+            )
+            .unwrap();
+        encoder
+            .encode(Response::JobResult { id: 3, n_bytes: 7 }, &mut dst)
+            .unwrap();
+
+        assert_eq!(
+            &dst[..],
+            b"RESERVED 1 5 content-type=application/json trace-id=abc123\r\n\
+              FOUND 2 0\r\n\
+              RESULT 3 7\r\n"
+        );
+    }
+
+    #[test]
+    fn test_reserve_mode() {
+        let mut dst = BytesMut::new();
+
+        Encoder::default()
+            .encode(
+                Response::ReserveModeSet {
+                    mode: ReserveMode::Ordered,
+                },
+                &mut dst,
+            )
+            .unwrap();
+
+        assert_eq!(&dst[..], b"RESERVE_MODE ordered\r\n");
+    }
+
+    #[test]
+    fn test_reserved_batch() {
+        let mut dst = BytesMut::new();
+        let jobs = vec![
+            ReservedJob {
+                id: 1,
+                data: Bytes::from_static(b"hello"),
+            },
+            ReservedJob {
+                id: 23,
+                data: Bytes::new(),
+            },
+        ];
+
+        Encoder::default()
+            .encode(Response::ReservedBatch { jobs }, &mut dst)
+            .unwrap();
+
+        assert_eq!(
+            &dst[..],
+            b"RESERVED_BATCH 2\r\nRESERVED 1 5\r\nhello\r\nRESERVED 23 0\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn test_capabilities() {
+        use crate::wire::protocol::{Capabilities, Limits};
+
+        let mut dst = BytesMut::new();
+        let data = Capabilities {
+            version: "0.1.0",
+            extensions: &["put-unique", "set-pri"],
+            limits: Limits {
+                max_job_size: 65535,
+                max_tube_name_length: 200,
+            },
+            durability: false,
+            replication: false,
+        };
+
+        Encoder::default()
+            .encode(Response::OkCapabilities { data }, &mut dst)
+            .unwrap();
+
+        let yaml = "version: 0.1.0\nextensions:\n- put-unique\n- set-pri\n\
+                    limits:\n  max-job-size: 65535\n  max-tube-name-length: 200\n\
+                    durability: false\nreplication: false\n";
+        let expected = format!("OK {}\r\n{yaml}\r\n", yaml.len());
+        assert_eq!(&dst[..], expected.as_bytes());
+    }
+
+    #[test]
+    fn test_tube_lists() {
+        use crate::wire::protocol::WatchList;
+
+        let mut dst = BytesMut::new();
+        let tubes = vec![b"urgent".to_vec(), b"normal".to_vec()];
+
+        let mut encoder = Encoder::default();
+        encoder
+            .encode(
+                Response::OkListTubes {
+                    tubes: tubes.clone(),
+                },
+                &mut dst,
+            )
+            .unwrap();
+        encoder
+            .encode(
+                Response::OkWatchList {
+                    data: WatchList {
+                        mode: ReserveMode::Ordered,
+                        tubes,
+                    },
+                },
+                &mut dst,
+            )
+            .unwrap();
+
+        assert_eq!(
+            &dst[..],
+            b"OK 18\r\n- urgent\n- normal\n\r\n\
+              OK 39\r\nmode: ordered\ntubes:\n- urgent\n- normal\n\r\n"
+        );
+    }
+
+    #[test]
+    fn test_list_clients() {
+        use crate::wire::protocol::ClientInfo;
+
+        let mut dst = BytesMut::new();
+        let clients = vec![ClientInfo {
+            id: 1,
+            peer: "127.0.0.1:1234".into(),
+            name: Some(b"worker-1".to_vec()),
+            tube: b"default".to_vec(),
+            watching: vec![b"default".to_vec(), b"jobs.*".to_vec()],
+            reserved: vec![7],
+            idle: 5,
+        }];
+
+        Encoder::default()
+            .encode(Response::OkListClients { clients }, &mut dst)
+            .unwrap();
+
+        let yaml = "- id: 1\n  peer: 127.0.0.1:1234\n  name: worker-1\n  \
+                    tube: default\n  watching:\n  - default\n  - jobs.*\n  \
+                    reserved:\n  - 7\n  idle: 5\n";
+        let expected = format!("OK {}\r\n{yaml}\r\n", yaml.len());
+        assert_eq!(&dst[..], expected.as_bytes());
+    }
+
+    #[test]
+    fn test_list_schedules() {
+        use crate::wire::protocol::ScheduleInfo;
+
+        let mut dst = BytesMut::new();
+        let schedules = vec![ScheduleInfo {
+            name: b"nightly".to_vec(),
+            tube: b"reports".to_vec(),
+            cron: "0 3 * * *".into(),
+            pri: 10,
+            ttr: 60,
+            next_run: Some(1_700_000_000),
+        }];
+
+        Encoder::default()
+            .encode(Response::OkListSchedules { schedules }, &mut dst)
+            .unwrap();
+
+        let yaml = "- name: nightly\n  tube: reports\n  cron: 0 3 * * *\n  \
+                    pri: 10\n  ttr: 60\n  next-run: 1700000000\n";
+        let expected = format!("OK {}\r\n{yaml}\r\n", yaml.len());
+        assert_eq!(&dst[..], expected.as_bytes());
+    }
+
+    #[test]
+    fn test_stats_job() {
+        use std::collections::BTreeMap;
+
+        use crate::types::states::JobState;
+        use crate::wire::protocol::JobStats;
+
+        let mut dst = BytesMut::new();
+        let data = JobStats {
+            id: 3,
+            tube: b"dead".to_vec(),
+            state: JobState::Reserved {
+                deadline: tokio::time::Instant::now(),
+            },
+            pri: 10,
+            age: 5,
+            delay: 0,
+            ttr: 60,
+            time_left: 0,
+            run_at: None,
+            file: 0,
+            original_tube: Some(b"jobs".to_vec()),
+            headers: BTreeMap::new(),
+            reserves: 3,
+            timeouts: 0,
+            releases: 3,
+            buries: 0,
+            kicks: 0,
+        };
+
+        Encoder::default()
+            .encode(Response::OkStatsJob { data }, &mut dst)
+            .unwrap();
+
+        let yaml = "id: 3\ntube: dead\nstate: reserved\npri: 10\nage: 5\n\
+                    delay: 0\nttr: 60\ntime-left: 0\nfile: 0\n\
+                    original-tube: jobs\nreserves: 3\ntimeouts: 0\n\
+                    releases: 3\nburies: 0\nkicks: 0\n";
+        let expected = format!("OK {}\r\n{yaml}\r\n", yaml.len());
+        assert_eq!(&dst[..], expected.as_bytes());
+    }
+
+    #[test]
+    fn test_stats_tube() {
+        use crate::types::config::TubeConfig;
+        use crate::types::tube::TubeStats;
+        use crate::wire::protocol::TubeStatsResp;
+
+        let mut dst = BytesMut::new();
+        let data = TubeStatsResp {
+            name: b"jobs".to_vec(),
+            ts: TubeStats::default(),
+            pause_time_left: 0,
+            config: TubeConfig {
+                dead_letter_tube: Some(b"dead".to_vec()),
+                dead_letter_releases: Some(3),
+                expired_tube: Some(b"stale".to_vec()),
+                subscribers: [b"audit".to_vec(), b"billing".to_vec()].into(),
+                ..TubeConfig::default()
+            },
+        };
+
+        Encoder::default()
+            .encode(Response::OkStatsTube { data }, &mut dst)
+            .unwrap();
+
+        let stats = "current-jobs-urgent: 0\ncurrent-jobs-ready: 0\n\
+                     current-jobs-reserved: 0\ncurrent-jobs-delayed: 0\n\
+                     current-jobs-buried: 0\ncurrent-jobs-waiting: 0\n\
+                     total-jobs: 0\ncurrent-using: 0\ncurrent-waiting: 0\n\
+                     current-watching: 0\npause: 0\ncurrent-bytes: 0\n\
+                     current-stored-bytes: 0\ncmd-delete: 0\n\
+                     cmd-pause-tube: 0\nreserves-throttled: 0\n\
+                     total-jobs-expired: 0\ntotal-reserves: 0\n";
+        let yaml = format!(
+            "name: jobs\n{stats}pause-time-left: 0\n\
+             dead-letter-tube: dead\ndead-letter-releases: 3\n\
+             expired-tube: stale\nsubscribers:\n- audit\n- billing\n"
+        );
+        let expected = format!("OK {}\r\n{yaml}\r\n", yaml.len());
+        assert_eq!(&dst[..], expected.as_bytes());
+    }
+}
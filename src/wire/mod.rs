@@ -3,6 +3,7 @@ use protocol::Response;
 use tokio::io::{AsyncRead, AsyncWrite};
 use tokio_util::codec::{self, Framed};
 
+pub mod binary;
 pub mod decoder;
 pub mod encoder;
 pub mod events;
@@ -13,10 +14,35 @@ pub fn framed<T: AsyncRead + AsyncWrite>(stream: T) -> Framed<T, Codec> {
     Framed::new(stream, Default::default())
 }
 
-#[derive(Default)]
-pub struct Codec {
-    d: decoder::Decoder,
-    e: encoder::Encoder,
+/// Frames a stream using the binary protocol, see [`binary`].
+pub fn framed_binary<T: AsyncRead + AsyncWrite>(stream: T) -> Framed<T, Codec> {
+    Framed::new(
+        stream,
+        Codec::Binary {
+            d: binary::Decoder::default(),
+            e: binary::Encoder::default(),
+        },
+    )
+}
+
+pub enum Codec {
+    Text {
+        d: decoder::Decoder,
+        e: encoder::Encoder,
+    },
+    Binary {
+        d: binary::Decoder,
+        e: binary::Encoder,
+    },
+}
+
+impl Default for Codec {
+    fn default() -> Self {
+        Self::Text {
+            d: decoder::Decoder::default(),
+            e: encoder::Encoder::default(),
+        }
+    }
 }
 
 impl codec::Decoder for Codec {
@@ -28,7 +54,10 @@ impl codec::Decoder for Codec {
         &mut self,
         src: &mut bytes::BytesMut,
     ) -> Result<Option<Self::Item>, Self::Error> {
-        self.d.decode(src)
+        match self {
+            Self::Text { d, .. } => d.decode(src),
+            Self::Binary { d, .. } => d.decode(src),
+        }
     }
 }
 
@@ -40,6 +69,9 @@ impl codec::Encoder<Response> for Codec {
         item: Response,
         dst: &mut bytes::BytesMut,
     ) -> Result<(), Self::Error> {
-        self.e.encode(item, dst)
+        match self {
+            Self::Text { e, .. } => e.encode(item, dst),
+            Self::Binary { e, .. } => e.encode(item, dst),
+        }
     }
 }
@@ -1,6 +1,10 @@
 //! implements a parser for the beanstalkd TCP protocol.
 
-use super::protocol::{Command, Response};
+use super::protocol::{Command, MAX_NAME_LEN, MAX_RESERVE_BATCH, Response};
+use crate::types::config::{RequeuePos, TubeSetting};
+use crate::types::cron::Cron;
+use crate::types::job::Headers;
+use crate::types::tube::ReserveMode;
 
 /// Provides a custom, minimal, zero-copy parser of byte slices.
 struct ParseState<'a> {
@@ -75,6 +79,28 @@ impl ParseState<'_> {
         Ok(r)
     }
 
+    /// Consumes the rest of the input, expecting one or more u64s, each
+    /// preceded by a space.
+    fn expect_rest_u64s(&mut self) -> Result<Vec<u64>, Response> {
+        let mut r = vec![self.expect_next_u64()?];
+        while !self.from.is_empty() {
+            r.push(self.expect_next_u64()?);
+        }
+
+        Ok(r)
+    }
+
+    /// Consumes the rest of the input, expecting a space then a cron
+    /// expression.
+    fn expect_rest_cron(&mut self) -> Result<Cron, Response> {
+        self.expect_space()?;
+
+        let cron = Cron::parse(self.from).ok_or(Response::BadFormat)?;
+        self.from = &[];
+
+        Ok(cron)
+    }
+
     /// Consumes from the input, expecting a space then a name.
     fn expect_next_name(&mut self) -> Result<Vec<u8>, Response> {
         self.expect_space()?;
@@ -82,28 +108,150 @@ impl ParseState<'_> {
         let token = self.expect_next_token()?;
         let r: Vec<u8> = token.iter().map(|v| *v).collect();
 
-        fn char_is_name_safe(c: u8, is_first: bool) -> bool {
-            match c {
-                b'a'..=b'z' => true,
-                b'A'..=b'Z' => true,
-                b'0'..=b'9' => true,
-                b'+' | b'/' | b';' | b'.' | b'$' | b'_' | b'(' | b')' => true,
-                b'-' => !is_first, // - is only name safe outside first position
-                _ => false,
-            }
+        if is_name(&r) {
+            Ok(r)
+        } else {
+            Err(Response::BadFormat)
         }
+    }
 
-        if r.iter()
-            .enumerate()
-            .all(|(i, c)| char_is_name_safe(*c, i == 0))
-            && r.len() <= 200
-        {
-            Ok(r)
+    /// Consumes from the input, expecting a space then a name, or a pattern
+    /// matching names in which any characters are replaced by `*`.
+    fn expect_next_name_or_pattern(&mut self) -> Result<Vec<u8>, Response> {
+        self.expect_space()?;
+
+        let token = self.expect_next_token()?;
+
+        if is_name_or_pattern(token) {
+            Ok(token.to_vec())
         } else {
             Err(Response::BadFormat)
         }
     }
 
+    /// Consumes the rest of the input, expecting one or more headers of the
+    /// form `<key>=<value>`, each preceded by a space. Keys and values must be
+    /// valid names, and keys must be unique.
+    fn expect_rest_headers(&mut self) -> Result<Headers, Response> {
+        let mut r = Headers::new();
+
+        loop {
+            self.expect_space()?;
+
+            let token = self.expect_next_token()?;
+            let eq = token
+                .iter()
+                .position(|&c| c == b'=')
+                .ok_or(Response::BadFormat)?;
+            let (key, value) = token.split_at(eq);
+            let value = value.get(1..).unwrap_or_default();
+
+            if !is_name(key)
+                || !is_name(value)
+                || r.insert(key.to_vec(), value.to_vec()).is_some()
+            {
+                return Err(Response::BadFormat);
+            }
+
+            if self.from.is_empty() {
+                return Ok(r);
+            }
+        }
+    }
+
+    /// Consumes from the input, expecting a space then either a name, or `-`
+    /// to indicate no name.
+    fn expect_next_name_or_dash(
+        &mut self,
+    ) -> Result<Option<Vec<u8>>, Response> {
+        if self.from == b" -" || self.from.starts_with(b" - ") {
+            self.from = &self.from[2..];
+            Ok(None)
+        } else {
+            self.expect_next_name().map(Some)
+        }
+    }
+
+    /// Consumes from the input, expecting a space then a number, where zero
+    /// indicates no limit.
+    fn expect_next_limit<T: TryFrom<u64>>(
+        &mut self,
+    ) -> Result<Option<T>, Response> {
+        match self.expect_next_u64()? {
+            0 => Ok(None),
+            v => T::try_from(v).map(Some).or(Err(Response::BadFormat)),
+        }
+    }
+
+    /// Consumes from the input, expecting a space then `front` or `back`, or
+    /// `-` to indicate neither.
+    fn expect_next_requeue_pos_or_dash(
+        &mut self,
+    ) -> Result<Option<RequeuePos>, Response> {
+        self.expect_space()?;
+
+        match self.expect_next_token()? {
+            b"front" => Ok(Some(RequeuePos::Front)),
+            b"back" => Ok(Some(RequeuePos::Back)),
+            b"-" => Ok(None),
+            _ => Err(Response::BadFormat),
+        }
+    }
+
+    /// Consumes from the input, expecting a space then `priority` or
+    /// `ordered`.
+    fn expect_next_reserve_mode(&mut self) -> Result<ReserveMode, Response> {
+        self.expect_space()?;
+
+        match self.expect_next_token()? {
+            b"priority" => Ok(ReserveMode::Priority),
+            b"ordered" => Ok(ReserveMode::Ordered),
+            _ => Err(Response::BadFormat),
+        }
+    }
+
+    /// Consumes from the input, expecting a space then a tube setting name,
+    /// followed by a space and a value for that setting.
+    fn expect_next_setting(&mut self) -> Result<TubeSetting, Response> {
+        use TubeSetting as S;
+
+        self.expect_space()?;
+
+        Ok(match self.expect_next_token()? {
+            b"dead-letter-tube" => {
+                S::DeadLetterTube(self.expect_next_name_or_dash()?)
+            },
+            b"dead-letter-releases" => {
+                S::DeadLetterReleases(self.expect_next_limit()?)
+            },
+            b"dead-letter-timeouts" => {
+                S::DeadLetterTimeouts(self.expect_next_limit()?)
+            },
+            b"dead-letter-buries" => {
+                S::DeadLetterBuries(self.expect_next_limit()?)
+            },
+            b"default-ttr" => S::DefaultTtr(self.expect_next_limit()?),
+            b"max-job-size" => S::MaxJobSize(self.expect_next_limit()?),
+            b"max-jobs" => S::MaxJobs(self.expect_next_limit()?),
+            b"max-bytes" => S::MaxBytes(self.expect_next_limit()?),
+            b"timeout-requeue" => {
+                S::TimeoutRequeue(self.expect_next_requeue_pos_or_dash()?)
+            },
+            b"pri-aging" => S::PriAging(self.expect_next_limit()?),
+            b"reserve-rate" => S::ReserveRate(self.expect_next_limit()?),
+            b"reserve-rate-interval" => {
+                S::ReserveRateInterval(self.expect_next_limit()?)
+            },
+            b"max-reserved" => S::MaxReserved(self.expect_next_limit()?),
+            b"weight" => S::Weight(self.expect_next_limit()?),
+            b"result-retention" => {
+                S::ResultRetention(self.expect_next_limit()?)
+            },
+            b"expired-tube" => S::ExpiredTube(self.expect_next_name_or_dash()?),
+            _ => return Err(Response::BadFormat),
+        })
+    }
+
     /// Consumes a space.
     fn expect_space(&mut self) -> Result<(), Response> {
         match self.from.get(0) {
@@ -153,9 +301,14 @@ impl TryFrom<&[u8]> for Command {
 
         let cmd = match ps.expect_next_token()? {
             // <cmd>
+            b"enable-headers" => EnableHeaders,
+            b"enable-milliseconds" => EnableMilliseconds,
+            b"capabilities" => Capabilities,
+            b"list-clients" => ListClients,
             b"list-tube-used" => ListTubeUsed,
             b"list-tubes-watched" => ListTubesWatched,
             b"list-tubes" => ListTubes,
+            b"list-schedules" => ListSchedules,
             b"peek-buried" => PeekBuried,
             b"peek-delayed" => PeekDelayed,
             b"peek-ready" => PeekReady,
@@ -185,31 +338,85 @@ impl TryFrom<&[u8]> for Command {
             b"touch" => Touch {
                 id: ps.expect_next_u64()?,
             },
+            b"kill-client" => KillClient {
+                id: ps.expect_next_u64()?,
+            },
 
             // <cmd> <timeout>
             b"reserve-with-timeout" => ReserveWithTimeout {
                 timeout: ps.expect_next_u32()?,
             },
 
+            // <cmd> <mode>
+            b"reserve-mode" => ReserveMode {
+                mode: ps.expect_next_reserve_mode()?,
+            },
+
             // <cmd> <tube>
             b"use" => Use {
                 tube: ps.expect_next_name()?,
             },
             b"watch" => Watch {
-                tube: ps.expect_next_name()?,
+                tube: ps.expect_next_name_or_pattern()?,
             },
             b"ignore" => Ignore {
-                tube: ps.expect_next_name()?,
+                tube: ps.expect_next_name_or_pattern()?,
             },
             b"stats-tube" => StatsTube {
                 tube: ps.expect_next_name()?,
             },
 
+            // <cmd> <name>
+            b"unschedule" => Unschedule {
+                name: ps.expect_next_name()?,
+            },
+            b"client-name" => ClientName {
+                name: ps.expect_next_name()?,
+            },
+
             // <cmd> <id> <pri>
             b"bury" => Bury {
                 id: ps.expect_next_u64()?,
                 pri: ps.expect_next_u32()?,
             },
+            b"set-pri" => SetPri {
+                id: ps.expect_next_u64()?,
+                pri: ps.expect_next_u32()?,
+            },
+
+            // <cmd> <id> <tube>
+            b"move-job" => MoveJob {
+                id: ps.expect_next_u64()?,
+                tube: ps.expect_next_name()?,
+            },
+
+            // <cmd> <id> <run_at>
+            b"reschedule" => Reschedule {
+                id: ps.expect_next_u64()?,
+                run_at: ps.expect_next_u64()?,
+            },
+
+            // <cmd> <id> <n_bytes>
+            b"complete" => Complete {
+                id: ps.expect_next_u64()?,
+                n_bytes: ps.expect_next_u32()?,
+            },
+
+            // <cmd> <id> <timeout>
+            b"result" => GetResult {
+                id: ps.expect_next_u64()?,
+                timeout: ps.expect_next_u32()?,
+            },
+
+            // <cmd> <tube> <tube>
+            b"subscribe" => Subscribe {
+                tube: ps.expect_next_name()?,
+                subscriber: ps.expect_next_name()?,
+            },
+            b"unsubscribe" => Unsubscribe {
+                tube: ps.expect_next_name()?,
+                subscriber: ps.expect_next_name()?,
+            },
 
             // <cmd> <tube> <delay>
             b"pause-tube" => PauseTube {
@@ -217,6 +424,30 @@ impl TryFrom<&[u8]> for Command {
                 delay: ps.expect_next_u32()?,
             },
 
+            // <cmd> <count> <timeout>
+            b"reserve-batch" => ReserveBatch {
+                count: match ps.expect_next_u32()? {
+                    0 => return Err(Response::BadFormat),
+                    count if count > MAX_RESERVE_BATCH => {
+                        return Err(Response::BadFormat);
+                    },
+                    count => count,
+                },
+                timeout: ps.expect_next_u32()?,
+            },
+
+            // <cmd> <tube> <setting> <value>
+            b"config-tube" => ConfigTube {
+                tube: ps.expect_next_name()?,
+                setting: ps.expect_next_setting()?,
+            },
+
+            // <cmd> <tube> <bound>
+            b"move-jobs" => MoveJobs {
+                tube: ps.expect_next_name()?,
+                bound: ps.expect_next_u64()?,
+            },
+
             // <cmd> <id> <pri> <delay>
             b"release" => Release {
                 id: ps.expect_next_u64()?,
@@ -232,6 +463,71 @@ impl TryFrom<&[u8]> for Command {
                 n_bytes: ps.expect_next_u32()?,
             },
 
+            // <cmd> <pri> <delay> <ttr> <n_bytes> <key>=<value> [...]
+            b"put-with-headers" => PutWithHeaders {
+                pri: ps.expect_next_u32()?,
+                delay: ps.expect_next_u32()?,
+                ttr: ps.expect_next_u32()?,
+                n_bytes: ps.expect_next_u32()?,
+                headers: ps.expect_rest_headers()?,
+            },
+
+            // <cmd> <tube> <pri> <delay> <ttr> <n_bytes>
+            b"put-with-reply" => PutWithReply {
+                reply_tube: ps.expect_next_name()?,
+                pri: ps.expect_next_u32()?,
+                delay: ps.expect_next_u32()?,
+                ttr: ps.expect_next_u32()?,
+                n_bytes: ps.expect_next_u32()?,
+            },
+
+            // <cmd> <pri> <delay> <ttr> <ttl> <n_bytes>
+            b"put-with-ttl" => PutWithTtl {
+                pri: ps.expect_next_u32()?,
+                delay: ps.expect_next_u32()?,
+                ttr: ps.expect_next_u32()?,
+                ttl: match ps.expect_next_u32()? {
+                    0 => return Err(Response::BadFormat),
+                    ttl => ttl,
+                },
+                n_bytes: ps.expect_next_u32()?,
+            },
+
+            // <cmd> <run_at> <pri> <ttr> <n_bytes>
+            b"put-at" => PutAt {
+                run_at: ps.expect_next_u64()?,
+                pri: ps.expect_next_u32()?,
+                ttr: ps.expect_next_u32()?,
+                n_bytes: ps.expect_next_u32()?,
+            },
+
+            // <cmd> <pri> <delay> <ttr> <n_bytes> <id> [<id>...]
+            b"put-after" => PutAfter {
+                pri: ps.expect_next_u32()?,
+                delay: ps.expect_next_u32()?,
+                ttr: ps.expect_next_u32()?,
+                n_bytes: ps.expect_next_u32()?,
+                after: ps.expect_rest_u64s()?,
+            },
+
+            // <cmd> <name> <pri> <ttr> <n_bytes> <cron>
+            b"schedule" => Schedule {
+                name: ps.expect_next_name()?,
+                pri: ps.expect_next_u32()?,
+                ttr: ps.expect_next_u32()?,
+                n_bytes: ps.expect_next_u32()?,
+                cron: ps.expect_rest_cron()?,
+            },
+
+            // <cmd> <key> <pri> <delay> <ttr> <n_bytes>
+            b"put-unique" => PutUnique {
+                key: ps.expect_next_name()?,
+                pri: ps.expect_next_u32()?,
+                delay: ps.expect_next_u32()?,
+                ttr: ps.expect_next_u32()?,
+                n_bytes: ps.expect_next_u32()?,
+            },
+
             _ => return Err(Response::UnknownCommand),
         };
 
@@ -239,6 +535,39 @@ impl TryFrom<&[u8]> for Command {
     }
 }
 
+/// Returns true if the input is a valid name, or a pattern matching names in
+/// which any characters are replaced by `*`.
+pub(super) fn is_name_or_pattern(token: &[u8]) -> bool {
+    // Patterns follow the rules for names, but may have a `*` anywhere.
+    let name: Vec<u8> = token
+        .iter()
+        .map(|&c| if c == b'*' { b'a' } else { c })
+        .collect();
+
+    is_name(&name)
+}
+
+/// Returns true if the input is a valid name, for tubes and other objects.
+pub(super) fn is_name(name: &[u8]) -> bool {
+    fn char_is_name_safe(c: u8, is_first: bool) -> bool {
+        match c {
+            b'a'..=b'z' => true,
+            b'A'..=b'Z' => true,
+            b'0'..=b'9' => true,
+            b'+' | b'/' | b';' | b'.' | b'$' | b'_' | b'(' | b')' => true,
+            b'-' => !is_first, // - is only name safe outside first position
+            _ => false,
+        }
+    }
+
+    !name.is_empty()
+        && name.len() <= MAX_NAME_LEN
+        && name
+            .iter()
+            .enumerate()
+            .all(|(i, c)| char_is_name_safe(*c, i == 0))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -294,6 +623,112 @@ mod tests {
         bf(format!("put 0 0 {U32_MAX_PLUS_1} 0").as_bytes());
         bf(format!("put 0 0 0 {U32_MAX_PLUS_1}").as_bytes());
 
+        ok(
+            b"put-unique order-123 987 654 321 123",
+            PutUnique {
+                key: "order-123".into(),
+                pri: 987,
+                delay: 654,
+                ttr: 321,
+                n_bytes: 123,
+            },
+        );
+        bf(b"put-unique 987 654 321 123");
+        bf(b"put-unique foo#bar 987 654 321 123");
+
+        ok(
+            b"put-with-reply replies 987 654 321 123",
+            PutWithReply {
+                reply_tube: "replies".into(),
+                pri: 987,
+                delay: 654,
+                ttr: 321,
+                n_bytes: 123,
+            },
+        );
+        bf(b"put-with-reply 987 654 321 123");
+
+        ok(
+            b"subscribe events cache-a",
+            Subscribe {
+                tube: "events".into(),
+                subscriber: "cache-a".into(),
+            },
+        );
+        ok(
+            b"unsubscribe events cache-a",
+            Unsubscribe {
+                tube: "events".into(),
+                subscriber: "cache-a".into(),
+            },
+        );
+        bf(b"subscribe events");
+        bf(b"unsubscribe events -cache");
+
+        ok(
+            b"complete 987 123",
+            Complete {
+                id: 987,
+                n_bytes: 123,
+            },
+        );
+        bf(b"complete 987");
+        ok(
+            b"result 987 10",
+            GetResult {
+                id: 987,
+                timeout: 10,
+            },
+        );
+        bf(b"result 987");
+
+        ok(
+            b"put-at 1767254400 987 321 123",
+            PutAt {
+                run_at: 1_767_254_400,
+                pri: 987,
+                ttr: 321,
+                n_bytes: 123,
+            },
+        );
+        bf(b"put-at 1767254400 987 321");
+        ok(
+            b"reschedule 12 1767254400",
+            Reschedule {
+                id: 12,
+                run_at: 1_767_254_400,
+            },
+        );
+        bf(b"reschedule 12");
+
+        ok(
+            b"put-with-ttl 987 654 321 3600 123",
+            PutWithTtl {
+                pri: 987,
+                delay: 654,
+                ttr: 321,
+                ttl: 3600,
+                n_bytes: 123,
+            },
+        );
+        bf(b"put-with-ttl 987 654 321 0 123");
+        bf(b"put-with-ttl 987 654 321 3600");
+
+        ok(
+            b"put-after 987 654 321 123 1 22 333",
+            PutAfter {
+                pri: 987,
+                delay: 654,
+                ttr: 321,
+                n_bytes: 123,
+                after: vec![1, 22, 333],
+            },
+        );
+        bf(b"put-after 987 654 321 123");
+        bf(b"put-after 987 654 321 123 1 ");
+        bf(b"put-after 987 654 321 123 1  2");
+        bf(format!("put-after 0 0 0 0 {U64_MAX_PLUS_1}").as_bytes());
+
         // Check use with tube name requirements.
         ok(
             b"use tube_name_here-098+/;.()-",
@@ -321,6 +756,23 @@ mod tests {
             ReserveWithTimeout { timeout: 123 },
         );
         bf(format!("reserve-with-timeout {U32_MAX_PLUS_1}").as_bytes());
+        ok(
+            b"reserve-batch 10 5",
+            ReserveBatch {
+                count: 10,
+                timeout: 5,
+            },
+        );
+        bf(b"reserve-batch 0 5");
+        ok(
+            b"reserve-batch 1000 5",
+            ReserveBatch {
+                count: 1000,
+                timeout: 5,
+            },
+        );
+        bf(b"reserve-batch 1001 5");
+        bf(b"reserve-batch 10");
 
         ok(b"reserve-job 987", ReserveJob { id: 987 });
         bf(format!("reserve-job {U64_MAX_PLUS_1}").as_bytes());
@@ -337,6 +789,9 @@ mod tests {
             },
         );
         ok(b"bury 543 987", Bury { id: 543, pri: 987 });
+        ok(b"set-pri 543 987", SetPri { id: 543, pri: 987 });
+        bf(b"set-pri 543");
+        bf(format!("set-pri 543 {U32_MAX_PLUS_1}").as_bytes());
 
         ok(b"touch 123", Touch { id: 123 });
         ok(
@@ -345,6 +800,21 @@ mod tests {
                 tube: "hello_world".into(),
             },
         );
+        ok(
+            b"watch billing.*",
+            Watch {
+                tube: "billing.*".into(),
+            },
+        );
+        ok(
+            b"ignore *.acme",
+            Ignore {
+                tube: "*.acme".into(),
+            },
+        );
+        bf(b"watch billing.#");
+        bf(b"use billing.*");
+        bf(b"stats-tube *");
         ok(
             b"ignore hello_world",
             Ignore {
@@ -372,9 +842,196 @@ mod tests {
         ok(b"list-tubes", ListTubes);
         ok(b"list-tube-used", ListTubeUsed);
         ok(b"list-tubes-watched", ListTubesWatched);
+        ok(b"list-clients", ListClients);
+        ok(b"capabilities", Capabilities);
+        ok(b"kill-client 12", KillClient { id: 12 });
+        bf(b"kill-client");
+        ok(
+            b"client-name worker-1",
+            ClientName {
+                name: "worker-1".into(),
+            },
+        );
+        bf(b"client-name worker#1");
+        ok(
+            b"reserve-mode ordered",
+            ReserveMode {
+                mode: crate::types::tube::ReserveMode::Ordered,
+            },
+        );
+        bf(b"reserve-mode fifo");
+
+        ok(
+            b"schedule tick 1 60 4 */5 * * * 1-5",
+            Schedule {
+                name: "tick".into(),
+                pri: 1,
+                ttr: 60,
+                n_bytes: 4,
+                cron: Cron::parse(b"*/5 * * * 1-5").unwrap(),
+            },
+        );
+        bf(b"schedule tick 1 60 4");
+        bf(b"schedule tick 1 60 4 ");
+        bf(b"schedule tick 1 60 4 * * * *");
+        bf(b"schedule tick 1 60 4 * * * * * *");
+        ok(
+            b"unschedule tick",
+            Unschedule {
+                name: "tick".into(),
+            },
+        );
+        ok(b"list-schedules", ListSchedules);
+
+        ok(b"enable-headers", EnableHeaders);
+        ok(b"enable-milliseconds", EnableMilliseconds);
+        ok(
+            b"put-with-headers 1 2 3 4 trace-id=abc123 type=text/plain",
+            PutWithHeaders {
+                pri: 1,
+                delay: 2,
+                ttr: 3,
+                n_bytes: 4,
+                headers: Headers::from([
+                    (b"trace-id".to_vec(), b"abc123".to_vec()),
+                    (b"type".to_vec(), b"text/plain".to_vec()),
+                ]),
+            },
+        );
+        bf(b"put-with-headers 1 2 3 4");
+        bf(b"put-with-headers 1 2 3 4 ");
+        bf(b"put-with-headers 1 2 3 4 a");
+        bf(b"put-with-headers 1 2 3 4 a=");
+        bf(b"put-with-headers 1 2 3 4 =b");
+        bf(b"put-with-headers 1 2 3 4 a=b=c");
+        bf(b"put-with-headers 1 2 3 4 a=b a=c");
+        bf(b"put-with-headers 1 2 3 4 a=b  c=d");
+
+        ok(
+            b"config-tube foo dead-letter-tube foo-dlq",
+            ConfigTube {
+                tube: "foo".into(),
+                setting: TubeSetting::DeadLetterTube(Some("foo-dlq".into())),
+            },
+        );
+        ok(
+            b"config-tube foo dead-letter-tube -",
+            ConfigTube {
+                tube: "foo".into(),
+                setting: TubeSetting::DeadLetterTube(None),
+            },
+        );
+        ok(
+            b"config-tube foo dead-letter-timeouts 3",
+            ConfigTube {
+                tube: "foo".into(),
+                setting: TubeSetting::DeadLetterTimeouts(Some(3)),
+            },
+        );
+        ok(
+            b"config-tube foo dead-letter-releases 0",
+            ConfigTube {
+                tube: "foo".into(),
+                setting: TubeSetting::DeadLetterReleases(None),
+            },
+        );
+        bf(b"config-tube foo dead-letter-tube - x");
+        ok(
+            b"config-tube foo weight 3",
+            ConfigTube {
+                tube: "foo".into(),
+                setting: TubeSetting::Weight(Some(3)),
+            },
+        );
+        ok(
+            b"config-tube foo expired-tube foo-stale",
+            ConfigTube {
+                tube: "foo".into(),
+                setting: TubeSetting::ExpiredTube(Some("foo-stale".into())),
+            },
+        );
+        bf(b"config-tube foo dead-letter-buries -");
+        bf(b"config-tube foo no-such-setting 1");
+        bf(b"config-tube foo dead-letter-buries");
+        ok(
+            b"config-tube foo default-ttr 120",
+            ConfigTube {
+                tube: "foo".into(),
+                setting: TubeSetting::DefaultTtr(Some(120)),
+            },
+        );
+        ok(
+            b"config-tube foo max-job-size 0",
+            ConfigTube {
+                tube: "foo".into(),
+                setting: TubeSetting::MaxJobSize(None),
+            },
+        );
+        ok(
+            b"config-tube foo max-bytes 1048576",
+            ConfigTube {
+                tube: "foo".into(),
+                setting: TubeSetting::MaxBytes(Some(1_048_576)),
+            },
+        );
+        bf(format!("config-tube foo max-job-size {U32_MAX_PLUS_1}").as_bytes());
+        ok(
+            b"config-tube foo timeout-requeue front",
+            ConfigTube {
+                tube: "foo".into(),
+                setting: TubeSetting::TimeoutRequeue(Some(RequeuePos::Front)),
+            },
+        );
+        ok(
+            b"config-tube foo timeout-requeue -",
+            ConfigTube {
+                tube: "foo".into(),
+                setting: TubeSetting::TimeoutRequeue(None),
+            },
+        );
+        bf(b"config-tube foo timeout-requeue middle");
+        bf(b"config-tube foo timeout-requeue 0");
+        ok(
+            b"config-tube foo pri-aging 10",
+            ConfigTube {
+                tube: "foo".into(),
+                setting: TubeSetting::PriAging(Some(10)),
+            },
+        );
+        ok(
+            b"config-tube foo reserve-rate 5",
+            ConfigTube {
+                tube: "foo".into(),
+                setting: TubeSetting::ReserveRate(Some(5)),
+            },
+        );
+        ok(
+            b"config-tube foo max-reserved 2",
+            ConfigTube {
+                tube: "foo".into(),
+                setting: TubeSetting::MaxReserved(Some(2)),
+            },
+        );
 
         ok(b"quit", Quit);
 
+        ok(
+            b"move-job 123 hello_world",
+            MoveJob {
+                id: 123,
+                tube: "hello_world".into(),
+            },
+        );
+        bf(b"move-job 123 -foo");
+        ok(
+            b"move-jobs hello_world 10",
+            MoveJobs {
+                tube: "hello_world".into(),
+                bound: 10,
+            },
+        );
+        bf(format!("move-jobs hello_world {U64_MAX_PLUS_1}").as_bytes());
+
         ok(
             b"pause-tube hello_world 62",
             PauseTube {
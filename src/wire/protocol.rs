@@ -1,8 +1,56 @@
+use std::collections::BTreeMap;
+
 use bytes::Bytes;
-use serde::Serialize;
+use serde::{Serialize, Serializer};
 
+use crate::types::config::{
+    TubeConfig, TubeSetting, serialize_name, serialize_names,
+    serialize_opt_name,
+};
+use crate::types::cron::Cron;
+use crate::types::job::Headers;
 use crate::types::states::JobState;
-use crate::types::tube::TubeStats;
+use crate::types::tube::{ReserveMode, TubeStats};
+
+/// The maximum length in bytes of tube names and other names.
+pub const MAX_NAME_LEN: usize = 200;
+
+/// The maximum number of jobs a single `reserve-batch` can reserve, which
+/// bounds the size of its response.
+pub const MAX_RESERVE_BATCH: u32 = 1000;
+
+/// The commands and features supported beyond the beanstalkd protocol, as
+/// listed by `capabilities`.
+pub const EXTENSIONS: &[&str] = &[
+    "binary-protocol",
+    "capabilities",
+    "client-name",
+    "complete",
+    "config-tube",
+    "enable-headers",
+    "enable-milliseconds",
+    "kill-client",
+    "list-clients",
+    "list-schedules",
+    "move-job",
+    "move-jobs",
+    "put-after",
+    "put-at",
+    "put-unique",
+    "put-with-headers",
+    "put-with-reply",
+    "put-with-ttl",
+    "reschedule",
+    "reserve-batch",
+    "reserve-mode",
+    "result",
+    "schedule",
+    "set-pri",
+    "subscribe",
+    "unschedule",
+    "unsubscribe",
+    "watch-patterns",
+];
 
 /// A command sent by the client to the server.
 #[derive(Clone, Debug, Eq, PartialEq)]
@@ -16,6 +64,80 @@ pub enum Command {
         ttr: u32,
         n_bytes: u32,
     },
+    /// As `put`, but if a job was put to the same tube with the same `key`
+    /// within the server's deduplication window, replies `INSERTED` with that
+    /// job's ID instead of creating a new job.
+    ///
+    /// On the wire: `put-unique <key> <pri> <delay> <ttr> <n_bytes>`
+    PutUnique {
+        key: Vec<u8>,
+        pri: u32,
+        delay: u32,
+        ttr: u32,
+        n_bytes: u32,
+    },
+    /// As `put`, but attaches the given headers to the job, which are returned
+    /// alongside it to clients that have sent `enable-headers`.
+    ///
+    /// On the wire: `put-with-headers <pri> <delay> <ttr> <n_bytes> <key>=<value>
+    /// [<key>=<value>...]`
+    PutWithHeaders {
+        pri: u32,
+        delay: u32,
+        ttr: u32,
+        n_bytes: u32,
+        headers: Headers,
+    },
+    /// As `put`, but the job expires `ttl` seconds after being put if it
+    /// hasn't been reserved by then, and is deleted or moved to its tube's
+    /// `expired-tube`.
+    ///
+    /// On the wire: `put-with-ttl <pri> <delay> <ttr> <ttl> <n_bytes>`
+    PutWithTtl {
+        pri: u32,
+        delay: u32,
+        ttr: u32,
+        ttl: u32,
+        n_bytes: u32,
+    },
+    /// As `put`, but when the job is completed with `complete`, its result is
+    /// put to the given reply tube.
+    ///
+    /// On the wire: `put-with-reply <tube> <pri> <delay> <ttr> <n_bytes>`
+    PutWithReply {
+        reply_tube: Vec<u8>,
+        pri: u32,
+        delay: u32,
+        ttr: u32,
+        n_bytes: u32,
+    },
+    /// As `put`, but the job is delayed until the given time in seconds since
+    /// the Unix epoch, or is ready immediately if that time has passed.
+    ///
+    /// On the wire: `put-at <run_at> <pri> <ttr> <n_bytes>`
+    PutAt {
+        run_at: u64,
+        pri: u32,
+        ttr: u32,
+        n_bytes: u32,
+    },
+    /// Changes when a delayed job becomes ready to the given time in seconds
+    /// since the Unix epoch. Returns `RESCHEDULED` or `NOT_FOUND`.
+    ///
+    /// On the wire: `reschedule <id> <run_at>`
+    Reschedule { id: u64, run_at: u64 },
+    /// As `put`, but the job waits until all the jobs with the given IDs have
+    /// been deleted before becoming ready (or delayed). Returns `NOT_FOUND`
+    /// without creating a job if any of those jobs don't exist.
+    ///
+    /// On the wire: `put-after <pri> <delay> <ttr> <n_bytes> <id> [<id>...]`
+    PutAfter {
+        pri: u32,
+        delay: u32,
+        ttr: u32,
+        n_bytes: u32,
+        after: Vec<u64>,
+    },
     /// Awaits a job from all the `watch`ed queues, blocking until one appears
     /// (or until the server shuts down).
     ///
@@ -26,6 +148,12 @@ pub enum Command {
     ///
     /// On the wire: `reserve-with-timeout <seconds>`
     ReserveWithTimeout { timeout: u32 },
+    /// As `reserve-with-timeout`, but reserves up to `count` jobs at once,
+    /// waiting only until at least one is available. `count` is at most
+    /// [`MAX_RESERVE_BATCH`].
+    ///
+    /// On the wire: `reserve-batch <count> <seconds>`
+    ReserveBatch { count: u32, timeout: u32 },
     /// Reserves a job with a given ID if it exists and is not already reserved,
     /// otherwise returning `NOT_FOUND`.
     ///
@@ -53,15 +181,16 @@ pub enum Command {
     ///
     /// On the wire: `touch <id>`
     Touch { id: u64 },
-    /// Adds a tube to the watchlist for this client. Always replies with
-    /// `WATCHING <number of watched tubes>`.
+    /// Adds a tube, or a pattern matching existing and future tubes, to the
+    /// watchlist for this client. Always replies with `WATCHING <number of
+    /// watched tubes and patterns>`.
     ///
-    /// On the wire: `watch <tube>`
+    /// On the wire: `watch <tube or pattern>`
     Watch { tube: Vec<u8> },
     /// Reverses the effect of `watch` on this client. Returns `WATCHING <n>` or
     /// `NOT_IGNORED` if this would remove the last queue in the watchlist.
     ///
-    /// On the wire: `ignore <tube>`
+    /// On the wire: `ignore <tube or pattern>`
     Ignore { tube: Vec<u8> },
     /// Returns the data for the job with this ID, regardless of its state.
     /// Response is either `FOUND <id> <bytes>` or `NOT_FOUND`, in common with
@@ -149,6 +278,128 @@ pub enum Command {
     PauseTube { tube: Vec<u8>, delay: u32 },
     /// On the wire: `use <tube>`
     Use { tube: Vec<u8> },
+    /// Changes the priority of a ready, delayed, or buried job in place.
+    /// Ready jobs are moved to their new place in the ready queue. Returns
+    /// `PRI_SET` or `NOT_FOUND`.
+    ///
+    /// On the wire: `set-pri <id> <pri>`
+    SetPri { id: u64, pri: u32 },
+    /// Moves a ready, delayed, or buried job to another tube, creating it if
+    /// required. The job keeps its ID, priority, remaining delay, and
+    /// statistics. Returns `MOVED` or `NOT_FOUND`.
+    ///
+    /// On the wire: `move-job <id> <tube>`
+    MoveJob { id: u64, tube: Vec<u8> },
+    /// Moves up to `bound` jobs from the currently-used tube to another tube,
+    /// as for `move-job`. Ready jobs are moved first, then delayed jobs, then
+    /// buried jobs. Returns `MOVED <count>` with the number of jobs moved.
+    ///
+    /// On the wire: `move-jobs <tube> <bound>`
+    MoveJobs { tube: Vec<u8>, bound: u64 },
+    /// Registers a recurring job template with the given name, replacing any
+    /// existing template of that name. Each time the cron expression matches,
+    /// the server puts a job with the given data to the currently-used tube.
+    /// Returns `SCHEDULED`.
+    ///
+    /// On the wire: `schedule <name> <pri> <ttr> <n_bytes> <cron>`, where
+    /// `<cron>` is a five-field cron expression.
+    Schedule {
+        name: Vec<u8>,
+        pri: u32,
+        ttr: u32,
+        n_bytes: u32,
+        cron: Cron,
+    },
+    /// Removes a recurring job template by name. Returns `UNSCHEDULED` or
+    /// `NOT_FOUND`.
+    ///
+    /// On the wire: `unschedule <name>`
+    Unschedule { name: Vec<u8> },
+    /// Returns a list of all recurring job templates.
+    ///
+    /// On the wire: `list-schedules`
+    ListSchedules,
+    /// Changes a single setting for a tube, creating the tube if required.
+    /// Returns `CONFIGURED`.
+    ///
+    /// On the wire: `config-tube <tube> <setting> <value>`
+    ConfigTube { tube: Vec<u8>, setting: TubeSetting },
+    /// Opts this connection in to receiving job headers in responses to the
+    /// `reserve` and `peek` families. Returns `HEADERS_ENABLED`.
+    ///
+    /// On the wire: `enable-headers`
+    EnableHeaders,
+    /// Opts this connection in to giving and receiving delays, TTRs, reserve
+    /// timeouts, and job times in `stats-job` in milliseconds rather than
+    /// seconds. Returns `MILLISECONDS_ENABLED`.
+    ///
+    /// On the wire: `enable-milliseconds`
+    EnableMilliseconds,
+    /// Deletes a job reserved by the same client, delivering the following
+    /// data as its result. Returns `COMPLETED`, `NOT_FOUND`, or if the result
+    /// can't be stored, `JOB_TOO_BIG` or `QUOTA_EXCEEDED`.
+    ///
+    /// On the wire: `complete <id> <n_bytes>`
+    Complete { id: u64, n_bytes: u32 },
+    /// Fetches the result of a completed job, waiting up to `timeout` seconds
+    /// for the job to be completed. Returns `RESULT`, `TIMED_OUT`, or
+    /// `NOT_FOUND`.
+    ///
+    /// On the wire: `result <id> <timeout>`
+    GetResult { id: u64, timeout: u32 },
+    /// Adds a subscriber tube to a tube, making it a broadcast tube whose jobs
+    /// are copied into each of its subscribers. Returns `SUBSCRIBED`.
+    ///
+    /// On the wire: `subscribe <tube> <subscriber>`
+    Subscribe { tube: Vec<u8>, subscriber: Vec<u8> },
+    /// Removes a subscriber tube from a broadcast tube. Returns `UNSUBSCRIBED`
+    /// or `NOT_FOUND`.
+    ///
+    /// On the wire: `unsubscribe <tube> <subscriber>`
+    Unsubscribe { tube: Vec<u8>, subscriber: Vec<u8> },
+    /// Sets how this client's `reserve` chooses between the tubes it watches.
+    /// Returns `RESERVE_MODE <mode>`.
+    ///
+    /// On the wire: `reserve-mode <priority|ordered>`
+    ReserveMode { mode: ReserveMode },
+    /// Names this client in the output of `list-clients`. Returns `NAMED`.
+    ///
+    /// On the wire: `client-name <name>`
+    ClientName { name: Vec<u8> },
+    /// Lists the connected clients. Returns `OK` with a YAML list of clients.
+    ///
+    /// On the wire: `list-clients`
+    ListClients,
+    /// Disconnects the client with the given ID, releasing its reserved jobs.
+    /// Returns `KILLED` or `NOT_FOUND`.
+    ///
+    /// On the wire: `kill-client <id>`
+    KillClient { id: u64 },
+    /// Describes the server's version, extensions and limits. Returns `OK`
+    /// with a YAML dictionary.
+    ///
+    /// On the wire: `capabilities`
+    Capabilities,
+}
+
+impl Command {
+    /// Returns the number of bytes of job data that follow this command on the
+    /// wire, if it's a member of the `put` family or a `complete`.
+    #[must_use]
+    pub fn put_bytes(&self) -> Option<u32> {
+        match self {
+            Self::Put { n_bytes, .. }
+            | Self::PutUnique { n_bytes, .. }
+            | Self::PutWithHeaders { n_bytes, .. }
+            | Self::PutWithTtl { n_bytes, .. }
+            | Self::PutWithReply { n_bytes, .. }
+            | Self::PutAt { n_bytes, .. }
+            | Self::Complete { n_bytes, .. }
+            | Self::PutAfter { n_bytes, .. }
+            | Self::Schedule { n_bytes, .. } => Some(*n_bytes),
+            _ => None,
+        }
+    }
 }
 
 /// All possible response types to a `BeanstalkRequest`.
@@ -195,6 +446,11 @@ pub enum Response {
     ///
     /// On the wire: `JOB_TOO_BIG`.
     JobTooBig,
+    /// In response to a `put`, indicates the tube is already at its maximum
+    /// number of jobs or total bytes of job data.
+    ///
+    /// On the wire: `QUOTA_EXCEEDED`.
+    QuotaExceeded,
     /// In response to a `put`, indicates the server is not currently accepting
     /// jobs.
     ///
@@ -222,10 +478,34 @@ pub enum Response {
     ///
     /// On the wire: `RESERVED <id> <n_bytes>`.
     Reserved { id: u64 },
+    /// As `Reserved`, for clients that have sent `enable-headers`, with the
+    /// job's headers appended.
+    ///
+    /// On the wire: `RESERVED <id> <n_bytes> [<key>=<value>...]`.
+    ReservedWithHeaders {
+        id: u64,
+        n_bytes: u32,
+        headers: Headers,
+    },
+    /// In response to a `reserve-batch`, provides the jobs that were just
+    /// reserved, each with the ID and data of the job.
+    ///
+    /// On the wire: `RESERVED_BATCH <count>`, then for each job,
+    /// `RESERVED <id> <n_bytes>` followed by the job data.
+    ReservedBatch { jobs: Vec<ReservedJob> },
     /// In response to a `peek`-family command, indicates success.
     ///
     /// On the wire: `FOUND <id> <n_bytes>`.
     Found { id: u64 },
+    /// As `Found`, for clients that have sent `enable-headers`, with the job's
+    /// headers appended.
+    ///
+    /// On the wire: `FOUND <id> <n_bytes> [<key>=<value>...]`.
+    FoundWithHeaders {
+        id: u64,
+        n_bytes: u32,
+        headers: Headers,
+    },
     /// After a Reserved or Found message, a chunk of the job data
     JobChunk(Bytes),
     /// Ends a job
@@ -246,6 +526,14 @@ pub enum Response {
     /// * `kick-job`: the job is unknown or is neither buried nor delayed, or
     ///   allowable if an internal server error occurred preventing the kick.
     /// * `pause-tube`: the tube does not exist.
+    /// * `set-pri` or `move-job`: the job is unknown or reserved.
+    /// * `put-after`: one of the jobs to wait on is unknown.
+    /// * `unschedule`: no recurring job template has the given name.
+    /// * `complete`: the job is unknown or is not reserved by this client.
+    /// * `result`: neither the job nor its result is known.
+    /// * `unsubscribe`: the tube is unknown or doesn't have that subscriber.
+    /// * `kill-client`: no client with that ID is connected.
+    /// * `reschedule`: the job is unknown or isn't delayed.
     ///
     /// On the wire: `NOT_FOUND`.
     NotFound,
@@ -297,6 +585,10 @@ pub enum Response {
     ///
     /// On the wire: `OK <n_bytes>` plus data in YAML dictionary format.
     OkStats { data: ServerStats },
+    /// In response to a `capabilities`, indicates success.
+    ///
+    /// On the wire: `OK <n_bytes>` plus data in YAML dictionary format.
+    OkCapabilities { data: Capabilities },
     ///In response to a `stats-tube`, indicates success.
     ///
     /// On the wire: `OK <n_bytes>` plus data in YAML dictionary format.
@@ -305,210 +597,411 @@ pub enum Response {
     ///
     /// On the wire: `OK <n_bytes>` plus data in YAML *list* format.
     OkListTubes { tubes: Vec<Vec<u8>> },
+    /// In response to a `list-tubes-watched` from a client in the `ordered`
+    /// reserve mode, indicates success.
+    ///
+    /// On the wire: `OK <n_bytes>` plus data in YAML dictionary format.
+    OkWatchList { data: WatchList },
+    /// In response to a `list-clients`, indicates success.
+    ///
+    /// On the wire: `OK <n_bytes>` plus data in YAML *list* format.
+    OkListClients { clients: Vec<ClientInfo> },
     /// In response to a `pause-tube`, indicates success.
     ///
     /// On the wire: `PAUSED`.
     Paused,
+    /// In response to a `set-pri`, indicates the job's priority was changed.
+    ///
+    /// On the wire: `PRI_SET`.
+    PriSet,
+    /// In response to a `move-job`, indicates success.
+    ///
+    /// On the wire: `MOVED`.
+    Moved,
+    /// In response to a `reschedule`, indicates success.
+    ///
+    /// On the wire: `RESCHEDULED`.
+    Rescheduled,
+    /// In response to a `move-jobs`, indicates success with the number of
+    /// jobs moved.
+    ///
+    /// On the wire: `MOVED <count>`.
+    MovedCount { count: u64 },
+    /// In response to a `schedule`, indicates success.
+    ///
+    /// On the wire: `SCHEDULED`.
+    Scheduled,
+    /// In response to an `unschedule`, indicates success.
+    ///
+    /// On the wire: `UNSCHEDULED`.
+    Unscheduled,
+    /// In response to a `list-schedules`, indicates success.
+    ///
+    /// On the wire: `OK <n_bytes>` plus data in YAML *list* format.
+    OkListSchedules { schedules: Vec<ScheduleInfo> },
+    /// In response to a `config-tube`, indicates success.
+    ///
+    /// On the wire: `CONFIGURED`.
+    Configured,
+    /// In response to an `enable-headers`, indicates success.
+    ///
+    /// On the wire: `HEADERS_ENABLED`.
+    HeadersEnabled,
+    /// In response to an `enable-milliseconds`, indicates success.
+    ///
+    /// On the wire: `MILLISECONDS_ENABLED`.
+    MillisecondsEnabled,
+    /// In response to a `complete`, indicates the job was deleted and its
+    /// result delivered.
+    ///
+    /// On the wire: `COMPLETED`.
+    Completed,
+    /// In response to a `result`, provides the result of the given job,
+    /// followed by the result data as for `Reserved`.
+    ///
+    /// On the wire: `RESULT <id> <n_bytes>`.
+    JobResult { id: u64, n_bytes: u32 },
+    /// In response to a `subscribe`, indicates success.
+    ///
+    /// On the wire: `SUBSCRIBED`.
+    Subscribed,
+    /// In response to an `unsubscribe`, indicates success.
+    ///
+    /// On the wire: `UNSUBSCRIBED`.
+    Unsubscribed,
+    /// In response to a `reserve-mode`, indicates success.
+    ///
+    /// On the wire: `RESERVE_MODE <mode>`.
+    ReserveModeSet { mode: ReserveMode },
+    /// In response to a `client-name`, indicates success.
+    ///
+    /// On the wire: `NAMED`.
+    Named,
+    /// In response to a `kill-client`, indicates the client was disconnected.
+    ///
+    /// On the wire: `KILLED`.
+    Killed,
 }
 
 #[derive(Debug, PartialEq, Serialize)]
 pub struct JobStats {
     /// job ID
-    id: u64,
+    pub id: u64,
     /// tube containing job
-    tube: Vec<u8>,
+    #[serde(serialize_with = "serialize_name")]
+    pub tube: Vec<u8>,
     /// job state
-    state: JobState,
+    pub state: JobState,
     /// priority set by last put/release/bury
-    pri: u32,
+    pub pri: u32,
 
     /// time in seconds since creation
-    age: u32, // TODO: size
+    pub age: u32, // TODO: size
     /// seconds remaining until ready
-    delay: u32, // TODO: size
+    pub delay: u32, // TODO: size
     /// allowed processing time in seconds
-    ttr: u32, // TODO: size
+    pub ttr: u32, // TODO: size
     /// time until job returns to ready queue
     #[serde(rename = "time-left")]
-    time_left: u32, // TODO: size
+    pub time_left: u32, // TODO: size
+    /// time a delayed job becomes ready in seconds since the Unix epoch
+    #[serde(rename = "run-at", skip_serializing_if = "Option::is_none")]
+    pub run_at: Option<u64>,
 
     /// earliest binlog file containing job
-    file: u32, // TODO: size
+    pub file: u32, // TODO: size
+    /// tube the job was in before being dead-lettered
+    #[serde(
+        rename = "original-tube",
+        serialize_with = "serialize_opt_name",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub original_tube: Option<Vec<u8>>,
+    /// headers attached to the job when it was put
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
 
     /// number of times job reserved
-    reserves: u64, // TODO: size
+    pub reserves: u64, // TODO: size
     /// number of times job timed out
-    timeouts: u64, // TODO: size
+    pub timeouts: u64, // TODO: size
     /// number of times job released
-    releases: u64, // TODO: size
+    pub releases: u64, // TODO: size
     /// number of times job buried
-    buries: u64, // TODO: size
+    pub buries: u64, // TODO: size
     /// number of times job kicked
-    kicks: u64, // TODO: size
+    pub kicks: u64, // TODO: size
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ReservedJob {
+    /// job ID
+    pub id: u64,
+    /// job data
+    pub data: Bytes,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct ScheduleInfo {
+    /// name of the recurring job template
+    #[serde(serialize_with = "serialize_name")]
+    pub name: Vec<u8>,
+    /// tube jobs are put to
+    #[serde(serialize_with = "serialize_name")]
+    pub tube: Vec<u8>,
+    /// cron expression the template runs on
+    pub cron: String,
+    /// priority of jobs put
+    pub pri: u32,
+    /// allowed processing time of jobs put, in seconds or, once enabled,
+    /// milliseconds
+    pub ttr: u32,
+    /// time of the next run in seconds since the Unix epoch
+    #[serde(rename = "next-run")]
+    pub next_run: Option<u64>,
+}
+
+/// Describes what the server supports, in response to `capabilities`.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Capabilities {
+    /// server version
+    pub version: &'static str,
+    /// commands and features supported beyond the beanstalkd protocol
+    pub extensions: &'static [&'static str],
+    /// limits on jobs and names
+    pub limits: Limits,
+    /// whether jobs are persisted to disk
+    pub durability: bool,
+    /// whether jobs are replicated to other servers
+    pub replication: bool,
+}
+
+/// Limits on jobs and names, as reported by `capabilities`.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Limits {
+    /// maximum size in bytes of a job body
+    #[serde(rename = "max-job-size")]
+    pub max_job_size: u32,
+    /// maximum length in bytes of a tube name
+    #[serde(rename = "max-tube-name-length")]
+    pub max_tube_name_length: usize,
+}
+
+/// Describes a connected client, in response to `list-clients`.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct ClientInfo {
+    /// client ID, as given to `kill-client`
+    pub id: u64,
+    /// client's address and port
+    pub peer: String,
+    /// name set by the client with `client-name`
+    #[serde(
+        serialize_with = "serialize_opt_name",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub name: Option<Vec<u8>>,
+    /// tube `use`d by the client
+    #[serde(serialize_with = "serialize_name")]
+    pub tube: Vec<u8>,
+    /// tube names and patterns watched by the client
+    #[serde(serialize_with = "serialize_names")]
+    pub watching: Vec<Vec<u8>>,
+    /// IDs of jobs reserved by the client
+    pub reserved: Vec<u64>,
+    /// seconds since the client's last command
+    pub idle: u32,
+}
+
+/// A client's watch list, with the reserve mode that determines how it's used.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct WatchList {
+    /// how `reserve` chooses between the watched tubes
+    pub mode: ReserveMode,
+    /// watched tube names and patterns, in the order they were watched
+    #[serde(serialize_with = "serialize_names")]
+    pub tubes: Vec<Vec<u8>>,
+}
+
+/// Tube names, serialised as a YAML list of strings for `list-tubes` and
+/// `list-tubes-watched`.
+pub(crate) struct TubeNames<'a>(pub &'a [Vec<u8>]);
+
+impl Serialize for TubeNames<'_> {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        serialize_names(self.0, s)
+    }
 }
 
 #[derive(Debug, PartialEq, Serialize)]
 pub struct TubeStatsResp {
     /// tube name
-    name: Vec<u8>,
+    #[serde(serialize_with = "serialize_name")]
+    pub name: Vec<u8>,
     #[serde(flatten)]
-    ts: TubeStats,
+    pub ts: TubeStats,
     /// seconds remaining until the queue is un-paused.
     #[serde(rename = "pause-time-left")]
-    pause_time_left: u32,
+    pub pause_time_left: u32,
+    /// settings configured for the tube, omitting those left unset
+    #[serde(flatten)]
+    pub config: TubeConfig,
 }
 
 // TODO: decompose into component structs
-#[derive(Debug, Default, PartialEq, Serialize)]
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
 pub struct ServerStats {
     /// number of ready jobs with priority < 1024
     #[serde(rename = "current-jobs-urgent")]
-    current_jobs_urgent: u64,
+    pub current_jobs_urgent: u64,
     /// number of jobs in the ready queue
     #[serde(rename = "current-jobs-ready")]
-    current_jobs_ready: u64,
+    pub current_jobs_ready: u64,
     /// number of jobs reserved by all clients
     #[serde(rename = "current-jobs-reserved")]
-    current_jobs_reserved: u64,
+    pub current_jobs_reserved: u64,
     /// number of delayed jobs
     #[serde(rename = "current-jobs-delayed")]
-    current_jobs_delayed: u64,
+    pub current_jobs_delayed: u64,
     /// number of buried jobs
     #[serde(rename = "current-jobs-buried")]
-    current_jobs_buried: u64,
+    pub current_jobs_buried: u64,
 
     /// number of X commands
     #[serde(rename = "cmd-put")]
-    cmd_put: u64,
+    pub cmd_put: u64,
     /// number of X commands
     #[serde(rename = "cmd-peek")]
-    cmd_peek: u64,
+    pub cmd_peek: u64,
     /// number of X commands
     #[serde(rename = "cmd-peek-ready")]
-    cmd_peek_ready: u64,
+    pub cmd_peek_ready: u64,
     /// number of X commands
     #[serde(rename = "cmd-peek-delayed")]
-    cmd_peek_delayed: u64,
+    pub cmd_peek_delayed: u64,
     /// number of X commands
     #[serde(rename = "cmd-peek-buried")]
-    cmd_peek_buried: u64,
+    pub cmd_peek_buried: u64,
     /// number of X commands
     #[serde(rename = "cmd-reserve")]
-    cmd_reserve: u64,
+    pub cmd_reserve: u64,
     /// number of X commands
     #[serde(rename = "cmd-reserve-with-timeout")]
-    cmd_reserve_with_timeout: u64,
+    pub cmd_reserve_with_timeout: u64,
     /// number of X commands
     #[serde(rename = "cmd-touch")]
-    cmd_touch: u64,
+    pub cmd_touch: u64,
     /// number of X commands
     #[serde(rename = "cmd-use")]
-    cmd_use: u64,
+    pub cmd_use: u64,
     /// number of X commands
     #[serde(rename = "cmd-watch")]
-    cmd_watch: u64,
+    pub cmd_watch: u64,
     /// number of X commands
     #[serde(rename = "cmd-ignore")]
-    cmd_ignore: u64,
+    pub cmd_ignore: u64,
     /// number of X commands
     #[serde(rename = "cmd-delete")]
-    cmd_delete: u64,
+    pub cmd_delete: u64,
     /// number of X commands
     #[serde(rename = "cmd-release")]
-    cmd_release: u64,
+    pub cmd_release: u64,
     /// number of X commands
     #[serde(rename = "cmd-bury")]
-    cmd_bury: u64,
+    pub cmd_bury: u64,
     /// number of X commands
     #[serde(rename = "cmd-kick")]
-    cmd_kick: u64,
+    pub cmd_kick: u64,
     /// number of X commands
     #[serde(rename = "cmd-stats")]
-    cmd_stats: u64,
+    pub cmd_stats: u64,
     /// number of X commands
     #[serde(rename = "cmd-stats-job")]
-    cmd_stats_job: u64,
+    pub cmd_stats_job: u64,
     /// number of X commands
     #[serde(rename = "cmd-stats-tube")]
-    cmd_stats_tube: u64,
+    pub cmd_stats_tube: u64,
     /// number of X commands
     #[serde(rename = "cmd-list-tubes")]
-    cmd_list_tubes: u64,
+    pub cmd_list_tubes: u64,
     /// number of X commands
     #[serde(rename = "cmd-list-tube-used")]
-    cmd_list_tube_used: u64,
+    pub cmd_list_tube_used: u64,
     /// number of X commands
     #[serde(rename = "cmd-list-tubes-watched")]
-    cmd_list_tubes_watched: u64,
+    pub cmd_list_tubes_watched: u64,
     /// number of X commands
     #[serde(rename = "cmd-pause-tube")]
-    cmd_pause_tube: u64,
+    pub cmd_pause_tube: u64,
 
     /// cumulative count of times a job has timed out
     #[serde(rename = "job-timeouts")]
-    job_timeouts: u64,
+    pub job_timeouts: u64,
     /// cumulative count of jobs created
     #[serde(rename = "total-jobs")]
-    total_jobs: u64,
+    pub total_jobs: u64,
     /// maximum number of bytes in a job
     #[serde(rename = "max-job-size")]
-    max_job_size: u64,
+    pub max_job_size: u64,
     /// number of currently-existing tubes
     #[serde(rename = "current-tubes")]
-    current_tubes: u64,
+    pub current_tubes: u64,
     /// number of currently open connections
     #[serde(rename = "current-connections")]
-    current_connections: u64,
+    pub current_connections: u64,
     /// number of open connections that have each issued at least one put command
     #[serde(rename = "current-producers")]
-    current_producers: u64,
+    pub current_producers: u64,
     /// number of open connections that have each issued at least one reserve command
     #[serde(rename = "current-workers")]
-    current_workers: u64,
+    pub current_workers: u64,
     /// number of open connections that have issued a reserve command but not yet received a response
     #[serde(rename = "current-waiting")]
-    current_waiting: u64,
+    pub current_waiting: u64,
     /// cumulative count of connections
     #[serde(rename = "total-connections")]
-    total_connections: u64,
+    pub total_connections: u64,
     /// process id of the server
-    pid: u32,
+    pub pid: u32,
     /// version string of the server
-    version: &'static str,
+    pub version: &'static str,
     /// cumulative user CPU time of this process in seconds and microseconds
     #[serde(rename = "rusage-utime")]
-    rusage_utime: u64,
+    pub rusage_utime: u64,
     /// cumulative system CPU time of this process in seconds and microseconds
     #[serde(rename = "rusage-stime")]
-    rusage_stime: u64,
+    pub rusage_stime: u64,
     /// number of seconds since this server process started running
-    uptime: u32,
+    pub uptime: u32,
 
     /// index of the oldest binlog file needed to store the current jobs
     #[serde(rename = "binlog-oldest-index")]
-    binlog_oldest_index: u64,
+    pub binlog_oldest_index: u64,
     /// index of the current binlog file being written to. If binlog is not active this value will be 0
     #[serde(rename = "binlog-current-index")]
-    binlog_current_index: u64,
+    pub binlog_current_index: u64,
     /// maximum size in bytes a binlog file is allowed to get before a new binlog file is opened
     #[serde(rename = "binlog-max-size")]
-    binlog_max_size: u64,
+    pub binlog_max_size: u64,
     /// cumulative number of records written to the binlog
     #[serde(rename = "binlog-records-written")]
-    binlog_records_written: u64,
+    pub binlog_records_written: u64,
     /// cumulative number of records written as part of compaction
     #[serde(rename = "binlog-records-migrated")]
-    binlog_records_migrated: u64,
+    pub binlog_records_migrated: u64,
 
     /// is server is in drain mode
-    draining: bool,
+    pub draining: bool,
     /// random id string for this server process, generated every time the
     /// process starts
-    id: Vec<u8>,
+    #[serde(serialize_with = "serialize_name")]
+    pub id: Vec<u8>,
     // hostname of the machine as determined by uname
-    hostname: Vec<u8>,
+    #[serde(serialize_with = "serialize_name")]
+    pub hostname: Vec<u8>,
     /// OS version as determined by uname
-    os: Vec<u8>,
+    #[serde(serialize_with = "serialize_name")]
+    pub os: Vec<u8>,
     /// machine architecture as determined by uname
-    platform: Vec<u8>,
+    #[serde(serialize_with = "serialize_name")]
+    pub platform: Vec<u8>,
 }